@@ -0,0 +1,230 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufReader, Read, Write},
+};
+
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    ssh::ParseRecipientKeyError,
+    stream::StreamReader,
+    DecryptError,
+};
+use age_core::format::{FileKey, Stanza};
+use ssh_key::{LineEnding, PrivateKey};
+
+/**
+* Encrypts the input to the SSH public keys (`ssh-ed25519` or `ssh-rsa`) of the recipients, like `age -R`.
+* The input is streamed, so files of any size can be encrypted without holding them in memory.
+*/
+pub fn encrypt<R: Read, W: Write>(
+    recipients: &[String],
+    armored: bool,
+    mut input: R,
+    output: W,
+) -> Result<(), anyhow::Error> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| parse_recipient(recipient))
+        .collect::<Result<Vec<_>, _>>()?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )?;
+
+    let format = match armored {
+        true => Format::AsciiArmor,
+        false => Format::Binary,
+    };
+    let mut writer = encryptor.wrap_output(ArmoredWriter::wrap_output(output, format)?)?;
+    io::copy(&mut input, &mut writer)?;
+    writer.finish()?.finish()?;
+    Ok(())
+}
+
+fn parse_recipient(public_key: &str) -> Result<age::ssh::Recipient, anyhow::Error> {
+    let public_key = public_key.trim();
+    public_key.parse().map_err(|e| {
+        let reason = match e {
+            ParseRecipientKeyError::Ignore | ParseRecipientKeyError::Invalid(_) => {
+                "not an SSH public key".to_string()
+            }
+            ParseRecipientKeyError::RsaModulusTooLarge => "RSA key is too large".to_string(),
+            ParseRecipientKeyError::RsaModulusTooSmall => "RSA key is too small".to_string(),
+            ParseRecipientKeyError::Unsupported(key_type) => {
+                format!("unsupported key type {}", key_type)
+            }
+        };
+        anyhow::anyhow!("Invalid recipient {}: {}", public_key, reason)
+    })
+}
+
+/**
+* An age file opened with a vault key. Only the header has been read when this is returned, which tells which
+* key the file is encrypted to; the plaintext is decrypted as it is read, so the caller can confirm the use of
+* the key before any of it is released.
+*/
+pub struct AgeDecryptor<R: Read> {
+    /// The vault item whose key decrypts the file
+    pub cipher_id: String,
+    reader: StreamReader<ArmoredReader<BufReader<R>>>,
+}
+
+impl<R: Read> AgeDecryptor<R> {
+    /// Reads the header of an armored or binary age file and unwraps its file key with the first matching key
+    pub fn new(identities: &[(String, PrivateKey)], input: R) -> Result<Self, anyhow::Error> {
+        let matched = RefCell::new(None);
+        let identities = identities
+            .iter()
+            .filter_map(|(cipher_id, private_key)| {
+                let openssh = private_key.to_openssh(LineEnding::LF).ok()?;
+                let identity = age::ssh::Identity::from_buffer(openssh.as_bytes(), None).ok()?;
+                Some(VaultIdentity {
+                    cipher_id,
+                    identity,
+                    matched: &matched,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(input))?;
+        let reader = match decryptor.decrypt(
+            identities
+                .iter()
+                .map(|identity| identity as &dyn age::Identity),
+        ) {
+            Ok(reader) => reader,
+            Err(DecryptError::NoMatchingKeys) => {
+                return Err(anyhow::anyhow!(
+                    "The file is not encrypted to any key in the vault"
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let cipher_id = matched
+            .into_inner()
+            .ok_or_else(|| anyhow::anyhow!("The file is not encrypted to any key in the vault"))?;
+        Ok(AgeDecryptor { cipher_id, reader })
+    }
+}
+
+impl<R: Read> Read for AgeDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Remembers which vault key unwrapped the file key, which the age crate does not report
+struct VaultIdentity<'a> {
+    cipher_id: &'a str,
+    identity: age::ssh::Identity,
+    matched: &'a RefCell<Option<String>>,
+}
+
+impl age::Identity for VaultIdentity<'_> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        let file_key = self.identity.unwrap_stanza(stanza);
+        if matches!(file_key, Some(Ok(_))) {
+            *self.matched.borrow_mut() = Some(self.cipher_id.to_string());
+        }
+        file_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault_keys() -> Vec<(String, PrivateKey)> {
+        [
+            (
+                "ed25519-cipher",
+                include_str!("./test_keys/ed25519_openssh_unencrypted"),
+            ),
+            (
+                "rsa-cipher",
+                include_str!("./test_keys/rsa_openssh_unencrypted"),
+            ),
+        ]
+        .into_iter()
+        .map(|(cipher_id, private_key)| {
+            (
+                cipher_id.to_string(),
+                PrivateKey::from_openssh(private_key).unwrap(),
+            )
+        })
+        .collect()
+    }
+
+    fn decrypt(identities: &[(String, PrivateKey)], ciphertext: &[u8]) -> (String, Vec<u8>) {
+        let mut decryptor = AgeDecryptor::new(identities, ciphertext).unwrap();
+        let mut plaintext = Vec::new();
+        decryptor.read_to_end(&mut plaintext).unwrap();
+        (decryptor.cipher_id, plaintext)
+    }
+
+    #[test]
+    fn round_trip_to_vault_keys() {
+        let plaintext = vec![0x42u8; 200 * 1024];
+        let identities = vault_keys();
+        for (public_key, cipher_id) in [
+            (
+                include_str!("./test_keys/ed25519_openssh_unencrypted.pub"),
+                "ed25519-cipher",
+            ),
+            (
+                include_str!("./test_keys/rsa_openssh_unencrypted.pub"),
+                "rsa-cipher",
+            ),
+        ] {
+            for armored in [true, false] {
+                let mut ciphertext = Vec::new();
+                encrypt(
+                    &[public_key.to_string()],
+                    armored,
+                    plaintext.as_slice(),
+                    &mut ciphertext,
+                )
+                .unwrap();
+                assert_eq!(
+                    ciphertext.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"),
+                    armored
+                );
+
+                let (decrypted_with, decrypted) = decrypt(&identities, &ciphertext);
+                assert_eq!(decrypted_with, cipher_id);
+                assert_eq!(decrypted, plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn file_for_other_keys_is_rejected() {
+        let mut ciphertext = Vec::new();
+        encrypt(
+            &[include_str!("./test_keys/ed25519_openssh_unencrypted.pub").to_string()],
+            false,
+            &b"secret"[..],
+            &mut ciphertext,
+        )
+        .unwrap();
+
+        let identities = vault_keys()
+            .into_iter()
+            .filter(|(cipher_id, _)| cipher_id == "rsa-cipher")
+            .collect::<Vec<_>>();
+        assert!(AgeDecryptor::new(&identities, ciphertext.as_slice()).is_err());
+    }
+
+    #[test]
+    fn invalid_recipient() {
+        let error = encrypt(
+            &["ecdsa-sha2-nistp256 AAAA".to_string()],
+            true,
+            &b"secret"[..],
+            Vec::new(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Invalid recipient"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Size after which the log is rotated
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+/// Number of rotated files kept next to the log, as `<path>.1` (newest) to `<path>.<n>` (oldest)
+const MAX_ROTATED_FILES: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    List,
+    Sign,
+    Add,
+    Remove,
+    Lock,
+    Unlock,
+    /// Decryption of an age file by the app
+    Decrypt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditDecision {
+    /// Allowed without asking the user, e.g. keys added through `ssh-add` without `-c`
+    AutoAllowed,
+    UserApproved,
+    /// Denied by the user or by a policy of the key
+    Denied,
+    /// The user did not respond to the prompt in time
+    TimedOut,
+    /// The app approved, but the key requires user verification by the OS, which failed or was cancelled
+    VerificationFailed,
+}
+
+impl AuditDecision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(
+            self,
+            AuditDecision::AutoAllowed | AuditDecision::UserApproved
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub operation: AuditOperation,
+    pub decision: AuditDecision,
+    pub key_name: Option<String>,
+    /// SHA256 fingerprint of the key
+    pub fingerprint: Option<String>,
+    pub cipher_id: Option<String>,
+    pub process_name: String,
+    pub pid: u32,
+    pub uid: u32,
+    /// What the signed data was decoded as, only set for sign operations
+    pub purpose: Option<String>,
+}
+
+/// Filters for [`AuditLog::query`], unset fields match every entry.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub cipher_id: Option<String>,
+    pub process_name: Option<String>,
+    pub operation: Option<AuditOperation>,
+    pub decision: Option<AuditDecision>,
+    /// Only return entries at or after this unix timestamp
+    pub since: Option<u64>,
+    /// Maximum number of entries, the most recent entries are kept
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.cipher_id
+            .as_ref()
+            .is_none_or(|cipher_id| entry.cipher_id.as_ref() == Some(cipher_id))
+            && self
+                .process_name
+                .as_ref()
+                .is_none_or(|process_name| &entry.process_name == process_name)
+            && self
+                .operation
+                .is_none_or(|operation| entry.operation == operation)
+            && self
+                .decision
+                .is_none_or(|decision| entry.decision == decision)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+    }
+}
+
+/**
+* Append-only log of the operations agent clients performed, stored as one JSON object per line.
+* Once the log grows past its maximum size it is moved aside, keeping a fixed number of old logs.
+*/
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    /// Serializes writes and rotation between connections
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self::with_max_size(path, DEFAULT_MAX_SIZE)
+    }
+
+    pub fn with_max_size(path: PathBuf, max_size: u64) -> Self {
+        Self {
+            path,
+            max_size,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn record(&self, entry: &AuditEntry) -> Result<(), anyhow::Error> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let _guard = self.write_lock.lock().expect("Mutex is not poisoned");
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if metadata.len() + line.len() as u64 > self.max_size {
+                self.rotate()?;
+            }
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        // The log reveals which hosts the user connects to, so only the user may read it
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&self.path)?.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the matching entries of the log and its rotated files, newest first.
+    pub fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, anyhow::Error> {
+        let _guard = self.write_lock.lock().expect("Mutex is not poisoned");
+        let mut entries = Vec::new();
+        for path in self.files_newest_first() {
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // Entries of a file are in chronological order
+            for line in contents.lines().rev() {
+                if filter.limit.is_some_and(|limit| entries.len() >= limit) {
+                    return Ok(entries);
+                }
+                match serde_json::from_str::<AuditEntry>(line) {
+                    Ok(entry) if filter.matches(&entry) => entries.push(entry),
+                    Ok(_) => {}
+                    // A line can be cut off if the app exited while writing it
+                    Err(_) => continue,
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn rotate(&self) -> Result<(), anyhow::Error> {
+        for index in (1..MAX_ROTATED_FILES).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        Ok(())
+    }
+
+    fn files_newest_first(&self) -> Vec<PathBuf> {
+        std::iter::once(self.path.clone())
+            .chain((1..=MAX_ROTATED_FILES).map(|index| rotated_path(&self.path, index)))
+            .collect()
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    rotated.into()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::test_support::TempDir;
+
+    fn entry(operation: AuditOperation, decision: AuditDecision, timestamp: u64) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            operation,
+            decision,
+            key_name: Some("key".to_string()),
+            fingerprint: None,
+            cipher_id: Some("cipher".to_string()),
+            process_name: "ssh".to_string(),
+            pid: 1,
+            uid: 1000,
+            purpose: None,
+        }
+    }
+
+    #[test]
+    fn query_filters_newest_first() {
+        let directory = TempDir::new("audit");
+        let log = AuditLog::new(directory.join("audit.log"));
+        log.record(&entry(AuditOperation::List, AuditDecision::AutoAllowed, 1))
+            .unwrap();
+        log.record(&entry(AuditOperation::Sign, AuditDecision::Denied, 2))
+            .unwrap();
+        log.record(&entry(AuditOperation::Sign, AuditDecision::UserApproved, 3))
+            .unwrap();
+
+        let signs = log
+            .query(&AuditFilter {
+                operation: Some(AuditOperation::Sign),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            signs.iter().map(|e| e.timestamp).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+
+        let latest = log
+            .query(&AuditFilter {
+                limit: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(latest[0].decision, AuditDecision::UserApproved);
+
+        let denied = log
+            .query(&AuditFilter {
+                decision: Some(AuditDecision::Denied),
+                since: Some(3),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(denied.is_empty());
+    }
+
+    #[test]
+    fn rotates_when_full() {
+        let directory = TempDir::new("audit");
+        let path = directory.join("audit.log");
+        let line_length =
+            serde_json::to_string(&entry(AuditOperation::Sign, AuditDecision::Denied, 0))
+                .unwrap()
+                .len() as u64
+                + 1;
+        let log = AuditLog::with_max_size(path.clone(), line_length * 2);
+        for timestamp in 0..7 {
+            log.record(&entry(
+                AuditOperation::Sign,
+                AuditDecision::Denied,
+                timestamp,
+            ))
+            .unwrap();
+        }
+
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+        // The oldest entries were dropped with the file rotated out
+        let timestamps = log
+            .query(&AuditFilter::default())
+            .unwrap()
+            .iter()
+            .map(|e| e.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![6, 5, 4, 3, 2]);
+    }
+}
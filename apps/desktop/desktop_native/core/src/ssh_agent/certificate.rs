@@ -0,0 +1,490 @@
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, engine::general_purpose::STANDARD_NO_PAD, Engine};
+use rand::RngCore;
+use rsa::signature::{Signer, Verifier};
+use sha2::{Digest, Sha256};
+use ssh_encoding::{Decode, Encode, Reader};
+use ssh_key::{PrivateKey, PublicKey, Signature};
+
+const CERTIFICATE_SUFFIX: &str = "-cert-v01@openssh.com";
+const SSH2_CERT_TYPE_USER: u32 = 1;
+const SSH2_CERT_TYPE_HOST: u32 = 2;
+const NONCE_LENGTH: usize = 32;
+
+/// Extensions `ssh-keygen` grants user certificates unless told otherwise
+pub const DEFAULT_USER_EXTENSIONS: &[&str] = &[
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+/// What the user is shown about a certificate attached to a key
+#[derive(Clone, Debug, PartialEq)]
+pub struct CertificateInfo {
+    /// `ssh-ed25519-cert-v01@openssh.com` etc.
+    pub algorithm: String,
+    pub key_id: String,
+    pub serial: u64,
+    pub is_host_certificate: bool,
+    /// Users or hosts the certificate is valid for, any if empty
+    pub principals: Vec<String>,
+    /// Unix timestamps of the validity period, `valid_before` is `u64::MAX` for certificates that never expire
+    pub valid_after: u64,
+    pub valid_before: u64,
+    /// SHA256 fingerprint of the CA key that signed the certificate
+    pub ca_fingerprint: String,
+    /// Critical options and their values, e.g. `force-command` and the command
+    pub critical_options: Vec<(String, String)>,
+    /// Names of the extensions, e.g. `permit-pty`
+    pub extensions: Vec<String>,
+}
+
+impl CertificateInfo {
+    /// Information about an OpenSSH encoded certificate (`ssh-ed25519-cert-v01@openssh.com AAAA...`)
+    pub fn from_openssh(certificate: &str) -> Result<Self, anyhow::Error> {
+        Ok(Certificate::from_openssh(certificate)?.info)
+    }
+}
+
+/**
+* An OpenSSH certificate (PROTOCOL.certkeys), kept as the blob that is sent to agent clients. It is parsed here
+* rather than with `ssh_key::Certificate`, which rejects the `valid_before` of certificates that never expire.
+* The CA signature is not verified, that is up to the server the certificate is presented to.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Certificate {
+    blob: Vec<u8>,
+    /// Public key blob of the key the certificate was issued for
+    public_key_blob: Vec<u8>,
+    /// Length of the part of the blob the CA signed
+    signed_length: usize,
+    ca_key_blob: Vec<u8>,
+    signature: Vec<u8>,
+    pub info: CertificateInfo,
+}
+
+impl Certificate {
+    pub fn from_openssh(certificate: &str) -> Result<Self, anyhow::Error> {
+        let mut fields = certificate.split_whitespace();
+        let algorithm = fields
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty certificate"))?;
+        let blob = STANDARD.decode(
+            fields
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Certificate data is missing"))?,
+        )?;
+        let certificate = Certificate::from_bytes(&blob)?;
+        if certificate.info.algorithm != algorithm {
+            return Err(anyhow::anyhow!(
+                "Certificate type {} does not match its data",
+                algorithm
+            ));
+        }
+        Ok(certificate)
+    }
+
+    pub fn from_bytes(blob: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = blob;
+        let algorithm = String::decode(&mut reader)?;
+        let key_type = algorithm
+            .strip_suffix(CERTIFICATE_SUFFIX)
+            .ok_or_else(|| anyhow::anyhow!("{} is not a certificate type", algorithm))?;
+        let _nonce = Vec::<u8>::decode(&mut reader)?;
+
+        // The public key fields of the certified key, e.g. the point of an Ed25519 key or e and n of an RSA key
+        let public_key_fields = match key_type {
+            "ssh-ed25519" => 1,
+            "ssh-rsa" | "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => 2,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported certificate type {}",
+                    algorithm
+                ))
+            }
+        };
+        let fields_start = blob.len() - reader.len();
+        for _ in 0..public_key_fields {
+            Vec::<u8>::decode(&mut reader)?;
+        }
+        let mut public_key_blob = Vec::new();
+        key_type.encode(&mut public_key_blob)?;
+        public_key_blob.extend_from_slice(&blob[fields_start..blob.len() - reader.len()]);
+
+        let serial = u64::decode(&mut reader)?;
+        let cert_type = u32::decode(&mut reader)?;
+        let key_id = String::decode(&mut reader)?;
+        let principals = decode_string_list(&Vec::<u8>::decode(&mut reader)?)?;
+        let valid_after = u64::decode(&mut reader)?;
+        let valid_before = u64::decode(&mut reader)?;
+        let critical_options = decode_options(&Vec::<u8>::decode(&mut reader)?)?;
+        let extensions = decode_options(&Vec::<u8>::decode(&mut reader)?)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let _reserved = Vec::<u8>::decode(&mut reader)?;
+        let signature_key = Vec::<u8>::decode(&mut reader)?;
+        let signed_length = blob.len() - reader.len();
+        let signature = Vec::<u8>::decode(&mut reader)?;
+        reader.finish(())?;
+
+        Ok(Certificate {
+            blob: blob.to_vec(),
+            public_key_blob,
+            signed_length,
+            info: CertificateInfo {
+                algorithm,
+                key_id,
+                serial,
+                is_host_certificate: cert_type == SSH2_CERT_TYPE_HOST,
+                principals,
+                valid_after,
+                valid_before,
+                ca_fingerprint: format!(
+                    "SHA256:{}",
+                    STANDARD_NO_PAD.encode(Sha256::digest(&signature_key))
+                ),
+                critical_options,
+                extensions,
+            },
+            ca_key_blob: signature_key,
+            signature,
+        })
+    }
+
+    /// OpenSSH encoding, as in a `-cert.pub` file
+    pub fn to_openssh(&self) -> String {
+        format!("{} {}", self.info.algorithm, STANDARD.encode(&self.blob))
+    }
+
+    /// Checks that the certificate was signed by the CA key it names, like a server does before trusting it
+    pub fn verify_signature(&self) -> Result<(), anyhow::Error> {
+        let ca_key = PublicKey::from_bytes(&self.ca_key_blob)?;
+        let signature = Signature::decode(&mut self.signature.as_slice())?;
+        ca_key
+            .key_data()
+            .verify(&self.blob[..self.signed_length], &signature)?;
+        Ok(())
+    }
+
+    /// The certificate as sent in identity lists and sign requests
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.blob
+    }
+
+    /// Whether the certificate is within its validity period at the given unix time
+    pub fn is_valid_at(&self, unix_timestamp: u64) -> bool {
+        self.info.valid_after <= unix_timestamp && unix_timestamp < self.info.valid_before
+    }
+}
+
+/// SHA256 fingerprint of a public key or certificate blob, of the certified key for certificates
+pub fn key_blob_fingerprint(key_blob: &[u8]) -> String {
+    let public_key_blob = match Certificate::from_bytes(key_blob) {
+        Ok(certificate) => certificate.public_key_blob,
+        Err(_) => key_blob.to_vec(),
+    };
+    format!(
+        "SHA256:{}",
+        STANDARD_NO_PAD.encode(Sha256::digest(&public_key_blob))
+    )
+}
+
+fn decode_string_list(mut reader: &[u8]) -> Result<Vec<String>, anyhow::Error> {
+    let mut strings = Vec::new();
+    while !reader.is_finished() {
+        strings.push(String::decode(&mut reader)?);
+    }
+    Ok(strings)
+}
+
+/// Critical options and extensions are names with data, which is empty or holds the value as a string
+fn decode_options(mut reader: &[u8]) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let mut options = Vec::new();
+    while !reader.is_finished() {
+        let name = String::decode(&mut reader)?;
+        let data = Vec::<u8>::decode(&mut reader)?;
+        let value = match data.is_empty() {
+            true => String::new(),
+            false => String::decode(&mut data.as_slice())?,
+        };
+        options.push((name, value));
+    }
+    Ok(options)
+}
+
+fn encode_options(options: &BTreeMap<String, String>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut encoded = Vec::new();
+    for (name, value) in options {
+        name.encode(&mut encoded)?;
+        let mut data = Vec::new();
+        if !value.is_empty() {
+            value.encode(&mut data)?;
+        }
+        data.encode(&mut encoded)?;
+    }
+    Ok(encoded)
+}
+
+/// What a certificate issued by [`issue_certificate`] grants
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CertificateOptions {
+    pub is_host_certificate: bool,
+    pub key_id: String,
+    pub serial: u64,
+    /// Users or host names the certificate is valid for, any if empty
+    pub principals: Vec<String>,
+    /// Unix timestamps of the validity period, `u64::MAX` as `valid_before` for a certificate that never expires
+    pub valid_after: u64,
+    pub valid_before: u64,
+    /// e.g. `force-command` or `source-address`, only allowed in user certificates
+    pub critical_options: BTreeMap<String, String>,
+    /// e.g. `permit-pty`, only allowed in user certificates
+    pub extensions: Vec<String>,
+}
+
+/**
+* Issues an OpenSSH certificate for the public key, signed by the CA key. RSA CA keys sign with `rsa-sha2-512`,
+* as OpenSSH no longer accepts SHA-1 certificate signatures.
+*/
+pub fn issue_certificate(
+    ca_key: &PrivateKey,
+    public_key: &PublicKey,
+    options: &CertificateOptions,
+) -> Result<Certificate, anyhow::Error> {
+    if options.valid_after >= options.valid_before {
+        return Err(anyhow::anyhow!(
+            "The validity period of the certificate is empty"
+        ));
+    }
+    if options.is_host_certificate
+        && !(options.critical_options.is_empty() && options.extensions.is_empty())
+    {
+        return Err(anyhow::anyhow!(
+            "Host certificates can not have critical options or extensions"
+        ));
+    }
+
+    let public_key_blob = public_key.to_bytes()?;
+    let mut public_key_fields = public_key_blob.as_slice();
+    let key_type = String::decode(&mut public_key_fields)?;
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let mut principals = Vec::new();
+    for principal in &options.principals {
+        principal.encode(&mut principals)?;
+    }
+    let extensions = options
+        .extensions
+        .iter()
+        .map(|extension| (extension.clone(), String::new()))
+        .collect();
+
+    let mut blob = Vec::new();
+    format!("{}{}", key_type, CERTIFICATE_SUFFIX).encode(&mut blob)?;
+    nonce.as_slice().encode(&mut blob)?;
+    blob.extend_from_slice(public_key_fields);
+    options.serial.encode(&mut blob)?;
+    match options.is_host_certificate {
+        true => SSH2_CERT_TYPE_HOST,
+        false => SSH2_CERT_TYPE_USER,
+    }
+    .encode(&mut blob)?;
+    options.key_id.encode(&mut blob)?;
+    principals.encode(&mut blob)?;
+    options.valid_after.encode(&mut blob)?;
+    options.valid_before.encode(&mut blob)?;
+    encode_options(&options.critical_options)?.encode(&mut blob)?;
+    encode_options(&extensions)?.encode(&mut blob)?;
+    Vec::<u8>::new().encode(&mut blob)?;
+    ca_key.public_key().to_bytes()?.encode(&mut blob)?;
+
+    let signature: Signature = ca_key.try_sign(&blob)?;
+    let mut signature_bytes = Vec::new();
+    signature.encode(&mut signature_bytes)?;
+    signature_bytes.encode(&mut blob)?;
+    Certificate::from_bytes(&blob)
+}
+
+/**
+* Parses the OpenSSH encoded certificates of a key. Certificates that can not be parsed, or that were issued
+* for another key, are left out, as the agent could not sign for them.
+*/
+pub(super) fn parse_certificates(
+    private_key: &PrivateKey,
+    certificates: &[String],
+) -> Vec<Certificate> {
+    let public_key_blob = private_key.public_key().to_bytes().unwrap_or_default();
+    certificates
+        .iter()
+        .filter_map(|certificate| match Certificate::from_openssh(certificate) {
+            Ok(certificate) if certificate.public_key_blob == public_key_blob => Some(certificate),
+            Ok(certificate) => {
+                println!(
+                    "[SSH Agent Native Module] Ignoring certificate {} issued for another key",
+                    certificate.info.key_id
+                );
+                None
+            }
+            Err(e) => {
+                println!(
+                    "[SSH Agent Native Module] Ignoring certificate that could not be parsed: {}",
+                    e
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use ssh_key::{rand_core::OsRng, Algorithm};
+
+    use super::*;
+
+    /// Issues a user certificate for the key, signed by a new CA
+    pub(crate) fn certificate_for(
+        private_key: &PrivateKey,
+        valid_after: u64,
+        valid_before: u64,
+    ) -> Certificate {
+        let ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        super::issue_certificate(
+            &ca_key,
+            private_key.public_key(),
+            &CertificateOptions {
+                key_id: "issued".to_string(),
+                principals: vec!["alice".to_string()],
+                valid_after,
+                valid_before,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_ssh_keygen_certificate() {
+        let certificate = Certificate::from_openssh(include_str!(
+            "./test_keys/ed25519_openssh_unencrypted-cert.pub"
+        ))
+        .unwrap();
+        assert_eq!(
+            certificate.info,
+            CertificateInfo {
+                algorithm: "ssh-ed25519-cert-v01@openssh.com".to_string(),
+                key_id: "alice@laptop".to_string(),
+                serial: 7,
+                is_host_certificate: false,
+                principals: vec!["alice".to_string(), "deploy".to_string()],
+                valid_after: 0,
+                valid_before: u64::MAX,
+                ca_fingerprint: "SHA256:BScDhzfRpqC+rkkd8neJ+F3eI2802Gpqkb26aiRmkJg".to_string(),
+                critical_options: vec![("force-command".to_string(), "/usr/bin/true".to_string())],
+                extensions: vec!["permit-pty".to_string()],
+            }
+        );
+        assert!(certificate.is_valid_at(1_800_000_000));
+        assert!(certificate.verify_signature().is_ok());
+
+        let public_key =
+            PublicKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted.pub"))
+                .unwrap();
+        let fingerprint = public_key.fingerprint(ssh_key::HashAlg::Sha256).to_string();
+        assert_eq!(key_blob_fingerprint(certificate.as_bytes()), fingerprint);
+        assert_eq!(
+            key_blob_fingerprint(&public_key.to_bytes().unwrap()),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn issue_user_and_host_certificates() {
+        let public_key =
+            PublicKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted.pub"))
+                .unwrap();
+        let ca_key =
+            PrivateKey::from_openssh(include_str!("./test_keys/rsa_openssh_unencrypted")).unwrap();
+        let options = CertificateOptions {
+            key_id: "alice@laptop".to_string(),
+            serial: 42,
+            principals: vec!["alice".to_string(), "deploy".to_string()],
+            valid_after: 1_700_000_000,
+            valid_before: u64::MAX,
+            critical_options: BTreeMap::from([(
+                "force-command".to_string(),
+                "/usr/bin/true".to_string(),
+            )]),
+            extensions: vec!["permit-pty".to_string()],
+            ..Default::default()
+        };
+
+        let certificate = issue_certificate(&ca_key, &public_key, &options).unwrap();
+        let parsed = Certificate::from_openssh(&certificate.to_openssh()).unwrap();
+        assert!(parsed.verify_signature().is_ok());
+        assert_eq!(parsed.public_key_blob, public_key.to_bytes().unwrap());
+        assert_eq!(
+            parsed.info,
+            CertificateInfo {
+                algorithm: "ssh-ed25519-cert-v01@openssh.com".to_string(),
+                key_id: "alice@laptop".to_string(),
+                serial: 42,
+                is_host_certificate: false,
+                principals: vec!["alice".to_string(), "deploy".to_string()],
+                valid_after: 1_700_000_000,
+                valid_before: u64::MAX,
+                ca_fingerprint: ca_key
+                    .public_key()
+                    .fingerprint(ssh_key::HashAlg::Sha256)
+                    .to_string(),
+                critical_options: vec![("force-command".to_string(), "/usr/bin/true".to_string())],
+                extensions: vec!["permit-pty".to_string()],
+            }
+        );
+
+        let host_options = CertificateOptions {
+            is_host_certificate: true,
+            principals: vec!["build.example.com".to_string()],
+            critical_options: BTreeMap::new(),
+            extensions: Vec::new(),
+            ..options
+        };
+        let host_certificate = issue_certificate(&ca_key, &public_key, &host_options).unwrap();
+        assert!(host_certificate.info.is_host_certificate);
+        assert!(host_certificate.verify_signature().is_ok());
+
+        // A certificate whose contents were changed after signing is rejected
+        let mut tampered = certificate.clone();
+        let key_id_offset = tampered
+            .blob
+            .windows(5)
+            .position(|window| window == b"alice")
+            .unwrap();
+        tampered.blob[key_id_offset] = b'm';
+        assert!(tampered.verify_signature().is_err());
+    }
+
+    #[test]
+    fn certificates_of_other_keys_are_ignored() {
+        let private_key =
+            PrivateKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted"))
+                .unwrap();
+        let other_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let certificates = vec![
+            include_str!("./test_keys/ed25519_openssh_unencrypted-cert.pub").to_string(),
+            format!(
+                "ssh-ed25519-cert-v01@openssh.com {}",
+                STANDARD.encode(certificate_for(&other_key, 0, 1).as_bytes())
+            ),
+            "ssh-ed25519-cert-v01@openssh.com AAAA".to_string(),
+        ];
+
+        let parsed = parse_certificates(&private_key, &certificates);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].info.key_id, "alice@laptop");
+    }
+}
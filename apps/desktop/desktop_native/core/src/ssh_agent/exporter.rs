@@ -0,0 +1,236 @@
+use pkcs8::{
+    der::Decode, pkcs5::pbes2, EncodePrivateKey, LineEnding as PemLineEnding, PrivateKeyInfo,
+    SecretDocument,
+};
+use rand::RngCore;
+use ssh_key::{private::KeypairData, LineEnding, PrivateKey};
+
+use super::ppk;
+
+const PKCS8_SALT_LENGTH: usize = 16;
+const PKCS8_ENCRYPTED_LABEL: &str = "ENCRYPTED PRIVATE KEY";
+const PKCS8_UNENCRYPTED_LABEL: &str = "PRIVATE KEY";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// OpenSSH private key format, encrypted with aes256-ctr under a bcrypt-pbkdf derived key
+    OpenSsh,
+    /// PKCS#8 PEM, encrypted with PBES2 (PBKDF2-HMAC-SHA256, AES-256-CBC)
+    Pkcs8,
+    /// PuTTY PPK version 3, encrypted with aes256-cbc under an Argon2id derived key
+    Putty,
+}
+
+/// Work factors of the key derivation functions that encrypt exported keys
+#[derive(Clone, Copy, Debug)]
+struct KdfCost {
+    /// PBKDF2-HMAC-SHA256 iterations for PKCS#8 files
+    pbkdf2_iterations: u32,
+    /// Argon2id parameters for PPK files
+    argon2: ppk::Argon2Cost,
+}
+
+impl KdfCost {
+    /// PBKDF2 iterations as recommended by OWASP, Argon2id parameters as used by puttygen
+    const DEFAULT: KdfCost = KdfCost {
+        pbkdf2_iterations: 600_000,
+        argon2: ppk::Argon2Cost::DEFAULT,
+    };
+}
+
+/**
+* Exports a key stored in the vault (OpenSSH encoded) into a file format other tools can read. The key is encrypted
+* with the passphrase, or written unencrypted if the passphrase is empty. Every format round-trips through
+* [`super::importer::import_key`].
+*/
+pub fn export_key(
+    private_key: &str,
+    format: ExportFormat,
+    passphrase: &str,
+) -> Result<String, anyhow::Error> {
+    export_key_with_cost(private_key, format, passphrase, KdfCost::DEFAULT)
+}
+
+fn export_key_with_cost(
+    private_key: &str,
+    format: ExportFormat,
+    passphrase: &str,
+    cost: KdfCost,
+) -> Result<String, anyhow::Error> {
+    let private_key = PrivateKey::from_openssh(private_key)?;
+    if private_key.is_encrypted() {
+        return Err(anyhow::anyhow!("Stored key is encrypted"));
+    }
+
+    match format {
+        ExportFormat::OpenSsh => export_openssh(&private_key, passphrase),
+        ExportFormat::Pkcs8 => export_pkcs8(&private_key, passphrase, cost.pbkdf2_iterations),
+        ExportFormat::Putty => ppk::encode(&private_key, passphrase, cost.argon2),
+    }
+}
+
+fn export_openssh(private_key: &PrivateKey, passphrase: &str) -> Result<String, anyhow::Error> {
+    let private_key = match passphrase.is_empty() {
+        true => private_key.clone(),
+        false => private_key.encrypt(&mut rand::thread_rng(), passphrase)?,
+    };
+    Ok(private_key.to_openssh(LineEnding::LF)?.to_string())
+}
+
+fn export_pkcs8(
+    private_key: &PrivateKey,
+    passphrase: &str,
+    pbkdf2_iterations: u32,
+) -> Result<String, anyhow::Error> {
+    let der = match private_key.key_data() {
+        KeypairData::Ed25519(keypair) => {
+            // The private key of an Ed25519 PKCS#8 structure is the seed wrapped in an OCTET STRING (RFC 8410)
+            let mut curve_private_key = vec![0x04, 0x20];
+            curve_private_key.extend_from_slice(&keypair.private.to_bytes());
+            let der = SecretDocument::try_from(PrivateKeyInfo::new(
+                ed25519::pkcs8::ALGORITHM_ID,
+                &curve_private_key,
+            ))?;
+            curve_private_key.fill(0);
+            der
+        }
+        KeypairData::Rsa(keypair) => {
+            // ssh-key's conversion to `rsa::RsaPrivateKey` passes `p` for both primes, so the key is built here
+            let mut private_key = rsa::RsaPrivateKey::from_components(
+                rsa::BigUint::try_from(&keypair.public.n)?,
+                rsa::BigUint::try_from(&keypair.public.e)?,
+                rsa::BigUint::try_from(&keypair.private.d)?,
+                vec![
+                    rsa::BigUint::try_from(&keypair.private.p)?,
+                    rsa::BigUint::try_from(&keypair.private.q)?,
+                ],
+            )?;
+            // PKCS#1 encoding needs the CRT exponents, which OpenSSH keys do not store
+            private_key.precompute()?;
+            private_key.to_pkcs8_der()?
+        }
+        _ => return Err(anyhow::anyhow!("Unsupported key type")),
+    };
+
+    if passphrase.is_empty() {
+        return Ok(der
+            .to_pem(PKCS8_UNENCRYPTED_LABEL, PemLineEnding::LF)?
+            .to_string());
+    }
+
+    let mut salt = [0u8; PKCS8_SALT_LENGTH];
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut iv);
+    let parameters = pbes2::Parameters::pbkdf2_sha256_aes256cbc(pbkdf2_iterations, &salt, &iv)
+        .map_err(|e| anyhow::anyhow!("Invalid PBES2 parameters: {}", e))?;
+    let encrypted =
+        PrivateKeyInfo::from_der(der.as_bytes())?.encrypt_with_params(parameters, passphrase)?;
+    Ok(encrypted
+        .to_pem(PKCS8_ENCRYPTED_LABEL, PemLineEnding::LF)?
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::importer::{import_key, SshKeyImportStatus};
+
+    const ED25519_KEY: &str = include_str!("./test_keys/ed25519_openssh_unencrypted");
+    const RSA_KEY: &str = include_str!("./test_keys/rsa_openssh_unencrypted");
+
+    /// Cheap work factors, the production ones take seconds per key
+    const TEST_COST: KdfCost = KdfCost {
+        pbkdf2_iterations: 1000,
+        argon2: ppk::Argon2Cost {
+            memory: 64,
+            passes: 1,
+        },
+    };
+
+    fn export(private_key: &str, format: ExportFormat, passphrase: &str) -> String {
+        export_key_with_cost(private_key, format, passphrase, TEST_COST).unwrap()
+    }
+
+    /// Public key without the comment, which PKCS#8 does not carry
+    fn public_key(private_key: &str) -> String {
+        let mut public_key = PrivateKey::from_openssh(private_key)
+            .unwrap()
+            .public_key()
+            .clone();
+        public_key.set_comment("");
+        public_key.to_string()
+    }
+
+    fn assert_round_trip(private_key: &str, format: ExportFormat, passphrase: &str) {
+        let exported = export(private_key, format, passphrase);
+        let result = import_key(exported, passphrase.to_string()).unwrap();
+        assert_eq!(result.status, SshKeyImportStatus::Success);
+
+        let mut imported =
+            ssh_key::PublicKey::from_openssh(&result.ssh_key.unwrap().public_key).unwrap();
+        imported.set_comment("");
+        assert_eq!(imported.to_string(), public_key(private_key));
+    }
+
+    #[test]
+    fn export_openssh_round_trip() {
+        for key in [ED25519_KEY, RSA_KEY] {
+            assert_round_trip(key, ExportFormat::OpenSsh, "");
+            assert_round_trip(key, ExportFormat::OpenSsh, "passphrase");
+        }
+    }
+
+    #[test]
+    fn export_pkcs8_round_trip() {
+        for key in [ED25519_KEY, RSA_KEY] {
+            assert_round_trip(key, ExportFormat::Pkcs8, "");
+            assert_round_trip(key, ExportFormat::Pkcs8, "passphrase");
+        }
+    }
+
+    #[test]
+    fn export_putty_round_trip() {
+        for key in [ED25519_KEY, RSA_KEY] {
+            assert_round_trip(key, ExportFormat::Putty, "");
+            assert_round_trip(key, ExportFormat::Putty, "passphrase");
+        }
+
+        let exported = export(ED25519_KEY, ExportFormat::Putty, "");
+        assert!(exported.starts_with("PuTTY-User-Key-File-3: ssh-ed25519\nEncryption: none\n"));
+    }
+
+    #[test]
+    fn export_encrypted_rejects_wrong_passphrase() {
+        for format in [
+            ExportFormat::OpenSsh,
+            ExportFormat::Pkcs8,
+            ExportFormat::Putty,
+        ] {
+            let exported = export(ED25519_KEY, format, "passphrase");
+            let result = import_key(exported, "wrong".to_string()).unwrap();
+            assert_eq!(result.status, SshKeyImportStatus::WrongPassword);
+        }
+
+        let exported = export(ED25519_KEY, ExportFormat::Putty, "passphrase");
+        let result = import_key(exported, "".to_string()).unwrap();
+        assert_eq!(result.status, SshKeyImportStatus::PasswordRequired);
+    }
+
+    #[test]
+    fn import_putty_rejects_excessive_argon2_cost() {
+        let exported = export(ED25519_KEY, ExportFormat::Putty, "passphrase");
+        for (header, value) in [("Argon2-Memory", "4194304"), ("Argon2-Passes", "100000")] {
+            let tampered = exported
+                .lines()
+                .map(|line| match line.starts_with(header) {
+                    true => format!("{}: {}", header, value),
+                    false => line.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let result = import_key(tampered, "passphrase".to_string()).unwrap();
+            assert_eq!(result.status, SshKeyImportStatus::ParsingError);
+        }
+    }
+}
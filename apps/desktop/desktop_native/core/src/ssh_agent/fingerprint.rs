@@ -0,0 +1,227 @@
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use ssh_key::{public::KeyData, HashAlg, PublicKey};
+
+/// Field size of the randomart, as in OpenSSH
+const RANDOMART_WIDTH: usize = 17;
+const RANDOMART_HEIGHT: usize = 9;
+/// Symbols for the number of visits of a field, followed by the start and end markers
+const RANDOMART_SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+const BUBBLE_BABBLE_VOWELS: &[u8] = b"aeiouy";
+const BUBBLE_BABBLE_CONSONANTS: &[u8] = b"bcdfghklmnprstvzx";
+
+/// The representations of a public key that `ssh-keygen` can show, so they can be compared against other tools.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyFingerprints {
+    /// `SHA256:` followed by the unpadded base64 digest, OpenSSH's default
+    pub sha256: String,
+    /// `MD5:` followed by the colon separated hex digest, as shown by older servers and admin pages
+    pub md5: String,
+    /// The "drunken bishop" randomart box of the SHA256 digest, as printed by `ssh-keygen -lv`
+    pub randomart: String,
+    /// The bubble-babble encoding of the SHA1 digest, as printed by `ssh-keygen -B`
+    pub bubble_babble: String,
+}
+
+impl KeyFingerprints {
+    /// Fingerprints of an OpenSSH encoded public key (`ssh-ed25519 AAAA... comment`)
+    pub fn from_openssh(public_key: &str) -> Result<Self, anyhow::Error> {
+        KeyFingerprints::try_from(&PublicKey::from_openssh(public_key)?)
+    }
+}
+
+impl TryFrom<&PublicKey> for KeyFingerprints {
+    type Error = anyhow::Error;
+
+    fn try_from(public_key: &PublicKey) -> Result<Self, Self::Error> {
+        let blob = public_key.to_bytes()?;
+        let sha256 = public_key.fingerprint(HashAlg::Sha256);
+        let md5 = Md5::digest(&blob)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        Ok(KeyFingerprints {
+            sha256: sha256.to_string(),
+            md5: format!("MD5:{}", md5),
+            randomart: randomart(public_key.key_data(), "SHA256", sha256.as_bytes()),
+            bubble_babble: bubble_babble(&Sha1::digest(&blob)),
+        })
+    }
+}
+
+/// Type and size shown in the randomart title, e.g. `ED25519 256`
+fn key_description(key_data: &KeyData) -> String {
+    match key_data {
+        KeyData::Ed25519(_) => "ED25519 256".to_string(),
+        KeyData::Rsa(rsa) => {
+            let modulus = rsa.n.as_positive_bytes().unwrap_or_default();
+            let bits =
+                modulus.len() * 8 - modulus.first().map_or(0, |b| b.leading_zeros() as usize);
+            format!("RSA {}", bits)
+        }
+        other => other.algorithm().as_str().to_uppercase(),
+    }
+}
+
+/**
+* Draws the digest as OpenSSH's randomart: a bishop starts in the center of the field and moves diagonally
+* for every two bits of the digest, and each field shows how often it was visited.
+*/
+fn randomart(key_data: &KeyData, hash_name: &str, digest: &[u8]) -> String {
+    let mut field = [[0u8; RANDOMART_HEIGHT]; RANDOMART_WIDTH];
+    let max_visits = (RANDOMART_SYMBOLS.len() - 3) as u8;
+    let (mut x, mut y) = (RANDOMART_WIDTH / 2, RANDOMART_HEIGHT / 2);
+
+    for byte in digest {
+        let mut input = *byte;
+        for _ in 0..4 {
+            x = match input & 0x1 {
+                0 => x.saturating_sub(1),
+                _ => (x + 1).min(RANDOMART_WIDTH - 1),
+            };
+            y = match input & 0x2 {
+                0 => y.saturating_sub(1),
+                _ => (y + 1).min(RANDOMART_HEIGHT - 1),
+            };
+            if field[x][y] < max_visits {
+                field[x][y] += 1;
+            }
+            input >>= 2;
+        }
+    }
+    field[RANDOMART_WIDTH / 2][RANDOMART_HEIGHT / 2] = max_visits + 1;
+    field[x][y] = max_visits + 2;
+
+    let mut art = border(&format!("[{}]", key_description(key_data)));
+    art.push('\n');
+    for y in 0..RANDOMART_HEIGHT {
+        art.push('|');
+        for column in field.iter() {
+            art.push(RANDOMART_SYMBOLS[column[y] as usize] as char);
+        }
+        art.push_str("|\n");
+    }
+    art.push_str(&border(&format!("[{}]", hash_name)));
+    art
+}
+
+/// A horizontal border with the label centered in it, left out if it does not fit
+fn border(label: &str) -> String {
+    let label = match label.len() > RANDOMART_WIDTH {
+        true => "",
+        false => label,
+    };
+    let left = (RANDOMART_WIDTH - label.len()) / 2;
+    format!(
+        "+{}{}{}+",
+        "-".repeat(left),
+        label,
+        "-".repeat(RANDOMART_WIDTH - left - label.len())
+    )
+}
+
+/// Bubble-babble encoding (Antti Huima), which turns a digest into pronounceable five letter words
+fn bubble_babble(digest: &[u8]) -> String {
+    let vowel = |index: usize| BUBBLE_BABBLE_VOWELS[index] as char;
+    let consonant = |index: usize| BUBBLE_BABBLE_CONSONANTS[index] as char;
+
+    let rounds = digest.len() / 2 + 1;
+    let mut seed = 1usize;
+    let mut encoded = String::from("x");
+    for i in 0..rounds {
+        if i + 1 < rounds || !digest.len().is_multiple_of(2) {
+            let byte = digest[2 * i] as usize;
+            encoded.push(vowel((((byte >> 6) & 3) + seed) % 6));
+            encoded.push(consonant((byte >> 2) & 15));
+            encoded.push(vowel(((byte & 3) + seed / 6) % 6));
+            if i + 1 < rounds {
+                let next = digest[2 * i + 1] as usize;
+                encoded.push(consonant((next >> 4) & 15));
+                encoded.push('-');
+                encoded.push(consonant(next & 15));
+                seed = (seed * 5 + byte * 7 + next) % 36;
+            }
+        } else {
+            encoded.push(vowel(seed % 6));
+            encoded.push(consonant(16));
+            encoded.push(vowel(seed / 6));
+        }
+    }
+    encoded.push('x');
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_match_ssh_keygen() {
+        let public_key =
+            PublicKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted.pub"))
+                .unwrap();
+        let fingerprints = KeyFingerprints::try_from(&public_key).unwrap();
+
+        assert_eq!(
+            fingerprints.sha256,
+            "SHA256:oaEiIEZe8SyB9Dh+eHD/SRkUj8enzP39H/sctgzbDb8"
+        );
+        assert_eq!(
+            fingerprints.md5,
+            "MD5:41:9d:09:ee:69:c4:b5:3b:ba:58:ed:47:19:42:b5:44"
+        );
+        assert_eq!(
+            fingerprints.bubble_babble,
+            "xomih-pyzoh-ryhyg-mesel-nufav-gibec-pehar-nisoz-cacel-sovah-texyx"
+        );
+        assert_eq!(
+            fingerprints.randomart,
+            "+--[ED25519 256]--+\n\
+             |.o.+.  o.        |\n\
+             |o.+ + . +        |\n\
+             |o* + o.o.+ .     |\n\
+             |= = o. o*.+      |\n\
+             |.o.o...oS+ .     |\n\
+             | .o.  o .   . .  |\n\
+             |       o     o.= |\n\
+             |              *+B|\n\
+             |             . E@|\n\
+             +----[SHA256]-----+"
+        );
+    }
+
+    #[test]
+    fn randomart_of_rsa_key() {
+        let public_key =
+            PublicKey::from_openssh(include_str!("./test_keys/rsa_openssh_unencrypted.pub"))
+                .unwrap();
+        let digest = Md5::digest(public_key.to_bytes().unwrap());
+        assert_eq!(
+            randomart(public_key.key_data(), "MD5", &digest),
+            "+---[RSA 3072]----+\n\
+             |    .+B= o. E    |\n\
+             |   . =o.* o. .   |\n\
+             |    . .+.B .     |\n\
+             |       .O o .    |\n\
+             |        S+ .     |\n\
+             |                 |\n\
+             |                 |\n\
+             |                 |\n\
+             |                 |\n\
+             +------[MD5]------+"
+        );
+    }
+
+    #[test]
+    fn bubble_babble_vectors() {
+        assert_eq!(bubble_babble(b""), "xexax");
+        assert_eq!(
+            bubble_babble(b"1234567890"),
+            "xesef-disof-gytuf-katof-movif-baxux"
+        );
+        assert_eq!(bubble_babble(b"Pineapple"), "xigak-nyryk-humil-bosek-sonax");
+    }
+}
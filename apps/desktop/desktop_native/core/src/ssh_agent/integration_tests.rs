@@ -0,0 +1,469 @@
+//! End-to-end tests of the agent: a `BitwardenDesktopAgent` listens on a temporary socket, a scripted UI
+//! answers its prompts and a client speaks the agent protocol to it over the socket.
+
+use std::{sync::Arc, time::Duration};
+
+use rsa::signature::Verifier;
+use ssh_encoding::Decode;
+use ssh_key::{HashAlg, PrivateKey, SshSig};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+    sync::Mutex,
+};
+
+use super::{
+    audit_log::{AuditDecision, AuditFilter, AuditLog, AuditOperation},
+    peerinfo,
+    protocol::{Request, Response, MAX_MESSAGE_LENGTH, SSH_AGENT_RSA_SHA2_512},
+    rate_limit::BlockedProcess,
+    sign_purpose::SignPurpose,
+    test_support::{agent_key, TempDir},
+    BitwardenDesktopAgent, SshAgentUIRequest,
+};
+
+/// Short, so that unanswered prompts time out within the test
+const UI_RESPONSE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A prompt the scripted UI was shown
+#[derive(Clone, Debug)]
+struct Prompt {
+    cipher_id: Option<String>,
+    is_list: bool,
+    process_name: String,
+    purpose: Option<SignPurpose>,
+    /// Set for the notice that the process was blocked, which is not answered
+    blocked_process: Option<BlockedProcess>,
+}
+
+/// Answers a prompt, `None` leaves it unanswered until it times out
+type Responder = fn(&SshAgentUIRequest) -> Option<bool>;
+
+struct Harness {
+    agent: BitwardenDesktopAgent,
+    /// Removed after the agent is stopped, fields are dropped after `Drop::drop`
+    directory: TempDir,
+    prompts: Arc<std::sync::Mutex<Vec<Prompt>>>,
+}
+
+impl Harness {
+    /// Starts an agent on a socket in a new temporary directory, serving the test keys
+    async fn start(responder: Responder) -> Self {
+        let directory = TempDir::new("agent");
+
+        let (request_tx, mut request_rx) = tokio::sync::mpsc::channel::<SshAgentUIRequest>(32);
+        let (response_tx, response_rx) = tokio::sync::broadcast::channel::<(u32, bool)>(32);
+        let mut agent = BitwardenDesktopAgent::new(
+            request_tx,
+            Arc::new(Mutex::new(response_rx)),
+            Some(AuditLog::new(directory.join("audit.log"))),
+            directory.join("agent.sock"),
+            None,
+        );
+        agent.ui_response_timeout = UI_RESPONSE_TIMEOUT;
+        agent.listen().unwrap();
+
+        let prompts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let shown = prompts.clone();
+        tokio::spawn(async move {
+            while let Some(request) = request_rx.recv().await {
+                shown.lock().unwrap().push(Prompt {
+                    cipher_id: request.cipher_id.clone(),
+                    is_list: request.is_list,
+                    process_name: request.process_name.clone(),
+                    purpose: request.purpose.clone(),
+                    blocked_process: request.blocked_process.clone(),
+                });
+                if let Some(approved) = responder(&request) {
+                    let _ = response_tx.send((request.request_id, approved));
+                }
+            }
+        });
+
+        agent
+            .upsert_keys(vec![
+                agent_key("ed25519-cipher", &ed25519_key()),
+                agent_key(
+                    "rsa-cipher",
+                    &PrivateKey::from_openssh(include_str!("./test_keys/rsa_openssh_unencrypted"))
+                        .unwrap(),
+                ),
+            ])
+            .unwrap();
+
+        Harness {
+            agent,
+            directory,
+            prompts,
+        }
+    }
+
+    async fn connect(&self) -> Client {
+        Client(
+            UnixStream::connect(self.directory.join("agent.sock"))
+                .await
+                .unwrap(),
+        )
+    }
+
+    fn prompts(&self) -> Vec<Prompt> {
+        self.prompts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|prompt| prompt.blocked_process.is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Waits for the notices of blocked processes, which are sent after the request that caused them is answered
+    async fn blocked_notices(&self, count: usize) -> Vec<BlockedProcess> {
+        for _ in 0..50 {
+            let notices = self
+                .prompts
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|prompt| prompt.blocked_process.clone())
+                .collect::<Vec<_>>();
+            if notices.len() >= count {
+                return notices;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Expected {} blocked process notices", count);
+    }
+
+    fn audit_decisions(&self, operation: AuditOperation) -> Vec<AuditDecision> {
+        let mut entries = self
+            .agent
+            .query_audit_log(&AuditFilter {
+                operation: Some(operation),
+                ..Default::default()
+            })
+            .unwrap();
+        // Oldest first, in the order the requests were made
+        entries.reverse();
+        for entry in &entries {
+            assert_eq!(entry.pid, std::process::id());
+        }
+        entries.into_iter().map(|entry| entry.decision).collect()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.agent.stop();
+    }
+}
+
+/// A client speaking the agent protocol, like `ssh` or `ssh-add`
+struct Client(UnixStream);
+
+impl Client {
+    async fn request(&mut self, request: &Request) -> Response {
+        self.0
+            .write_all(&request.to_bytes().unwrap())
+            .await
+            .unwrap();
+        let length = self.0.read_u32().await.unwrap() as usize;
+        assert!(length > 0 && length <= MAX_MESSAGE_LENGTH);
+        let mut message = vec![0u8; length];
+        self.0.read_exact(&mut message).await.unwrap();
+        Response::parse(&message).unwrap()
+    }
+
+    async fn identities(&mut self) -> Vec<(Vec<u8>, String)> {
+        match self.request(&Request::RequestIdentities).await {
+            Response::Identities(identities) => identities,
+            response => panic!("Expected identities, got {:?}", response),
+        }
+    }
+
+    async fn sign(&mut self, private_key: &PrivateKey, data: &[u8], flags: u32) -> Response {
+        self.request(&Request::SignRequest {
+            key_blob: private_key.public_key().to_bytes().unwrap(),
+            data: data.to_vec(),
+            flags,
+        })
+        .await
+    }
+}
+
+fn ed25519_key() -> PrivateKey {
+    PrivateKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted")).unwrap()
+}
+
+fn rsa_key() -> PrivateKey {
+    PrivateKey::from_openssh(include_str!("./test_keys/rsa_openssh_unencrypted")).unwrap()
+}
+
+fn commit_signature_data() -> Vec<u8> {
+    SshSig::signed_data("git", HashAlg::Sha512, b"commit").unwrap()
+}
+
+fn own_process_name() -> String {
+    peerinfo::gather::get_peer_info(std::process::id())
+        .unwrap()
+        .process_name()
+        .to_string()
+}
+
+fn assert_signed_by(response: Response, private_key: &PrivateKey, data: &[u8]) -> String {
+    let Response::SignResponse(signature_bytes) = response else {
+        panic!("Expected a signature, got {:?}", response);
+    };
+    let signature = ssh_key::Signature::decode(&mut signature_bytes.as_slice()).unwrap();
+    assert!(private_key
+        .public_key()
+        .key_data()
+        .verify(data, &signature)
+        .is_ok());
+    signature.algorithm().to_string()
+}
+
+#[tokio::test]
+async fn approved_requests_list_and_sign() {
+    let harness = Harness::start(|_| Some(true)).await;
+    let mut client = harness.connect().await;
+    let data = commit_signature_data();
+
+    let identities = client.identities().await;
+    let mut names = identities
+        .iter()
+        .map(|(_, name)| name.as_str())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["ed25519", "rsa"]);
+    assert!(identities.contains(&(
+        ed25519_key().public_key().to_bytes().unwrap(),
+        "ed25519".to_string()
+    )));
+
+    let response = client.sign(&ed25519_key(), &data, 0).await;
+    assert_eq!(
+        assert_signed_by(response, &ed25519_key(), &data),
+        "ssh-ed25519"
+    );
+    let response = client.sign(&rsa_key(), &data, SSH_AGENT_RSA_SHA2_512).await;
+    assert_eq!(
+        assert_signed_by(response, &rsa_key(), &data),
+        "rsa-sha2-512"
+    );
+
+    // The prompts name the process on the other end of the socket, as found through its peer credentials
+    let prompts = harness.prompts();
+    assert_eq!(prompts.len(), 3);
+    assert!(prompts[0].is_list);
+    assert_eq!(prompts[1].cipher_id.as_deref(), Some("ed25519-cipher"));
+    assert_eq!(prompts[2].cipher_id.as_deref(), Some("rsa-cipher"));
+    assert_eq!(
+        prompts[1].purpose,
+        Some(SignPurpose::SshSig {
+            namespace: "git".to_string(),
+            hash_algorithm: "sha512".to_string(),
+        })
+    );
+    for prompt in &prompts {
+        assert_eq!(prompt.process_name, own_process_name());
+    }
+
+    assert_eq!(
+        harness.audit_decisions(AuditOperation::Sign),
+        vec![AuditDecision::UserApproved, AuditDecision::UserApproved]
+    );
+    let status = harness.agent.status();
+    assert_eq!(status.active_connections, 1);
+    assert_eq!(status.denied_requests, 0);
+}
+
+#[tokio::test]
+async fn denied_and_unanswered_requests_fail() {
+    // The ed25519 key is denied, prompts for the rsa key are never answered
+    let harness = Harness::start(|request| match request.cipher_id.as_deref() {
+        Some("ed25519-cipher") => Some(false),
+        _ => None,
+    })
+    .await;
+    let mut client = harness.connect().await;
+    let data = commit_signature_data();
+
+    assert_eq!(
+        client.sign(&ed25519_key(), &data, 0).await,
+        Response::Failure
+    );
+    assert_eq!(
+        client.sign(&rsa_key(), &data, SSH_AGENT_RSA_SHA2_512).await,
+        Response::Failure
+    );
+    // The list prompt is not answered either
+    assert_eq!(
+        client.request(&Request::RequestIdentities).await,
+        Response::Failure
+    );
+
+    assert_eq!(harness.prompts().len(), 3);
+    assert_eq!(
+        harness.audit_decisions(AuditOperation::Sign),
+        vec![AuditDecision::Denied, AuditDecision::TimedOut]
+    );
+    assert_eq!(
+        harness.audit_decisions(AuditOperation::List),
+        vec![AuditDecision::TimedOut]
+    );
+    assert_eq!(harness.agent.status().denied_requests, 3);
+}
+
+#[tokio::test]
+async fn repeatedly_denied_process_is_blocked() {
+    let harness = Harness::start(|_| Some(false)).await;
+    let mut client = harness.connect().await;
+    let data = commit_signature_data();
+
+    for _ in 0..3 {
+        assert_eq!(
+            client.sign(&ed25519_key(), &data, 0).await,
+            Response::Failure
+        );
+    }
+    let notices = harness.blocked_notices(1).await;
+    assert_eq!(notices[0].process_name, own_process_name());
+
+    // Denied without asking the user until the process is unblocked
+    assert_eq!(
+        client.sign(&ed25519_key(), &data, 0).await,
+        Response::Failure
+    );
+    assert_eq!(harness.prompts().len(), 3);
+    assert_eq!(harness.agent.blocked_processes(), notices);
+
+    assert!(harness.agent.unblock_process(&notices[0].executable));
+    assert_eq!(
+        client.sign(&ed25519_key(), &data, 0).await,
+        Response::Failure
+    );
+    assert_eq!(harness.prompts().len(), 4);
+    assert!(harness.agent.blocked_processes().is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_requests_of_a_process_are_prompted_once() {
+    let harness = Harness::start(|_| None).await;
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        clients.push(harness.connect().await);
+    }
+    let key = ed25519_key();
+    let data = commit_signature_data();
+
+    let responses =
+        futures::future::join_all(clients.iter_mut().map(|client| client.sign(&key, &data, 0)))
+            .await;
+    assert!(responses
+        .iter()
+        .all(|response| *response == Response::Failure));
+
+    // The others are denied without a prompt and without counting towards blocking the process
+    assert_eq!(harness.prompts().len(), 1);
+    let mut decisions = harness.audit_decisions(AuditOperation::Sign);
+    decisions.sort_by_key(|decision| *decision == AuditDecision::TimedOut);
+    assert_eq!(
+        decisions,
+        vec![
+            AuditDecision::Denied,
+            AuditDecision::Denied,
+            AuditDecision::Denied,
+            AuditDecision::TimedOut
+        ]
+    );
+    assert!(harness.agent.blocked_processes().is_empty());
+
+    // Prompted again once the open prompt is answered
+    assert_eq!(clients[0].sign(&key, &data, 0).await, Response::Failure);
+    assert_eq!(harness.prompts().len(), 2);
+}
+
+#[tokio::test]
+async fn requests_of_the_app_are_not_rate_limited() {
+    let harness = Harness::start(|_| Some(false)).await;
+
+    for _ in 0..4 {
+        assert!(harness
+            .agent
+            .sign_sshsig("ed25519-cipher", "git", b"commit")
+            .await
+            .is_err());
+    }
+    assert_eq!(harness.prompts().len(), 4);
+    assert!(harness.agent.blocked_processes().is_empty());
+}
+
+#[tokio::test]
+async fn locked_agent_does_not_sign() {
+    let mut harness = Harness::start(|_| Some(true)).await;
+    let mut client = harness.connect().await;
+    let data = commit_signature_data();
+
+    // Locked by an agent client, as with `ssh-add -x`
+    let passphrase = b"correct horse".to_vec();
+    assert_eq!(
+        client
+            .request(&Request::Lock {
+                passphrase: passphrase.clone()
+            })
+            .await,
+        Response::Success
+    );
+    assert!(client.identities().await.is_empty());
+    assert_eq!(
+        client.sign(&ed25519_key(), &data, 0).await,
+        Response::Failure
+    );
+    assert_eq!(
+        client
+            .request(&Request::Unlock {
+                passphrase: b"wrong".to_vec()
+            })
+            .await,
+        Response::Failure
+    );
+    assert_eq!(
+        client.request(&Request::Unlock { passphrase }).await,
+        Response::Success
+    );
+    let response = client.sign(&ed25519_key(), &data, 0).await;
+    assert_signed_by(response, &ed25519_key(), &data);
+
+    // Locked with the vault, the private keys are gone and the user is not asked
+    harness.agent.lock(None).unwrap();
+    let prompts = harness.prompts().len();
+    assert_eq!(
+        client.sign(&ed25519_key(), &data, 0).await,
+        Response::Failure
+    );
+    assert_eq!(harness.prompts().len(), prompts);
+
+    assert_eq!(
+        harness.audit_decisions(AuditOperation::Unlock),
+        vec![AuditDecision::Denied, AuditDecision::AutoAllowed]
+    );
+}
+
+#[tokio::test]
+async fn sealed_keys_are_restored_once() {
+    let mut harness = Harness::start(|_| Some(true)).await;
+    let mut client = harness.connect().await;
+    let data = commit_signature_data();
+
+    let wrapped_session_key = harness.agent.lock(Some(b"1234")).unwrap().unwrap();
+    // A second lock would seal nothing, and leave the keys sealed under the first session key
+    assert!(harness.agent.lock(Some(b"1234")).is_err());
+    assert_eq!(
+        client.sign(&ed25519_key(), &data, 0).await,
+        Response::Failure
+    );
+
+    harness.agent.unlock(&wrapped_session_key, b"1234").unwrap();
+    let response = client.sign(&ed25519_key(), &data, 0).await;
+    assert_signed_by(response, &ed25519_key(), &data);
+}
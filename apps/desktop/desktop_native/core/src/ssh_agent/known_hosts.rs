@@ -0,0 +1,298 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use homedir::my_home;
+use sha1::Sha1;
+
+const HASHED_HOSTNAME_PREFIX: &str = "|1|";
+const REVOKED_MARKER: &str = "@revoked";
+#[cfg(not(target_os = "windows"))]
+const SYSTEM_KNOWN_HOSTS_PATH: &str = "/etc/ssh/ssh_known_hosts";
+/// Options of ssh that take a value, as listed in its usage
+const OPTIONS_WITH_VALUE: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/**
+* Looks up the hostnames a host key is known under in the user's and the system's known_hosts files.
+*
+* Hashed (`|1|`) entries can not be reversed, so they are only matched against the candidate hostnames,
+* usually taken from the command line of the connecting ssh client. If the key is marked as revoked in
+* any of the files, no hostnames are returned.
+*/
+pub fn find_hostnames(host_key: &[u8], candidate_hostnames: &[String]) -> Vec<String> {
+    let mut paths = Vec::new();
+    if let Ok(Some(home)) = my_home() {
+        paths.push(home.join(".ssh").join("known_hosts"));
+    }
+    #[cfg(not(target_os = "windows"))]
+    paths.push(SYSTEM_KNOWN_HOSTS_PATH.into());
+
+    let mut hostnames = Vec::new();
+    for path in paths {
+        let Ok(known_hosts) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        match find_hostnames_in(&known_hosts, host_key, candidate_hostnames) {
+            Some(found) => hostnames.extend(found),
+            None => {
+                println!(
+                    "[SSH Agent Native Module] Host key is marked as revoked in {:?}",
+                    path
+                );
+                return Vec::new();
+            }
+        }
+    }
+
+    let mut unique_hostnames = Vec::new();
+    for hostname in hostnames {
+        if !unique_hostnames.contains(&hostname) {
+            unique_hostnames.push(hostname);
+        }
+    }
+    unique_hostnames
+}
+
+/// Returns `None` if the host key is revoked in the given known_hosts contents.
+fn find_hostnames_in(
+    known_hosts: &str,
+    host_key: &[u8],
+    candidate_hostnames: &[String],
+) -> Option<Vec<String>> {
+    let mut hostnames = Vec::new();
+
+    for line in known_hosts.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (marker, host_patterns) = match fields.next() {
+            Some(marker) if marker.starts_with('@') => (Some(marker), fields.next()),
+            host_patterns => (None, host_patterns),
+        };
+        let (Some(host_patterns), Some(_key_type), Some(encoded_key)) =
+            (host_patterns, fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if STANDARD.decode(encoded_key).ok().as_deref() != Some(host_key) {
+            continue;
+        }
+
+        match marker {
+            Some(REVOKED_MARKER) => return None,
+            // @cert-authority entries hold the key of a CA, not the host key itself
+            Some(_) => continue,
+            None => {}
+        }
+
+        if let Some(hashed) = host_patterns.strip_prefix(HASHED_HOSTNAME_PREFIX) {
+            hostnames.extend(
+                candidate_hostnames
+                    .iter()
+                    .filter(|candidate| matches_hashed_hostname(hashed, candidate))
+                    .map(|candidate| display_hostname(candidate)),
+            );
+        } else {
+            hostnames.extend(
+                host_patterns
+                    .split(',')
+                    // Negations and wildcards are patterns, not names of hosts
+                    .filter(|pattern| {
+                        !pattern.is_empty()
+                            && !pattern.starts_with('!')
+                            && !pattern.contains(['*', '?'])
+                    })
+                    .map(display_hostname),
+            );
+        }
+    }
+
+    Some(hostnames)
+}
+
+/// Hashed entries have the format `|1|base64(salt)|base64(hmac-sha1(salt, hostname))`
+fn matches_hashed_hostname(hashed: &str, hostname: &str) -> bool {
+    let Some((salt, hash)) = hashed.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (STANDARD.decode(salt), STANDARD.decode(hash)) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(hostname.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+/// Turns the `[host]:port` form used for non-default ports into `host:port`
+fn display_hostname(hostname: &str) -> String {
+    match hostname
+        .strip_prefix('[')
+        .and_then(|hostname| hostname.split_once("]:"))
+    {
+        Some((host, port)) => format!("{}:{}", host, port),
+        None => hostname.to_string(),
+    }
+}
+
+/**
+* Guesses the hostnames an ssh client connects to from its command line, in the form they are stored
+* in known_hosts. This over-approximates, since it is only used to test hashed known_hosts entries.
+*/
+pub fn candidate_hostnames(command_line: &[String]) -> Vec<String> {
+    let mut port = None;
+    let mut hosts = Vec::new();
+
+    let mut args = command_line.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(options) = arg.strip_prefix('-').filter(|options| !options.is_empty()) {
+            // Flags can be grouped (`-vT`), the first option taking a value ends the group and its value is
+            // either the rest of the argument (`-p2222`) or the next argument (`-p 2222`)
+            for (index, option) in options.char_indices() {
+                if !OPTIONS_WITH_VALUE.contains(option) {
+                    continue;
+                }
+                let inline_value = &options[index + option.len_utf8()..];
+                let value = if inline_value.is_empty() {
+                    args.next().cloned()
+                } else {
+                    Some(inline_value.to_string())
+                };
+                if option == 'p' {
+                    port = value;
+                }
+                break;
+            }
+            continue;
+        }
+
+        let destination = arg.strip_prefix("ssh://").unwrap_or(arg);
+        let destination = destination
+            .split_once('/')
+            .map_or(destination, |(destination, _path)| destination);
+        let host = destination
+            .rsplit_once('@')
+            .map_or(destination, |(_user, host)| host);
+        if !host.is_empty() {
+            hosts.push(host.to_string());
+        }
+        // Everything after the destination is the remote command
+        break;
+    }
+
+    hosts
+        .into_iter()
+        .map(|host| match (&port, host.split_once(':')) {
+            // ssh://host:port URLs
+            (_, Some((host, port))) if port.chars().all(|c| c.is_ascii_digit()) => {
+                format!("[{}]:{}", host, port)
+            }
+            (Some(port), _) if port != "22" => format!("[{}]:{}", host, port),
+            _ => host,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_key() -> Vec<u8> {
+        STANDARD
+            .decode("AAAAC3NzaC1lZDI1NTE5AAAAIOqc4RXebQIkcCj3oexOjyCz9lsSWQpq2nLiw4bHeGKX")
+            .unwrap()
+    }
+
+    fn hashed_entry(hostname: &str) -> String {
+        let salt = b"0123456789abcdefghij";
+        let mut mac = Hmac::<Sha1>::new_from_slice(salt).unwrap();
+        mac.update(hostname.as_bytes());
+        format!(
+            "|1|{}|{}",
+            STANDARD.encode(salt),
+            STANDARD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOqc4RXebQIkcCj3oexOjyCz9lsSWQpq2nLiw4bHeGKX";
+    const OTHER_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    #[test]
+    fn find_plain_hostnames() {
+        let known_hosts = format!(
+            "# comment\ngithub.com,140.82.121.4 {KEY}\n[git.example.com]:2222 {KEY}\nother.com {OTHER_KEY}\n*.example.org,!bad.example.org {KEY}\n"
+        );
+        assert_eq!(
+            find_hostnames_in(&known_hosts, &host_key(), &[]).unwrap(),
+            vec!["github.com", "140.82.121.4", "git.example.com:2222"]
+        );
+    }
+
+    #[test]
+    fn find_hashed_hostnames() {
+        let known_hosts = format!(
+            "{} {KEY}\n{} {KEY}\n",
+            hashed_entry("github.com"),
+            hashed_entry("[git.example.com]:2222")
+        );
+        let candidates = vec!["github.com".to_string(), "gitlab.com".to_string()];
+        assert_eq!(
+            find_hostnames_in(&known_hosts, &host_key(), &candidates).unwrap(),
+            vec!["github.com"]
+        );
+        let candidates = vec!["[git.example.com]:2222".to_string()];
+        assert_eq!(
+            find_hostnames_in(&known_hosts, &host_key(), &candidates).unwrap(),
+            vec!["git.example.com:2222"]
+        );
+    }
+
+    #[test]
+    fn revoked_and_cert_authority_markers() {
+        let known_hosts = format!("@cert-authority *.example.com {KEY}\n");
+        assert!(find_hostnames_in(&known_hosts, &host_key(), &[])
+            .unwrap()
+            .is_empty());
+
+        let known_hosts = format!("github.com {KEY}\n@revoked * {KEY}\n");
+        assert!(find_hostnames_in(&known_hosts, &host_key(), &[]).is_none());
+    }
+
+    #[test]
+    fn candidates_from_command_line() {
+        let command_line =
+            |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            candidate_hostnames(&command_line(&["ssh", "-T", "git@github.com"])),
+            vec!["github.com"]
+        );
+        assert_eq!(
+            candidate_hostnames(&command_line(&["ssh", "-p", "2222", "user@host"])),
+            vec!["[host]:2222"]
+        );
+        assert_eq!(
+            candidate_hostnames(&command_line(&["ssh", "ssh://user@host:2222/path"])),
+            vec!["[host]:2222"]
+        );
+        assert_eq!(
+            candidate_hostnames(&command_line(&[
+                "ssh",
+                "-i",
+                "~/.ssh/id",
+                "-o",
+                "SendEnv=GIT_PROTOCOL",
+                "-vp2222",
+                "-J",
+                "jump.example.com",
+                "git@github.com",
+                "git-upload-pack",
+                "'repo.git'"
+            ])),
+            vec!["[github.com]:2222"]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use tokio_util::sync::CancellationToken;
+
+use super::server::Key;
+
+/**
+* Selects the keys a listener serves. A key is served if it matches any of the criteria, and a filter
+* without criteria serves every key. Keys added by agent clients (`ssh-add`) are not part of the vault
+* and are only served on unfiltered listeners.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyFilter {
+    pub cipher_ids: Vec<String>,
+    pub folder_ids: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl KeyFilter {
+    pub fn is_empty(&self) -> bool {
+        self.cipher_ids.is_empty() && self.folder_ids.is_empty() && self.tags.is_empty()
+    }
+
+    pub fn matches(&self, key: &Key) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        key.cipher_uuid
+            .as_ref()
+            .is_some_and(|cipher_id| self.cipher_ids.contains(cipher_id))
+            || key
+                .folder_id
+                .as_ref()
+                .is_some_and(|folder_id| self.folder_ids.contains(folder_id))
+            || key.tags.iter().any(|tag| self.tags.contains(tag))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConfirmationPolicy {
+    /// Every signature has to be approved in the app
+    #[default]
+    Always,
+    /// Signatures are made without asking, e.g. for a socket that only exposes a deploy key to a container.
+    /// Usage policies and allowed host keys of the keys still apply.
+    Never,
+}
+
+/// An additional socket exposing a subset of the keys, such as a socket per context or per container.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListenerConfig {
+    /// Unique name of the listener, shown in prompts for requests arriving on it
+    pub name: String,
+    /// Path of the unix socket, or name of the named pipe on Windows
+    pub path: PathBuf,
+    pub filter: KeyFilter,
+    pub confirmation: ConfirmationPolicy,
+}
+
+/// A listener managed by the agent, served until it is removed or the agent stops.
+#[derive(Clone)]
+pub(super) struct Listener {
+    pub config: std::sync::Arc<ListenerConfig>,
+    /// Child of the agent token, so stopping the agent stops every listener
+    pub cancellation_token: CancellationToken,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(cipher_id: Option<&str>, folder_id: Option<&str>, tags: &[&str]) -> Key {
+        Key {
+            name: "key".to_string(),
+            cipher_uuid: cipher_id.map(str::to_string),
+            folder_id: folder_id.map(str::to_string),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_filter_matches_every_key() {
+        let filter = KeyFilter::default();
+        assert!(filter.matches(&key(Some("cipher"), None, &[])));
+        assert!(filter.matches(&key(None, None, &[])));
+    }
+
+    #[test]
+    fn filter_matches_any_criterion() {
+        let filter = KeyFilter {
+            cipher_ids: vec!["deploy".to_string()],
+            folder_ids: vec!["work".to_string()],
+            tags: vec!["ci".to_string()],
+        };
+        assert!(filter.matches(&key(Some("deploy"), None, &[])));
+        assert!(filter.matches(&key(Some("other"), Some("work"), &[])));
+        assert!(filter.matches(&key(Some("other"), None, &["ci", "prod"])));
+        assert!(!filter.matches(&key(Some("other"), Some("personal"), &["prod"])));
+        // Keys added through ssh-add are not served on filtered listeners
+        assert!(!filter.matches(&key(None, None, &[])));
+    }
+}
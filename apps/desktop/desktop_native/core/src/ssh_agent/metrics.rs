@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    audit_log::{self, AuditDecision, AuditOperation},
+    server::Key,
+};
+
+/// How a key was used, to show e.g. "last used 3 min ago by git" next to it
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyUsage {
+    pub cipher_id: Option<String>,
+    pub name: String,
+    pub fingerprint: String,
+    /// Signatures and decryptions allowed with the key this session
+    pub use_count: u64,
+    /// Requests for the key that were denied or timed out this session
+    pub denied_count: u64,
+    /// Seconds since the unix epoch, kept when the session counters are reset
+    pub last_used: Option<u64>,
+    /// Process that last used the key
+    pub last_used_by: Option<String>,
+}
+
+/// Snapshot of the state and usage of the agent
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgentStatus {
+    pub is_running: bool,
+    /// Socket or named pipe the agent listens on
+    pub socket_path: Option<String>,
+    pub active_connections: u64,
+    /// Connections accepted this session
+    pub total_connections: u64,
+    /// Requests handled this session, by operation
+    pub requests: Vec<(AuditOperation, u64)>,
+    /// Requests denied or timed out this session
+    pub denied_requests: u64,
+    /// Keys used or asked for this session, most recently used first
+    pub keys: Vec<KeyUsage>,
+    /// Seconds since the unix epoch at which the session counters were last reset
+    pub session_started: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    active_connections: u64,
+    total_connections: u64,
+    requests: Vec<(AuditOperation, u64)>,
+    denied_requests: u64,
+    /// By fingerprint, as keys added through `ssh-add` have no cipher id
+    keys: HashMap<String, KeyUsage>,
+    session_started: u64,
+}
+
+/**
+ * Usage counters of the agent, kept in memory only. The counters of a session are reset when the system is
+ * locked, the time a key was last used is kept.
+ */
+#[derive(Clone)]
+pub struct AgentMetrics(Arc<Mutex<Counters>>);
+
+impl Default for AgentMetrics {
+    fn default() -> Self {
+        AgentMetrics(Arc::new(Mutex::new(Counters {
+            session_started: audit_log::now(),
+            ..Default::default()
+        })))
+    }
+}
+
+impl AgentMetrics {
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.0.lock().expect("Mutex is not poisoned")
+    }
+
+    pub fn connection_opened(&self) {
+        let mut counters = self.counters();
+        counters.active_connections += 1;
+        counters.total_connections += 1;
+    }
+
+    pub fn connection_closed(&self) {
+        let mut counters = self.counters();
+        counters.active_connections = counters.active_connections.saturating_sub(1);
+    }
+
+    /// Counts a request the agent decided on, and the use of the key it was for
+    pub fn record(
+        &self,
+        operation: AuditOperation,
+        ssh_key: Option<&Key>,
+        decision: AuditDecision,
+        process_name: &str,
+    ) {
+        let key = ssh_key.and_then(|key| {
+            key.fingerprint()
+                .map(|fingerprint| (fingerprint, key.cipher_uuid.clone(), key.name.clone()))
+        });
+        self.count(operation, key, decision, process_name);
+    }
+
+    /// Counts a signature of the upstream agent, whose keys are neither in the vault nor named
+    pub fn record_upstream(
+        &self,
+        fingerprint: String,
+        decision: AuditDecision,
+        process_name: &str,
+    ) {
+        self.count(
+            AuditOperation::Sign,
+            Some((fingerprint, None, String::new())),
+            decision,
+            process_name,
+        );
+    }
+
+    /// `key` is the fingerprint, cipher id and name of the key the request was for
+    fn count(
+        &self,
+        operation: AuditOperation,
+        key: Option<(String, Option<String>, String)>,
+        decision: AuditDecision,
+        process_name: &str,
+    ) {
+        let mut counters = self.counters();
+        match counters
+            .requests
+            .iter_mut()
+            .find(|(recorded, _)| *recorded == operation)
+        {
+            Some((_, count)) => *count += 1,
+            None => counters.requests.push((operation, 1)),
+        }
+        if !decision.is_allowed() {
+            counters.denied_requests += 1;
+        }
+
+        let Some((fingerprint, cipher_id, name)) = key else {
+            return;
+        };
+        let usage = counters
+            .keys
+            .entry(fingerprint.clone())
+            .or_insert_with(|| KeyUsage {
+                cipher_id: None,
+                name: String::new(),
+                fingerprint,
+                use_count: 0,
+                denied_count: 0,
+                last_used: None,
+                last_used_by: None,
+            });
+        usage.cipher_id = cipher_id;
+        usage.name = name;
+        if !decision.is_allowed() {
+            usage.denied_count += 1;
+        } else if matches!(operation, AuditOperation::Sign | AuditOperation::Decrypt) {
+            usage.use_count += 1;
+            usage.last_used = Some(audit_log::now());
+            usage.last_used_by = Some(process_name.to_string());
+        }
+    }
+
+    /// Starts a new session, e.g. when the system is locked. Open connections stay counted as active.
+    pub fn reset_session(&self) {
+        let mut counters = self.counters();
+        counters.total_connections = counters.active_connections;
+        counters.requests.clear();
+        counters.denied_requests = 0;
+        for usage in counters.keys.values_mut() {
+            usage.use_count = 0;
+            usage.denied_count = 0;
+        }
+        counters.session_started = audit_log::now();
+    }
+
+    pub fn status(&self, is_running: bool, socket_path: Option<String>) -> AgentStatus {
+        let counters = self.counters();
+        let mut keys = counters.keys.values().cloned().collect::<Vec<_>>();
+        keys.sort_by_key(|usage| std::cmp::Reverse(usage.last_used));
+        AgentStatus {
+            is_running,
+            socket_path,
+            active_connections: counters.active_connections,
+            total_connections: counters.total_connections,
+            requests: counters.requests.clone(),
+            denied_requests: counters.denied_requests,
+            keys,
+            session_started: counters.session_started,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Key {
+        let private_key =
+            ssh_key::PrivateKey::random(&mut rand::thread_rng(), ssh_key::Algorithm::Ed25519)
+                .unwrap();
+        Key {
+            private_key: Some(private_key),
+            name: name.to_string(),
+            cipher_uuid: Some(format!("{}-cipher", name)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn counts_requests_and_key_usage() {
+        let metrics = AgentMetrics::default();
+        let deploy_key = key("deploy");
+        let signing_key = key("signing");
+
+        metrics.connection_opened();
+        metrics.connection_opened();
+        metrics.connection_closed();
+        metrics.record(
+            AuditOperation::List,
+            None,
+            AuditDecision::AutoAllowed,
+            "ssh",
+        );
+        metrics.record(
+            AuditOperation::Sign,
+            Some(&deploy_key),
+            AuditDecision::UserApproved,
+            "ssh",
+        );
+        metrics.record(
+            AuditOperation::Sign,
+            Some(&signing_key),
+            AuditDecision::TimedOut,
+            "git",
+        );
+
+        let status = metrics.status(true, None);
+        assert_eq!(status.active_connections, 1);
+        assert_eq!(status.total_connections, 2);
+        assert_eq!(
+            status.requests,
+            vec![(AuditOperation::List, 1), (AuditOperation::Sign, 2)]
+        );
+        assert_eq!(status.denied_requests, 1);
+        assert_eq!(status.keys.len(), 2);
+        let deploy = &status.keys[0];
+        assert_eq!(deploy.cipher_id.as_deref(), Some("deploy-cipher"));
+        assert_eq!(deploy.use_count, 1);
+        assert_eq!(deploy.last_used_by.as_deref(), Some("ssh"));
+        let signing = &status.keys[1];
+        assert_eq!((signing.use_count, signing.denied_count), (0, 1));
+        assert_eq!(signing.last_used, None);
+    }
+
+    #[test]
+    fn reset_keeps_last_use_and_open_connections() {
+        let metrics = AgentMetrics::default();
+        let deploy_key = key("deploy");
+        metrics.connection_opened();
+        metrics.record(
+            AuditOperation::Sign,
+            Some(&deploy_key),
+            AuditDecision::AutoAllowed,
+            "ssh",
+        );
+
+        metrics.reset_session();
+        let status = metrics.status(true, None);
+        assert_eq!(status.active_connections, 1);
+        assert_eq!(status.total_connections, 1);
+        assert!(status.requests.is_empty());
+        assert_eq!(status.keys[0].use_count, 0);
+        assert!(status.keys[0].last_used.is_some());
+    }
+}
@@ -1,13 +1,15 @@
-use std::sync::{
-    atomic::{AtomicBool, AtomicU32},
-    Arc,
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32},
+        Arc,
+    },
+    time::Duration,
 };
 
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
-use bitwarden_russh::ssh_agent::{self, Key};
-
 #[cfg_attr(target_os = "windows", path = "windows.rs")]
 #[cfg_attr(target_os = "macos", path = "unix.rs")]
 #[cfg_attr(target_os = "linux", path = "unix.rs")]
@@ -16,11 +18,121 @@ mod platform_ssh_agent;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 mod peercred_unix_listener_stream;
 
+#[cfg(all(test, unix))]
+mod integration_tests;
+
+pub mod age_encryption;
+pub mod audit_log;
+pub mod certificate;
+pub mod exporter;
+pub mod fingerprint;
 pub mod importer;
+pub mod known_hosts;
+pub mod listeners;
+pub mod metrics;
+mod passphrase_lock;
 pub mod peerinfo;
+pub mod policy;
+mod ppk;
+pub mod protocol;
+pub mod rate_limit;
+pub mod scanner;
+pub mod server;
+mod session_key;
+pub mod sign_purpose;
+pub mod ssh_config;
+pub mod sshsig;
+#[cfg(test)]
+mod test_support;
+pub mod upstream;
+
+use crate::biometric::{Biometric, BiometricTrait};
+use audit_log::{AuditDecision, AuditEntry, AuditFilter, AuditLog, AuditOperation};
+use listeners::{ConfirmationPolicy, Listener, ListenerConfig};
+use metrics::{AgentMetrics, AgentStatus};
+use passphrase_lock::PassphraseLock;
+use policy::KeyUsagePolicy;
+use protocol::SessionBind;
+use rate_limit::{BlockedProcess, RateLimiter};
+use server::Key;
+use session_key::SessionKey;
+use sign_purpose::SignPurpose;
+use upstream::UpstreamAgent;
+
+/// How long the user has to respond to a prompt before the request is denied
+const UI_RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often and how long restarting waits for the stopped server to release its socket or pipe
+const RESTART_ATTEMPTS: u32 = 20;
+const RESTART_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// An SSH key stored in the vault, as synced to the agent by the app
+#[derive(Clone)]
+pub struct AgentKey {
+    pub cipher_id: String,
+    pub name: String,
+    /// OpenSSH encoded private key
+    pub private_key: String,
+    pub folder_id: Option<String>,
+    /// tags used to select the keys a listener serves
+    pub tags: Vec<String>,
+    /// disabled keys are removed from the agent, but stay in the vault
+    pub enabled: bool,
+    /// SHA256 fingerprints of the host keys the key may sign in to, empty if any host is allowed
+    pub allowed_host_keys: Vec<String>,
+    /// restricts what the key may sign, `None` if the key is unrestricted
+    pub policy: Option<KeyUsagePolicy>,
+    /// OpenSSH encoded certificates (`ssh-ed25519-cert-v01@openssh.com AAAA...`) issued for the key
+    pub certificates: Vec<String>,
+    /// whether every use of the key has to be verified by the OS (Windows Hello, polkit) after the app approved it
+    pub require_user_verification: bool,
+}
+
+// The private key is left out, so it does not end up in logs
+impl std::fmt::Debug for AgentKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentKey")
+            .field("cipher_id", &self.cipher_id)
+            .field("name", &self.name)
+            .field("private_key", &"<redacted>")
+            .field("folder_id", &self.folder_id)
+            .field("tags", &self.tags)
+            .field("enabled", &self.enabled)
+            .field("allowed_host_keys", &self.allowed_host_keys)
+            .field("policy", &self.policy)
+            .field("certificates", &self.certificates)
+            .field("require_user_verification", &self.require_user_verification)
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySyncStatus {
+    /// the key was not served before
+    Added,
+    /// the private key or the settings of the key changed
+    Updated,
+    /// the agent already served the key as is, its entry is kept
+    Unchanged,
+    /// the key is disabled and no longer served
+    Disabled,
+    /// the private key could not be parsed, the key is not served
+    ParsingError,
+    /// another vault item holds the same private key, the key is only served for that item
+    Duplicate,
+}
+
+/// Outcome of syncing a single key, so that the app can flag vault items holding unusable keys
+#[derive(Clone, Debug)]
+pub struct KeySyncResult {
+    pub cipher_id: String,
+    pub status: KeySyncStatus,
+    /// why the key could not be parsed, or which vault item holds the same key
+    pub error: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct BitwardenDesktopAgent {
-    keystore: ssh_agent::KeyStore,
+    keystore: server::KeyStore,
     cancellation_token: CancellationToken,
     show_ui_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
     get_ui_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
@@ -28,70 +140,267 @@ pub struct BitwardenDesktopAgent {
     /// before first unlock, or after account switching, listing keys should require an unlock to get a list of public keys
     needs_unlock: Arc<AtomicBool>,
     is_running: Arc<AtomicBool>,
+    /// set by agent clients through `ssh-add -x`, independent of the vault lock
+    passphrase_lock: PassphraseLock,
+    /// records what agent clients did, `None` if auditing is disabled
+    audit_log: Option<AuditLog>,
+    /// agent whose keys are served next to the vault keys, set through `BITWARDEN_SSH_UPSTREAM_AUTH_SOCK`
+    upstream: Option<UpstreamAgent>,
+    /// socket file the agent listens on, including an inherited socket. `None` for the Windows named pipe
+    socket_path: Option<std::path::PathBuf>,
+    /// listening socket passed by systemd socket activation, kept open across restarts
+    #[cfg(unix)]
+    inherited_listener: Option<Arc<std::os::unix::net::UnixListener>>,
+    /// additional sockets serving a subset of the keys, by name
+    listeners: Arc<std::sync::Mutex<HashMap<String, Listener>>>,
+    /// the listener this instance serves connections of, `None` for the default socket
+    listener: Option<Arc<ListenerConfig>>,
+    /// native handle of the app window the Windows Hello prompt is shown in, unused on other platforms
+    verification_window: Arc<std::sync::Mutex<Vec<u8>>>,
+    /// connection and request counters, shared by all listeners
+    metrics: AgentMetrics,
+    /// how long the user has to respond to a prompt before the request is denied
+    ui_response_timeout: Duration,
+    /// programs whose requests are denied without prompting, shared by all listeners
+    rate_limiter: RateLimiter,
 }
 
+#[derive(Default)]
 pub struct SshAgentUIRequest {
     pub request_id: u32,
     pub cipher_id: Option<String>,
     pub process_name: String,
     pub is_list: bool,
+    /// SHA256 fingerprint of the host key of the server the signature is for, if the client sent a session bind
+    pub host_key_fingerprint: Option<String>,
+    /// names the destination host key is known under in known_hosts
+    pub host_names: Vec<String>,
+    /// whether the request arrives over a forwarded agent connection
+    pub is_forwarding: bool,
+    /// what the data to be signed is, `None` for list requests
+    pub purpose: Option<SignPurpose>,
+    /// certificate of the key the client signs with, `None` if it uses the plain key
+    pub certificate: Option<certificate::CertificateInfo>,
+    /// key an agent client (`ssh-add`) asks to import into the vault
+    pub import_request: Option<ImportRequest>,
+    /// whether an agent client asks to remove the vault key `cipher_id`
+    pub is_remove: bool,
+    /// name of the listener the request arrived on, `None` for the default socket
+    pub listener_name: Option<String>,
+    /// not a request but a notice: requests of the program are denied without prompting for a while
+    pub blocked_process: Option<rate_limit::BlockedProcess>,
 }
 
-impl ssh_agent::Agent<peerinfo::models::PeerInfo> for BitwardenDesktopAgent {
-    async fn confirm(&self, ssh_key: Key, info: &peerinfo::models::PeerInfo) -> bool {
-        if !self.is_running() {
-            println!("[BitwardenDesktopAgent] Agent is not running, but tried to call confirm");
-            return false;
-        }
+/// A key sent by an agent client through `ssh-add`, which is only stored in the vault once the user confirms.
+pub struct ImportRequest {
+    pub ssh_key: importer::SshKey,
+    pub comment: String,
+    pub constraints: protocol::KeyConstraints,
+}
 
-        let request_id = self.get_request_id().await;
-        println!(
-            "[SSH Agent] Confirming request from application: {}",
-            info.process_name()
+impl server::Agent<peerinfo::models::PeerInfo> for BitwardenDesktopAgent {
+    async fn confirm(
+        &self,
+        ssh_key: Key,
+        certificate: Option<&certificate::Certificate>,
+        purpose: &SignPurpose,
+        session_binds: &[SessionBind],
+        info: &peerinfo::models::PeerInfo,
+    ) -> bool {
+        let decision = self
+            .confirm_sign(&ssh_key, certificate, purpose, session_binds, info, true)
+            .await;
+        self.record(
+            AuditOperation::Sign,
+            Some(&ssh_key),
+            Some(purpose),
+            decision,
+            info,
         );
+        decision.is_allowed()
+    }
 
-        let mut rx_channel = self.get_ui_response_rx.lock().await.resubscribe();
-        self.show_ui_request_tx
-            .send(SshAgentUIRequest {
-                request_id,
-                cipher_id: Some(ssh_key.cipher_uuid.clone()),
-                process_name: info.process_name().to_string(),
-                is_list: false,
-            })
+    async fn can_list(&self, info: &peerinfo::models::PeerInfo) -> bool {
+        let decision = if self.needs_unlock.load(std::sync::atomic::Ordering::Relaxed) {
+            let request_id = self.get_request_id().await;
+            self.request_ui_confirmation(
+                SshAgentUIRequest {
+                    request_id,
+                    process_name: info.process_name().to_string(),
+                    is_list: true,
+                    listener_name: self.listener_name(),
+                    ..Default::default()
+                },
+                info,
+                true,
+            )
             .await
-            .expect("Should send request to ui");
-        while let Ok((id, response)) = rx_channel.recv().await {
-            if id == request_id {
-                return response;
+        } else {
+            AuditDecision::AutoAllowed
+        };
+        self.record(AuditOperation::List, None, None, decision, info);
+        decision.is_allowed()
+    }
+
+    async fn import_identity(
+        &self,
+        private_key: &ssh_key::private::PrivateKey,
+        constraints: &protocol::KeyConstraints,
+        info: &peerinfo::models::PeerInfo,
+    ) -> bool {
+        let ssh_key = Key {
+            private_key: Some(private_key.clone()),
+            name: private_key.comment().to_string(),
+            constraints: constraints.clone(),
+            ..Default::default()
+        };
+        let decision = match importer::SshKey::try_from(private_key) {
+            Ok(imported_key) if self.is_running() => {
+                let request_id = self.get_request_id().await;
+                println!(
+                    "[SSH Agent] Application {} asks to import key {}",
+                    info.process_name(),
+                    imported_key.key_fingerprint
+                );
+                self.request_ui_confirmation(
+                    SshAgentUIRequest {
+                        request_id,
+                        process_name: info.process_name().to_string(),
+                        import_request: Some(ImportRequest {
+                            ssh_key: imported_key,
+                            comment: private_key.comment().to_string(),
+                            constraints: constraints.clone(),
+                        }),
+                        listener_name: self.listener_name(),
+                        ..Default::default()
+                    },
+                    info,
+                    true,
+                )
+                .await
             }
-        }
-        false
+            Ok(_) => AuditDecision::Denied,
+            Err(e) => {
+                println!(
+                    "[SSH Agent Native Module] Could not encode key for import: {}",
+                    e
+                );
+                AuditDecision::Denied
+            }
+        };
+        self.record(AuditOperation::Add, Some(&ssh_key), None, decision, info);
+        decision.is_allowed()
     }
 
-    async fn can_list(&self, info: &peerinfo::models::PeerInfo) -> bool {
-        if !self.needs_unlock.load(std::sync::atomic::Ordering::Relaxed) {
-            return true;
-        }
+    async fn remove_vault_identity(&self, ssh_key: Key, info: &peerinfo::models::PeerInfo) -> bool {
+        let decision = if self.is_running() {
+            let request_id = self.get_request_id().await;
+            self.request_ui_confirmation(
+                SshAgentUIRequest {
+                    request_id,
+                    cipher_id: ssh_key.cipher_uuid.clone(),
+                    process_name: info.process_name().to_string(),
+                    is_remove: true,
+                    listener_name: self.listener_name(),
+                    ..Default::default()
+                },
+                info,
+                true,
+            )
+            .await
+        } else {
+            AuditDecision::Denied
+        };
+        self.record(AuditOperation::Remove, Some(&ssh_key), None, decision, info);
+        decision.is_allowed()
+    }
 
-        let request_id = self.get_request_id().await;
+    fn record_operation(
+        &self,
+        operation: AuditOperation,
+        ssh_key: Option<&Key>,
+        decision: AuditDecision,
+        info: &peerinfo::models::PeerInfo,
+    ) {
+        self.record(operation, ssh_key, None, decision, info);
+    }
 
-        let mut rx_channel = self.get_ui_response_rx.lock().await.resubscribe();
-        let message = SshAgentUIRequest {
-            request_id,
+    fn record_upstream_signature(
+        &self,
+        key_blob: &[u8],
+        purpose: &SignPurpose,
+        decision: AuditDecision,
+        info: &peerinfo::models::PeerInfo,
+    ) {
+        let fingerprint = certificate::key_blob_fingerprint(key_blob);
+        self.metrics
+            .record_upstream(fingerprint.clone(), decision, info.process_name());
+        self.write_audit_entry(AuditEntry {
+            timestamp: audit_log::now(),
+            operation: AuditOperation::Sign,
+            decision,
+            key_name: None,
+            fingerprint: Some(fingerprint),
             cipher_id: None,
             process_name: info.process_name().to_string(),
-            is_list: true,
-        };
-        self.show_ui_request_tx
-            .send(message)
-            .await
-            .expect("Should send request to ui");
-        while let Ok((id, response)) = rx_channel.recv().await {
-            if id == request_id {
-                return response;
+            pid: info.pid(),
+            uid: info.uid(),
+            purpose: Some(purpose.to_string()),
+        });
+    }
+
+    fn connection_opened(&self) {
+        self.metrics.connection_opened();
+    }
+
+    fn connection_closed(&self) {
+        self.metrics.connection_closed();
+    }
+
+    async fn lock_with_passphrase(&self, passphrase: &[u8]) -> bool {
+        self.passphrase_lock.lock(passphrase).await
+    }
+
+    async fn unlock_with_passphrase(&self, passphrase: &[u8]) -> bool {
+        self.passphrase_lock.unlock(passphrase).await
+    }
+
+    fn is_passphrase_locked(&self) -> bool {
+        self.passphrase_lock.is_locked()
+    }
+
+    fn upstream(&self) -> Option<&UpstreamAgent> {
+        // Keys of the upstream agent are only served on the default socket
+        match self.listener {
+            Some(_) => None,
+            None => self.upstream.as_ref(),
+        }
+    }
+
+    fn serves(&self, ssh_key: &Key) -> bool {
+        self.listener
+            .as_ref()
+            .is_none_or(|listener| listener.filter.matches(ssh_key))
+    }
+}
+
+/**
+ * Retries serving a socket or pipe of a stopped server. On Windows the pipe cannot be created again until the
+ * accept task and the connections of the stopped server closed their pipe instances, which they do shortly after
+ * being cancelled.
+ */
+fn retry_until_released(
+    mut serve: impl FnMut() -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let mut attempts = 1;
+    loop {
+        match serve() {
+            Err(_) if attempts < RESTART_ATTEMPTS => {
+                attempts += 1;
+                std::thread::sleep(RESTART_RETRY_DELAY);
             }
+            result => return result,
         }
-        false
     }
 }
 
@@ -102,6 +411,7 @@ impl BitwardenDesktopAgent {
             return;
         }
 
+        self.cancellation_token.cancel();
         self.is_running
             .store(false, std::sync::atomic::Ordering::Relaxed);
         self.keystore
@@ -109,65 +419,247 @@ impl BitwardenDesktopAgent {
             .write()
             .expect("RwLock is not poisoned")
             .clear();
+
+        // The socket unit owns the path of an inherited socket, so it is left in place
+        #[cfg(unix)]
+        let socket_path = self
+            .socket_path
+            .as_ref()
+            .filter(|_| self.inherited_listener.is_none());
+        #[cfg(not(unix))]
+        let socket_path = self.socket_path.as_ref();
+        if let Some(socket_path) = socket_path {
+            remove_socket_file(socket_path);
+        }
+        // The listeners are kept, and served again on restart
+        for listener in self
+            .listeners
+            .lock()
+            .expect("Mutex is not poisoned")
+            .values()
+        {
+            remove_socket_file(&listener.config.path);
+        }
     }
 
-    pub fn set_keys(
-        &mut self,
-        new_keys: Vec<(String, String, String)>,
-    ) -> Result<(), anyhow::Error> {
+    /**
+     * Stops the agent if it is running and starts serving again, keeping the UI channels and the audit log.
+     * The keys are cleared and have to be synced again. Must be called from within the tokio runtime, but not
+     * on one of its worker threads, as it blocks until the tasks of the stopped server released its pipes.
+     */
+    pub fn restart(&mut self) -> Result<(), anyhow::Error> {
+        if self.is_running() {
+            self.stop();
+        }
+
+        // Connections of the previous server still hold the cancelled token and the old running flag
+        self.cancellation_token = CancellationToken::new();
+        self.is_running = Arc::new(AtomicBool::new(false));
+        retry_until_released(|| self.listen())?;
+
+        let mut listeners = self.listeners.lock().expect("Mutex is not poisoned");
+        for listener in listeners.values_mut() {
+            listener.cancellation_token = self.cancellation_token.child_token();
+            if let Err(e) = retry_until_released(|| {
+                self.serve_listener(listener.config.clone(), listener.cancellation_token.clone())
+            }) {
+                println!(
+                    "[BitwardenDesktopAgent] Could not restart listener {}: {}",
+                    listener.config.name, e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves an additional socket exposing the keys selected by the filter of the listener.
+    pub fn add_listener(&self, config: ListenerConfig) -> Result<(), anyhow::Error> {
         if !self.is_running() {
             return Err(anyhow::anyhow!(
-                "[BitwardenDesktopAgent] Tried to set keys while agent is not running"
+                "[BitwardenDesktopAgent] Tried to add a listener while agent is not running"
             ));
         }
 
-        let keystore = &mut self.keystore;
-        keystore.0.write().expect("RwLock is not poisoned").clear();
+        let mut listeners = self.listeners.lock().expect("Mutex is not poisoned");
+        if listeners.contains_key(&config.name) {
+            return Err(anyhow::anyhow!(
+                "[BitwardenDesktopAgent] A listener named {} already exists",
+                config.name
+            ));
+        }
+        if self.socket_path.as_ref() == Some(&config.path)
+            || listeners
+                .values()
+                .any(|listener| listener.config.path == config.path)
+        {
+            return Err(anyhow::anyhow!(
+                "[BitwardenDesktopAgent] The agent already listens on {:?}",
+                config.path
+            ));
+        }
 
-        self.needs_unlock
-            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let listener = Listener {
+            config: Arc::new(config),
+            cancellation_token: self.cancellation_token.child_token(),
+        };
+        self.serve_listener(listener.config.clone(), listener.cancellation_token.clone())?;
+        listeners.insert(listener.config.name.clone(), listener);
+        Ok(())
+    }
 
-        for (key, name, cipher_id) in new_keys.iter() {
-            match parse_key_safe(key) {
-                Ok(private_key) => {
-                    let public_key_bytes = private_key
-                        .public_key()
-                        .to_bytes()
-                        .expect("Cipher private key is always correctly parsed");
-                    keystore.0.write().expect("RwLock is not poisoned").insert(
-                        public_key_bytes,
-                        Key {
-                            private_key: Some(private_key),
-                            name: name.clone(),
-                            cipher_uuid: cipher_id.clone(),
-                        },
-                    );
-                }
-                Err(e) => {
-                    eprintln!("[SSH Agent Native Module] Error while parsing key: {}", e);
-                }
-            }
+    /// Stops serving the listener and removes its socket.
+    pub fn remove_listener(&self, name: &str) -> Result<(), anyhow::Error> {
+        let listener = self
+            .listeners
+            .lock()
+            .expect("Mutex is not poisoned")
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("[BitwardenDesktopAgent] No listener named {}", name))?;
+        listener.cancellation_token.cancel();
+        remove_socket_file(&listener.config.path);
+        Ok(())
+    }
+
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        self.listeners
+            .lock()
+            .expect("Mutex is not poisoned")
+            .values()
+            .map(|listener| listener.config.as_ref().clone())
+            .collect()
+    }
+
+    /**
+     * Adds or updates the given vault keys. Entries whose private key and settings did not change are kept
+     * as they are, every other vault key stays untouched. Keys that can not be parsed, or that another vault
+     * item already holds, are not served, and reported in the returned results.
+     */
+    pub fn upsert_keys(
+        &mut self,
+        keys: Vec<AgentKey>,
+    ) -> Result<Vec<KeySyncResult>, anyhow::Error> {
+        if !self.is_running() {
+            return Err(anyhow::anyhow!(
+                "[BitwardenDesktopAgent] Tried to set keys while agent is not running"
+            ));
         }
 
+        let mut keystore = self.keystore.0.write().expect("RwLock is not poisoned");
+        // Listing requires an unlock again after a full resync, as after startup or an account switch, but
+        // not after the incremental syncs that follow
+        if !keystore.values().any(|key| key.cipher_uuid.is_some()) {
+            self.needs_unlock
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        Ok(keys
+            .into_iter()
+            .map(|key| upsert_key(&mut keystore, key))
+            .collect())
+    }
+
+    /// Stops serving the keys of the given vault items, e.g. after they were deleted.
+    pub fn remove_keys(&mut self, cipher_ids: &[String]) -> Result<(), anyhow::Error> {
+        self.keystore
+            .0
+            .write()
+            .expect("RwLock is not poisoned")
+            .retain(|_, key| {
+                key.cipher_uuid
+                    .as_ref()
+                    .is_none_or(|cipher_id| !cipher_ids.contains(cipher_id))
+            });
         Ok(())
     }
 
-    pub fn lock(&mut self) -> Result<(), anyhow::Error> {
+    /**
+     * Removes the plaintext private keys from memory. With a wrapping secret, the keys are kept encrypted
+     * under a random session key, which is returned wrapped by the secret so that `unlock` can restore them.
+     * Without one, the keys are dropped, including keys sealed by an earlier lock, and have to be sent again
+     * through `upsert_keys`. Sealing keys that are already sealed fails, as `unlock` could not restore them.
+     */
+    pub fn lock(
+        &mut self,
+        wrapping_secret: Option<&[u8]>,
+    ) -> Result<Option<String>, anyhow::Error> {
         if !self.is_running() {
             return Err(anyhow::anyhow!(
                 "[BitwardenDesktopAgent] Tried to lock agent, but it is not running"
             ));
         }
 
-        let keystore = &mut self.keystore;
-        keystore
+        let Some(wrapping_secret) = wrapping_secret else {
+            for key in self
+                .keystore
+                .0
+                .write()
+                .expect("RwLock is not poisoned")
+                .values_mut()
+            {
+                key.private_key = None;
+                key.sealed_private_key = None;
+            }
+            return Ok(None);
+        };
+
+        if self.has_sealed_keys() {
+            return Err(anyhow::anyhow!(
+                "[BitwardenDesktopAgent] Tried to lock agent, but it is already locked"
+            ));
+        }
+        // Stretching the secret takes a while, so it is wrapped before requests are blocked on the keystore
+        let session_key = SessionKey::generate();
+        let wrapped_session_key = session_key.wrap(wrapping_secret)?;
+
+        let mut keys = self.keystore.0.write().expect("RwLock is not poisoned");
+        // Another lock might have sealed the keys while the session key was wrapped
+        if keys.values().any(|key| key.sealed_private_key.is_some()) {
+            return Err(anyhow::anyhow!(
+                "[BitwardenDesktopAgent] Tried to lock agent, but it is already locked"
+            ));
+        }
+        for key in keys.values_mut() {
+            if let Some(private_key) = &key.private_key {
+                key.sealed_private_key = Some(session_key.seal(&private_key.to_bytes()?)?);
+                key.private_key = None;
+            }
+        }
+        Ok(Some(wrapped_session_key))
+    }
+
+    fn has_sealed_keys(&self) -> bool {
+        self.keystore
             .0
-            .write()
+            .read()
             .expect("RwLock is not poisoned")
-            .iter_mut()
-            .for_each(|(_public_key, key)| {
-                key.private_key = None;
-            });
+            .values()
+            .any(|key| key.sealed_private_key.is_some())
+    }
+
+    /// Restores the private keys sealed by `lock`, given the wrapped session key and the secret it was wrapped with.
+    pub fn unlock(
+        &mut self,
+        wrapped_session_key: &str,
+        wrapping_secret: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        let session_key = SessionKey::unwrap(wrapped_session_key, wrapping_secret)?;
+        let mut keys = self.keystore.0.write().expect("RwLock is not poisoned");
+        // Decrypt everything before changing any key, so a wrong session key leaves the agent locked
+        let mut private_keys = Vec::new();
+        for (public_key_bytes, key) in keys.iter() {
+            if let Some(sealed_private_key) = &key.sealed_private_key {
+                let private_key_bytes = session_key.open(sealed_private_key)?;
+                private_keys.push((
+                    public_key_bytes.clone(),
+                    ssh_key::private::PrivateKey::from_bytes(&private_key_bytes)?,
+                ));
+            }
+        }
+        for (public_key_bytes, private_key) in private_keys {
+            if let Some(key) = keys.get_mut(&public_key_bytes) {
+                key.private_key = Some(private_key);
+                key.sealed_private_key = None;
+            }
+        }
         Ok(())
     }
 
@@ -190,9 +682,661 @@ impl BitwardenDesktopAgent {
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Sets the native handle of the app window, in which Windows shows the prompt of keys requiring user verification
+    pub fn set_verification_window(&self, window: Vec<u8>) {
+        *self
+            .verification_window
+            .lock()
+            .expect("Mutex is not poisoned") = window;
+    }
+
     pub fn is_running(&self) -> bool {
         self.is_running.load(std::sync::atomic::Ordering::Relaxed)
     }
+
+    async fn confirm_sign(
+        &self,
+        ssh_key: &Key,
+        certificate: Option<&certificate::Certificate>,
+        purpose: &SignPurpose,
+        session_binds: &[SessionBind],
+        info: &peerinfo::models::PeerInfo,
+        rate_limited: bool,
+    ) -> AuditDecision {
+        if !self.is_running() {
+            println!("[BitwardenDesktopAgent] Agent is not running, but tried to call confirm");
+            return AuditDecision::Denied;
+        }
+
+        if let Some(usage_policy) = &ssh_key.usage_policy {
+            if let Err(reason) = usage_policy.check(purpose) {
+                println!(
+                    "[SSH Agent] Denied request from application {} for key {}: {}",
+                    info.process_name(),
+                    ssh_key.name,
+                    reason
+                );
+                return AuditDecision::Denied;
+            }
+        }
+
+        if certificate.is_some_and(|certificate| !certificate.is_valid_at(audit_log::now())) {
+            println!(
+                "[SSH Agent] Denied signing with a certificate of key {} outside of its validity period",
+                ssh_key.name
+            );
+            return AuditDecision::Denied;
+        }
+
+        if !ssh_key.allowed_host_keys.is_empty()
+            && !is_allowed_destination(&ssh_key.allowed_host_keys, purpose, session_binds)
+        {
+            println!(
+                "[SSH Agent] Denied signing with key {} for a host that is not allowed",
+                ssh_key.name
+            );
+            return AuditDecision::Denied;
+        }
+
+        // Keys added through ssh-add are only confirmed if they were added with `ssh-add -c`
+        if ssh_key.cipher_uuid.is_none() && !ssh_key.constraints.confirm {
+            return AuditDecision::AutoAllowed;
+        }
+
+        let decision = if self
+            .listener
+            .as_ref()
+            .is_some_and(|listener| listener.confirmation == ConfirmationPolicy::Never)
+        {
+            AuditDecision::AutoAllowed
+        } else {
+            self.request_sign_confirmation(
+                ssh_key,
+                certificate,
+                purpose,
+                session_binds,
+                info,
+                rate_limited,
+            )
+            .await
+        };
+
+        if decision.is_allowed() && ssh_key.require_user_verification {
+            return self.verify_user(ssh_key).await;
+        }
+        decision
+    }
+
+    async fn request_sign_confirmation(
+        &self,
+        ssh_key: &Key,
+        certificate: Option<&certificate::Certificate>,
+        purpose: &SignPurpose,
+        session_binds: &[SessionBind],
+        info: &peerinfo::models::PeerInfo,
+        rate_limited: bool,
+    ) -> AuditDecision {
+        let destination = session_binds.last();
+
+        let host_names = match destination {
+            Some(bind) => {
+                let host_key = bind.host_key_blob.clone();
+                let candidates = known_hosts::candidate_hostnames(info.command_line());
+                // Reading the known_hosts files blocks, which must not stall the other connections
+                tokio::task::spawn_blocking(move || {
+                    known_hosts::find_hostnames(&host_key, &candidates)
+                })
+                .await
+                .unwrap_or_default()
+            }
+            None => Vec::new(),
+        };
+
+        if purpose.is_suspicious() {
+            println!(
+                "[SSH Agent] Application {} requested a signature for data that is neither an ssh authentication nor an SSHSIG signature",
+                info.process_name()
+            );
+        }
+
+        let request_id = self.get_request_id().await;
+        println!(
+            "[SSH Agent] Confirming request from application: {}",
+            info.process_name()
+        );
+
+        self.request_ui_confirmation(
+            SshAgentUIRequest {
+                request_id,
+                cipher_id: ssh_key.cipher_uuid.clone(),
+                process_name: info.process_name().to_string(),
+                is_list: false,
+                host_key_fingerprint: destination.map(|bind| bind.host_key_fingerprint()),
+                host_names,
+                is_forwarding: session_binds.iter().any(|bind| bind.is_forwarding),
+                purpose: Some(purpose.clone()),
+                certificate: certificate.map(|certificate| certificate.info.clone()),
+                listener_name: self.listener_name(),
+                ..Default::default()
+            },
+            info,
+            rate_limited,
+        )
+        .await
+    }
+
+    /**
+     * Asks the OS to verify the user (Windows Hello, polkit on Linux) before a key that requires it is used.
+     * This does not go through the renderer, so approving in the app alone can not unlock signing.
+     */
+    async fn verify_user(&self, ssh_key: &Key) -> AuditDecision {
+        let window = self
+            .verification_window
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clone();
+        if cfg!(target_os = "windows") && window.is_empty() {
+            println!(
+                "[SSH Agent] Can not verify the user for key {}, the app window is not known",
+                ssh_key.name
+            );
+            return AuditDecision::VerificationFailed;
+        }
+
+        let message = format!("Verify your identity to use the SSH key {}", ssh_key.name);
+        match Biometric::prompt(window, message).await {
+            Ok(true) => AuditDecision::UserApproved,
+            Ok(false) => {
+                println!(
+                    "[SSH Agent] User verification for key {} failed",
+                    ssh_key.name
+                );
+                AuditDecision::VerificationFailed
+            }
+            Err(e) => {
+                println!(
+                    "[SSH Agent] User verification for key {} is not available: {}",
+                    ssh_key.name, e
+                );
+                AuditDecision::VerificationFailed
+            }
+        }
+    }
+
+    fn listener_name(&self) -> Option<String> {
+        self.listener.as_ref().map(|listener| listener.name.clone())
+    }
+
+    /**
+     * Shows the request to the user, unless the requesting program is blocked for repeatedly having its requests
+     * denied. Blocking is announced to the UI with a request that is not answered.
+     */
+    async fn request_ui_confirmation(
+        &self,
+        request: SshAgentUIRequest,
+        info: &peerinfo::models::PeerInfo,
+        rate_limited: bool,
+    ) -> AuditDecision {
+        if rate_limited && self.rate_limiter.is_blocked(info.executable()) {
+            println!(
+                "[SSH Agent] Denied request from blocked application {}",
+                info.process_name()
+            );
+            return AuditDecision::Denied;
+        }
+        // Held until the prompt is answered, or the connection waiting for it is closed
+        let _open_prompt = match rate_limited {
+            true => match self.rate_limiter.open_prompt(info.executable()) {
+                Some(open_prompt) => Some(open_prompt),
+                None => {
+                    println!(
+                        "[SSH Agent] Denied request from {} while another one of its requests is prompted",
+                        info.process_name()
+                    );
+                    return AuditDecision::Denied;
+                }
+            },
+            false => None,
+        };
+
+        let request_id = request.request_id;
+        let mut rx_channel = self.get_ui_response_rx.lock().await.resubscribe();
+        self.show_ui_request_tx
+            .send(request)
+            .await
+            .expect("Should send request to ui");
+
+        let response = tokio::time::timeout(self.ui_response_timeout, async {
+            while let Ok((id, response)) = rx_channel.recv().await {
+                if id == request_id {
+                    return response;
+                }
+            }
+            false
+        })
+        .await;
+        let decision = match response {
+            Ok(true) => AuditDecision::UserApproved,
+            Ok(false) => AuditDecision::Denied,
+            Err(_) => AuditDecision::TimedOut,
+        };
+
+        if rate_limited && !decision.is_allowed() {
+            if let Some(blocked_process) = self
+                .rate_limiter
+                .record_failure(info.executable(), info.process_name())
+            {
+                println!(
+                    "[SSH Agent] Blocking application {} after repeatedly denied requests",
+                    info.process_name()
+                );
+                let request_id = self.get_request_id().await;
+                self.show_ui_request_tx
+                    .send(SshAgentUIRequest {
+                        request_id,
+                        process_name: info.process_name().to_string(),
+                        blocked_process: Some(blocked_process),
+                        listener_name: self.listener_name(),
+                        ..Default::default()
+                    })
+                    .await
+                    .expect("Should send request to ui");
+            }
+        }
+        decision
+    }
+
+    fn record(
+        &self,
+        operation: AuditOperation,
+        ssh_key: Option<&Key>,
+        purpose: Option<&SignPurpose>,
+        decision: AuditDecision,
+        info: &peerinfo::models::PeerInfo,
+    ) {
+        self.metrics
+            .record(operation, ssh_key, decision, info.process_name());
+        self.write_audit_entry(AuditEntry {
+            timestamp: audit_log::now(),
+            operation,
+            decision,
+            key_name: ssh_key.map(|key| key.name.clone()),
+            fingerprint: ssh_key.and_then(|key| key.fingerprint()),
+            cipher_id: ssh_key.and_then(|key| key.cipher_uuid.clone()),
+            process_name: info.process_name().to_string(),
+            pid: info.pid(),
+            uid: info.uid(),
+            purpose: purpose.map(|purpose| purpose.to_string()),
+        });
+    }
+
+    fn write_audit_entry(&self, entry: AuditEntry) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        if let Err(e) = audit_log.record(&entry) {
+            println!("[SSH Agent Native Module] Could not write audit log: {}", e);
+        }
+    }
+
+    /// Snapshot of the connections, requests and key usage of the agent
+    pub fn status(&self) -> AgentStatus {
+        self.metrics.status(
+            self.is_running(),
+            self.socket_path()
+                .map(|path| path.to_string_lossy().to_string()),
+        )
+    }
+
+    /// Programs whose requests are currently denied without prompting
+    pub fn blocked_processes(&self) -> Vec<BlockedProcess> {
+        self.rate_limiter.blocked_processes()
+    }
+
+    /// Prompts for the requests of a blocked program again. Returns whether it was blocked.
+    pub fn unblock_process(&self, executable: &str) -> bool {
+        self.rate_limiter.unblock(executable)
+    }
+
+    /// Resets the counters of the session, e.g. when the system is locked
+    pub fn reset_session_metrics(&self) {
+        self.metrics.reset_session();
+    }
+
+    /// Returns the recorded agent operations matching the filter, newest first
+    pub fn query_audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>, anyhow::Error> {
+        match &self.audit_log {
+            Some(audit_log) => audit_log.query(filter),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Whether an agent client locked the agent with a passphrase (`ssh-add -x`)
+    pub fn is_passphrase_locked(&self) -> bool {
+        self.passphrase_lock.is_locked()
+    }
+
+    /**
+     * Signs the data with a vault key as an armored SSHSIG signature, like `ssh-keygen -Y sign`, without going through
+     * the socket. The request is confirmed and recorded like a signature requested by an agent client, with the app
+     * itself as the requesting process.
+     */
+    pub async fn sign_sshsig(
+        &self,
+        cipher_id: &str,
+        namespace: &str,
+        data: &[u8],
+    ) -> Result<String, anyhow::Error> {
+        let (ssh_key, private_key) = self.vault_key(cipher_id)?;
+        let purpose = SignPurpose::parse(&ssh_key::SshSig::signed_data(
+            namespace,
+            ssh_key::HashAlg::Sha512,
+            data,
+        )?);
+        if !self
+            .confirm_own_request(&ssh_key, &purpose, AuditOperation::Sign)
+            .await
+        {
+            return Err(anyhow::anyhow!("Signing was denied"));
+        }
+
+        Ok(private_key
+            .sign(namespace, ssh_key::HashAlg::Sha512, data)?
+            .to_pem(ssh_key::LineEnding::LF)?)
+    }
+
+    /**
+     * Issues an OpenSSH certificate for the public key with a vault key as the CA. The request is confirmed and
+     * recorded like a signature requested by an agent client, with the app itself as the requesting process.
+     */
+    pub async fn issue_certificate(
+        &self,
+        ca_cipher_id: &str,
+        public_key: &str,
+        options: &certificate::CertificateOptions,
+    ) -> Result<String, anyhow::Error> {
+        let public_key = ssh_key::PublicKey::from_openssh(public_key.trim())?;
+        let (ssh_key, ca_key) = self.vault_key(ca_cipher_id)?;
+        let purpose = SignPurpose::Certificate {
+            key_id: options.key_id.clone(),
+            principals: options.principals.clone(),
+            is_host_certificate: options.is_host_certificate,
+        };
+        if !self
+            .confirm_own_request(&ssh_key, &purpose, AuditOperation::Sign)
+            .await
+        {
+            return Err(anyhow::anyhow!("Issuing the certificate was denied"));
+        }
+
+        Ok(certificate::issue_certificate(&ca_key, &public_key, options)?.to_openssh())
+    }
+
+    /**
+     * Confirms and records an operation the app requests itself, with the app as the requesting process. The app
+     * is not rate limited: the user denying a prompt they triggered in the app must not block the app.
+     */
+    async fn confirm_own_request(
+        &self,
+        ssh_key: &Key,
+        purpose: &SignPurpose,
+        operation: AuditOperation,
+    ) -> bool {
+        let info = own_peer_info();
+        let decision = self
+            .confirm_sign(ssh_key, None, purpose, &[], &info, false)
+            .await;
+        self.record(operation, Some(ssh_key), Some(purpose), decision, &info);
+        decision.is_allowed()
+    }
+
+    /// The key of a vault item, for operations the app requests itself
+    fn vault_key(&self, cipher_id: &str) -> Result<(Key, ssh_key::PrivateKey), anyhow::Error> {
+        if self.is_passphrase_locked() {
+            return Err(anyhow::anyhow!("The agent is locked"));
+        }
+        let ssh_key = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .values()
+            .find(|key| key.cipher_uuid.as_deref() == Some(cipher_id))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No key for cipher {}", cipher_id))?;
+        let private_key = ssh_key
+            .private_key
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("The key is locked"))?;
+        Ok((ssh_key, private_key))
+    }
+
+    /**
+     * Opens an age file encrypted to one of the vault keys. Only the header is read before the user confirms the
+     * use of the key, like a sign request of the app itself; the returned reader decrypts the rest as it is read.
+     */
+    pub async fn decrypt_age<R: std::io::Read + Send>(
+        &self,
+        input: R,
+    ) -> Result<age_encryption::AgeDecryptor<R>, anyhow::Error> {
+        if self.is_passphrase_locked() {
+            return Err(anyhow::anyhow!("The agent is locked"));
+        }
+        let vault_keys = self
+            .keystore
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .values()
+            .filter(|key| key.cipher_uuid.is_some() && key.private_key.is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        let identities = vault_keys
+            .iter()
+            .filter_map(|key| Some((key.cipher_uuid.clone()?, key.private_key.clone()?)))
+            .collect::<Vec<_>>();
+
+        let decryptor = age_encryption::AgeDecryptor::new(&identities, input)?;
+        let ssh_key = vault_keys
+            .into_iter()
+            .find(|key| key.cipher_uuid.as_deref() == Some(decryptor.cipher_id.as_str()))
+            .expect("The matching identity is a vault key");
+
+        if !self
+            .confirm_own_request(
+                &ssh_key,
+                &SignPurpose::AgeDecryption,
+                AuditOperation::Decrypt,
+            )
+            .await
+        {
+            return Err(anyhow::anyhow!("Decryption was denied"));
+        }
+        Ok(decryptor)
+    }
+}
+
+/**
+* Whether a key restricted to some hosts may sign the request. As in OpenSSH, the only thing such a key signs is
+* authentication to the host the connection was last bound to, within the session of that bind: a bind replayed from
+* another connection carries a session id that differs from the one being authenticated, and a hostbound request must
+* name the bound host key.
+*/
+fn is_allowed_destination(
+    allowed_host_keys: &[String],
+    purpose: &SignPurpose,
+    session_binds: &[SessionBind],
+) -> bool {
+    // The last bind is the host the signature is for, unless the connection is only forwarded to it
+    let Some(bind) = session_binds.last() else {
+        return false;
+    };
+    let SignPurpose::UserAuth {
+        session_id,
+        host_key,
+        ..
+    } = purpose
+    else {
+        return false;
+    };
+    !bind.is_forwarding
+        && allowed_host_keys.contains(&bind.host_key_fingerprint())
+        && *session_id == bind.session_id
+        && host_key
+            .as_ref()
+            .is_none_or(|host_key| *host_key == bind.host_key_blob)
+}
+
+/// The app itself, as the requesting process of operations that do not come from an agent client
+fn own_peer_info() -> peerinfo::models::PeerInfo {
+    peerinfo::gather::get_peer_info(std::process::id()).unwrap_or_else(|_| {
+        peerinfo::models::PeerInfo::new(
+            0,
+            std::process::id(),
+            "Bitwarden".to_string(),
+            vec![],
+            None,
+        )
+    })
+}
+
+/// Reads the upstream agent from `BITWARDEN_SSH_UPSTREAM_AUTH_SOCK`, unless it points at the agent itself
+fn upstream_from_env(own_path: &std::path::Path) -> Option<UpstreamAgent> {
+    let path = std::path::PathBuf::from(std::env::var_os("BITWARDEN_SSH_UPSTREAM_AUTH_SOCK")?);
+    if path == own_path {
+        println!(
+            "[SSH Agent Native Module] BITWARDEN_SSH_UPSTREAM_AUTH_SOCK points to the Bitwarden agent itself, ignoring it"
+        );
+        return None;
+    }
+    println!(
+        "[SSH Agent Native Module] Serving keys of upstream agent at {:?}",
+        path
+    );
+    Some(UpstreamAgent::new(path))
+}
+
+/// Removes the socket file of a stopped listener. Named pipes on Windows disappear with their last handle.
+fn remove_socket_file(path: &std::path::Path) {
+    if cfg!(windows) {
+        return;
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            println!(
+                "[BitwardenDesktopAgent] Could not remove socket file {:?}: {}",
+                path, e
+            );
+        }
+        _ => {}
+    }
+}
+
+fn upsert_key(keystore: &mut HashMap<Vec<u8>, Key>, agent_key: AgentKey) -> KeySyncResult {
+    let existing = keystore
+        .iter()
+        .find(|(_, key)| key.cipher_uuid.as_ref() == Some(&agent_key.cipher_id))
+        .map(|(public_key_bytes, _)| public_key_bytes.clone());
+    let result = |status, error| KeySyncResult {
+        cipher_id: agent_key.cipher_id.clone(),
+        status,
+        error,
+    };
+
+    if !agent_key.enabled {
+        if let Some(public_key_bytes) = existing {
+            keystore.remove(&public_key_bytes);
+        }
+        return result(KeySyncStatus::Disabled, None);
+    }
+
+    let private_key = match parse_key_safe(&agent_key.private_key) {
+        Ok(private_key) => private_key,
+        Err(e) => {
+            eprintln!(
+                "[SSH Agent Native Module] Error while parsing key of cipher {}: {}",
+                agent_key.cipher_id, e
+            );
+            // The previous version of the key must not be served once the vault item changed
+            if let Some(public_key_bytes) = existing {
+                keystore.remove(&public_key_bytes);
+            }
+            return result(KeySyncStatus::ParsingError, Some(e.to_string()));
+        }
+    };
+    let public_key_bytes = private_key
+        .public_key()
+        .to_bytes()
+        .expect("Cipher private key is always correctly parsed");
+    let certificates = certificate::parse_certificates(&private_key, &agent_key.certificates);
+
+    // Keys are indexed by their public key, so a second vault item holding the same key would replace the
+    // first one, and removing either would stop serving it
+    if let Some(other_cipher_id) = keystore
+        .get(&public_key_bytes)
+        .and_then(|key| key.cipher_uuid.as_ref())
+        .filter(|cipher_id| **cipher_id != agent_key.cipher_id)
+    {
+        let error = format!(
+            "The same key is stored in vault item {}, it is served for that item",
+            other_cipher_id
+        );
+        if let Some(existing) = existing {
+            keystore.remove(&existing);
+        }
+        return result(KeySyncStatus::Duplicate, Some(error));
+    }
+
+    let status = if let Some(existing) = existing {
+        if existing == public_key_bytes {
+            let key = keystore
+                .get_mut(&existing)
+                .expect("Existing key is in the keystore");
+            // A key sealed by `lock` is replaced by the plaintext key the app just sent
+            if key.private_key.is_none() {
+                key.private_key = Some(private_key);
+                key.sealed_private_key = None;
+            }
+            if key.name == agent_key.name
+                && key.folder_id == agent_key.folder_id
+                && key.tags == agent_key.tags
+                && key.allowed_host_keys == agent_key.allowed_host_keys
+                && key.usage_policy == agent_key.policy
+                && key.certificates == certificates
+                && key.require_user_verification == agent_key.require_user_verification
+            {
+                return result(KeySyncStatus::Unchanged, None);
+            }
+            key.name = agent_key.name;
+            key.folder_id = agent_key.folder_id;
+            key.tags = agent_key.tags;
+            key.allowed_host_keys = agent_key.allowed_host_keys;
+            key.usage_policy = agent_key.policy;
+            key.certificates = certificates;
+            key.require_user_verification = agent_key.require_user_verification;
+            return result(KeySyncStatus::Updated, None);
+        }
+        keystore.remove(&existing);
+        KeySyncStatus::Updated
+    } else {
+        KeySyncStatus::Added
+    };
+    keystore.insert(
+        public_key_bytes,
+        Key {
+            private_key: Some(private_key),
+            name: agent_key.name.clone(),
+            cipher_uuid: Some(agent_key.cipher_id.clone()),
+            folder_id: agent_key.folder_id.clone(),
+            tags: agent_key.tags.clone(),
+            allowed_host_keys: agent_key.allowed_host_keys.clone(),
+            usage_policy: agent_key.policy.clone(),
+            certificates,
+            require_user_verification: agent_key.require_user_verification,
+            ..Default::default()
+        },
+    );
+    result(status, None)
 }
 
 fn parse_key_safe(pem: &str) -> Result<ssh_key::private::PrivateKey, anyhow::Error> {
@@ -207,3 +1351,173 @@ fn parse_key_safe(pem: &str) -> Result<ssh_key::private::PrivateKey, anyhow::Err
         Err(e) => Err(anyhow::Error::msg(format!("Failed to parse key: {}", e))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::test_support::agent_key;
+
+    fn generate_key() -> ssh_key::private::PrivateKey {
+        ssh_key::private::PrivateKey::random(&mut rand::thread_rng(), ssh_key::Algorithm::Ed25519)
+            .unwrap()
+    }
+
+    #[test]
+    fn upsert_keeps_unchanged_keys() {
+        let mut keystore = HashMap::new();
+        let private_key = generate_key();
+
+        let added = upsert_key(&mut keystore, agent_key("cipher", &private_key));
+        assert_eq!(added.status, KeySyncStatus::Added);
+        keystore.values_mut().next().unwrap().expires_at = Some(std::time::Instant::now());
+
+        let unchanged = upsert_key(&mut keystore, agent_key("cipher", &private_key));
+        assert_eq!(unchanged.status, KeySyncStatus::Unchanged);
+        // The entry was kept rather than replaced
+        assert!(keystore.values().next().unwrap().expires_at.is_some());
+
+        let mut renamed = agent_key("cipher", &private_key);
+        renamed.name = "renamed".to_string();
+        assert_eq!(
+            upsert_key(&mut keystore, renamed).status,
+            KeySyncStatus::Updated
+        );
+        assert_eq!(keystore.values().next().unwrap().name, "renamed");
+
+        let mut verified = agent_key("cipher", &private_key);
+        verified.name = "renamed".to_string();
+        verified.require_user_verification = true;
+        assert_eq!(
+            upsert_key(&mut keystore, verified).status,
+            KeySyncStatus::Updated
+        );
+        assert!(keystore.values().next().unwrap().require_user_verification);
+
+        let rotated = upsert_key(&mut keystore, agent_key("cipher", &generate_key()));
+        assert_eq!(rotated.status, KeySyncStatus::Updated);
+        assert_eq!(keystore.len(), 1);
+    }
+
+    #[test]
+    fn upsert_updates_certificates() {
+        let mut keystore = HashMap::new();
+        let private_key = generate_key();
+        upsert_key(&mut keystore, agent_key("cipher", &private_key));
+
+        let mut with_certificate = agent_key("cipher", &private_key);
+        with_certificate.certificates = vec![
+            certificate::tests::certificate_for(&private_key, 0, u64::MAX).to_openssh(),
+            certificate::tests::certificate_for(&generate_key(), 0, u64::MAX).to_openssh(),
+        ];
+        assert_eq!(
+            upsert_key(&mut keystore, with_certificate.clone()).status,
+            KeySyncStatus::Updated
+        );
+        assert_eq!(
+            upsert_key(&mut keystore, with_certificate).status,
+            KeySyncStatus::Unchanged
+        );
+        // The certificate of the other key is not served
+        assert_eq!(keystore.values().next().unwrap().certificates.len(), 1);
+    }
+
+    #[test]
+    fn upsert_drops_disabled_and_unparsable_keys() {
+        let mut keystore = HashMap::new();
+        upsert_key(&mut keystore, agent_key("cipher", &generate_key()));
+
+        let mut disabled = agent_key("cipher", &generate_key());
+        disabled.enabled = false;
+        assert_eq!(
+            upsert_key(&mut keystore, disabled).status,
+            KeySyncStatus::Disabled
+        );
+        assert!(keystore.is_empty());
+
+        upsert_key(&mut keystore, agent_key("cipher", &generate_key()));
+        let mut invalid = agent_key("cipher", &generate_key());
+        invalid.private_key = "not a key".to_string();
+        let result = upsert_key(&mut keystore, invalid);
+        assert_eq!(result.status, KeySyncStatus::ParsingError);
+        assert!(result.error.is_some());
+        assert!(keystore.is_empty());
+    }
+
+    #[test]
+    fn upsert_reports_keys_held_by_two_ciphers() {
+        let mut keystore = HashMap::new();
+        let private_key = generate_key();
+        upsert_key(&mut keystore, agent_key("first", &private_key));
+        upsert_key(&mut keystore, agent_key("second", &generate_key()));
+
+        // The second cipher changed to the key of the first
+        let duplicate = upsert_key(&mut keystore, agent_key("second", &private_key));
+        assert_eq!(duplicate.status, KeySyncStatus::Duplicate);
+        assert!(duplicate.error.unwrap().contains("first"));
+        assert_eq!(keystore.len(), 1);
+        assert_eq!(
+            keystore.values().next().unwrap().cipher_uuid.as_deref(),
+            Some("first")
+        );
+        assert_eq!(
+            upsert_key(&mut keystore, agent_key("first", &private_key)).status,
+            KeySyncStatus::Unchanged
+        );
+    }
+
+    fn userauth(session_id: &[u8], host_key: Option<Vec<u8>>) -> SignPurpose {
+        SignPurpose::UserAuth {
+            session_id: session_id.to_vec(),
+            username: "git".to_string(),
+            service: "ssh-connection".to_string(),
+            public_key_algorithm: "ssh-ed25519".to_string(),
+            host_key,
+        }
+    }
+
+    #[test]
+    fn restricted_keys_only_authenticate_to_the_bound_session() {
+        let host_key = generate_key();
+        let host_key_blob = host_key.public_key().to_bytes().unwrap();
+        let binds = [SessionBind {
+            host_key: host_key.public_key().key_data().clone(),
+            host_key_blob: host_key_blob.clone(),
+            session_id: b"session".to_vec(),
+            is_forwarding: false,
+        }];
+        let allowed = vec![binds[0].host_key_fingerprint()];
+
+        assert!(is_allowed_destination(
+            &allowed,
+            &userauth(b"session", None),
+            &binds
+        ));
+        assert!(is_allowed_destination(
+            &allowed,
+            &userauth(b"session", Some(host_key_blob)),
+            &binds
+        ));
+        // A bind replayed on another connection does not match the session being authenticated there
+        assert!(!is_allowed_destination(
+            &allowed,
+            &userauth(b"other session", None),
+            &binds
+        ));
+        let other_host_key = generate_key().public_key().to_bytes().unwrap();
+        assert!(!is_allowed_destination(
+            &allowed,
+            &userauth(b"session", Some(other_host_key)),
+            &binds
+        ));
+        let sshsig = SignPurpose::SshSig {
+            namespace: "git".to_string(),
+            hash_algorithm: "sha512".to_string(),
+        };
+        assert!(!is_allowed_destination(&allowed, &sshsig, &binds));
+        assert!(!is_allowed_destination(
+            &allowed,
+            &userauth(b"session", None),
+            &[]
+        ));
+    }
+}
@@ -18,7 +18,7 @@ use windows::Win32::{Foundation::HANDLE, System::Pipes::GetNamedPipeClientProces
 
 use crate::ssh_agent::peerinfo::{self, models::PeerInfo};
 
-const PIPE_NAME: &str = r"\\.\pipe\openssh-ssh-agent";
+pub const PIPE_NAME: &str = r"\\.\pipe\openssh-ssh-agent";
 
 #[pin_project::pin_project]
 pub struct NamedPipeServerStream {
@@ -26,23 +26,23 @@ pub struct NamedPipeServerStream {
 }
 
 impl NamedPipeServerStream {
-    pub fn new(cancellation_token: CancellationToken, is_running: Arc<AtomicBool>) -> Self {
+    /// Fails if another process, such as the system's openssh agent service, already serves the pipe
+    pub fn new(
+        pipe_name: String,
+        cancellation_token: CancellationToken,
+        is_running: Arc<AtomicBool>,
+    ) -> io::Result<Self> {
+        println!(
+            "[SSH Agent Native Module] Creating named pipe server on {}",
+            pipe_name
+        );
+        // Creating the first instance fails if the pipe exists, so no other server can take it in between
+        let mut listener = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&pipe_name)?;
+
         let (tx, rx) = tokio::sync::mpsc::channel(16);
         tokio::spawn(async move {
-            println!(
-                "[SSH Agent Native Module] Creating named pipe server on {}",
-                PIPE_NAME
-            );
-            let mut listener = match ServerOptions::new().create(PIPE_NAME) {
-                Ok(pipe) => pipe,
-                Err(err) => {
-                    println!("[SSH Agent Native Module] Encountered an error creating the first pipe. The system's openssh service must likely be disabled");
-                    println!("[SSH Agent Natvie Module] error: {}", err);
-                    cancellation_token.cancel();
-                    is_running.store(false, Ordering::Relaxed);
-                    return;
-                }
-            };
             loop {
                 println!("[SSH Agent Native Module] Waiting for connection");
                 select! {
@@ -72,7 +72,7 @@ impl NamedPipeServerStream {
 
                         tx.send((listener, peer_info)).await.unwrap();
 
-                        listener = match ServerOptions::new().create(PIPE_NAME) {
+                        listener = match ServerOptions::new().create(&pipe_name) {
                             Ok(pipe) => pipe,
                             Err(err) => {
                                 println!("[SSH Agent Native Module] Encountered an error creating a new pipe {}", err);
@@ -85,7 +85,7 @@ impl NamedPipeServerStream {
                 }
             }
         });
-        Self { rx }
+        Ok(Self { rx })
     }
 }
 
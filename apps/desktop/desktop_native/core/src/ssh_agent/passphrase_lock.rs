@@ -0,0 +1,158 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+/// Delay added after each failed unlock, multiplied by the number of failures in a row like OpenSSH does
+const FAILED_UNLOCK_DELAY: Duration = Duration::from_millis(100);
+const MAX_FAILED_UNLOCK_DELAY: Duration = Duration::from_secs(10);
+
+/**
+* Lock state set by agent clients through `ssh-add -x` / `ssh-add -X`.
+* Only an argon2 hash of the passphrase is kept in memory while the agent is locked. Unlock attempts are handled one
+* at a time and slowed down after failures, so the passphrase cannot be guessed quickly over many connections.
+*/
+#[derive(Clone, Default)]
+pub struct PassphraseLock {
+    passphrase_hash: Arc<Mutex<Option<String>>>,
+    /// Held for the whole unlock attempt, including the delay after a failure
+    failed_unlocks: Arc<tokio::sync::Mutex<u32>>,
+}
+
+impl PassphraseLock {
+    /// Locks the agent, fails if it is already locked.
+    pub async fn lock(&self, passphrase: &[u8]) -> bool {
+        if self.is_locked() {
+            return false;
+        }
+
+        let passphrase = passphrase.to_vec();
+        let hash = tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(&passphrase, &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(e) => {
+                println!(
+                    "[SSH Agent Native Module] Could not hash lock passphrase: {}",
+                    e
+                );
+                return false;
+            }
+        };
+
+        // Another connection may have locked the agent while the passphrase was hashed
+        let mut passphrase_hash = self.passphrase_hash.lock().expect("Mutex is not poisoned");
+        if passphrase_hash.is_some() {
+            return false;
+        }
+        *passphrase_hash = Some(hash);
+        true
+    }
+
+    /// Unlocks the agent if the passphrase matches the one it was locked with.
+    pub async fn unlock(&self, passphrase: &[u8]) -> bool {
+        let mut failed_unlocks = self.failed_unlocks.lock().await;
+        let Some(hash) = self
+            .passphrase_hash
+            .lock()
+            .expect("Mutex is not poisoned")
+            .clone()
+        else {
+            return false;
+        };
+
+        let passphrase = passphrase.to_vec();
+        let verified_hash = hash.clone();
+        let matches =
+            tokio::task::spawn_blocking(move || match PasswordHash::new(&verified_hash) {
+                Ok(hash) => Argon2::default()
+                    .verify_password(&passphrase, &hash)
+                    .is_ok(),
+                Err(_) => false,
+            })
+            .await
+            .unwrap_or(false);
+
+        if !matches {
+            *failed_unlocks += 1;
+            let delay = FAILED_UNLOCK_DELAY
+                .saturating_mul(*failed_unlocks)
+                .min(MAX_FAILED_UNLOCK_DELAY);
+            tokio::time::sleep(delay).await;
+            return false;
+        }
+
+        *failed_unlocks = 0;
+        let mut passphrase_hash = self.passphrase_hash.lock().expect("Mutex is not poisoned");
+        if passphrase_hash.as_ref() == Some(&hash) {
+            *passphrase_hash = None;
+        }
+        true
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.passphrase_hash
+            .lock()
+            .expect("Mutex is not poisoned")
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unlock_requires_same_passphrase() {
+        let lock = PassphraseLock::default();
+        assert!(lock.lock(b"passphrase").await);
+        assert!(lock.is_locked());
+        assert!(!lock.lock(b"passphrase").await);
+        assert!(!lock.unlock(b"wrong passphrase").await);
+        assert!(lock.is_locked());
+        assert!(lock.unlock(b"passphrase").await);
+        assert!(!lock.is_locked());
+    }
+
+    #[tokio::test]
+    async fn unlock_fails_when_not_locked() {
+        let lock = PassphraseLock::default();
+        assert!(!lock.unlock(b"passphrase").await);
+    }
+
+    #[tokio::test]
+    async fn failed_unlocks_are_delayed_increasingly() {
+        let lock = PassphraseLock::default();
+        assert!(lock.lock(b"passphrase").await);
+
+        let start = Instant::now();
+        assert!(!lock.unlock(b"wrong passphrase").await);
+        assert!(start.elapsed() >= FAILED_UNLOCK_DELAY);
+
+        // Concurrent attempts wait for each other, each one waiting longer than the previous
+        let start = Instant::now();
+        let attempts = [lock.clone(), lock.clone()]
+            .map(|lock| tokio::spawn(async move { lock.unlock(b"wrong passphrase").await }));
+        for attempt in attempts {
+            assert!(!attempt.await.unwrap());
+        }
+        assert!(start.elapsed() >= FAILED_UNLOCK_DELAY * 5);
+
+        assert!(lock.unlock(b"passphrase").await);
+        assert!(!lock.is_locked());
+    }
+}
@@ -12,10 +12,18 @@ pub fn get_peer_info(peer_pid: u32) -> Result<PeerInfo, String> {
             }
         };
 
+        let command_line = process
+            .cmd()
+            .iter()
+            .filter_map(|arg| arg.to_str().map(|arg| arg.to_string()))
+            .collect();
+
         return Ok(PeerInfo::new(
             peer_pid,
             process.pid().as_u32(),
             peer_process_name,
+            command_line,
+            process.exe().map(|path| path.to_string_lossy().to_string()),
         ));
     }
 
@@ -7,14 +7,25 @@ pub struct PeerInfo {
     uid: u32,
     pid: u32,
     process_name: String,
+    command_line: Vec<String>,
+    /// Path of the executable, if the OS reports it
+    executable: Option<String>,
 }
 
 impl PeerInfo {
-    pub fn new(uid: u32, pid: u32, process_name: String) -> Self {
+    pub fn new(
+        uid: u32,
+        pid: u32,
+        process_name: String,
+        command_line: Vec<String>,
+        executable: Option<String>,
+    ) -> Self {
         Self {
             uid,
             pid,
             process_name,
+            command_line,
+            executable,
         }
     }
 
@@ -29,4 +40,13 @@ impl PeerInfo {
     pub fn process_name(&self) -> &str {
         &self.process_name
     }
+
+    pub fn command_line(&self) -> &[String] {
+        &self.command_line
+    }
+
+    /// Identifies the program across its processes, the executable path or the process name if it is unknown
+    pub fn executable(&self) -> &str {
+        self.executable.as_deref().unwrap_or(&self.process_name)
+    }
 }
@@ -0,0 +1,104 @@
+use super::sign_purpose::SignPurpose;
+
+/**
+* Restricts what a key may be used for, based on the decoded data of a sign request.
+* Keys without a policy can be used for anything the user confirms.
+*/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyUsagePolicy {
+    /// Whether the key may be used to authenticate to servers
+    pub allow_authentication: bool,
+    /// SSHSIG namespaces (e.g. `git` or `file`) the key may sign for, SSHSIG signing is not allowed if empty
+    pub allowed_namespaces: Vec<String>,
+    /// Whether the key may decrypt age files encrypted to it
+    pub allow_decryption: bool,
+    /// Whether the key may be used as a CA to sign certificates
+    pub allow_certificate_signing: bool,
+}
+
+impl KeyUsagePolicy {
+    /// Returns the reason the request is denied, if it is not allowed by the policy
+    pub fn check(&self, purpose: &SignPurpose) -> Result<(), String> {
+        match purpose {
+            SignPurpose::UserAuth { .. } if self.allow_authentication => Ok(()),
+            SignPurpose::UserAuth { .. } => {
+                Err("key is not allowed to authenticate to servers".to_string())
+            }
+            SignPurpose::SshSig { namespace, .. }
+                if self.allowed_namespaces.contains(namespace) =>
+            {
+                Ok(())
+            }
+            SignPurpose::SshSig { namespace, .. } => Err(format!(
+                "key is not allowed to sign for namespace {}",
+                namespace
+            )),
+            SignPurpose::AgeDecryption if self.allow_decryption => Ok(()),
+            SignPurpose::AgeDecryption => Err("key is not allowed to decrypt files".to_string()),
+            SignPurpose::Certificate { .. } if self.allow_certificate_signing => Ok(()),
+            SignPurpose::Certificate { .. } => {
+                Err("key is not allowed to sign certificates".to_string())
+            }
+            SignPurpose::Unknown => {
+                Err("key is not allowed to sign data of an unknown format".to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn userauth() -> SignPurpose {
+        SignPurpose::UserAuth {
+            session_id: Vec::new(),
+            username: "git".to_string(),
+            service: "ssh-connection".to_string(),
+            public_key_algorithm: "ssh-ed25519".to_string(),
+            host_key: None,
+        }
+    }
+
+    fn sshsig(namespace: &str) -> SignPurpose {
+        SignPurpose::SshSig {
+            namespace: namespace.to_string(),
+            hash_algorithm: "sha512".to_string(),
+        }
+    }
+
+    #[test]
+    fn auth_only() {
+        let policy = KeyUsagePolicy {
+            allow_authentication: true,
+            allowed_namespaces: Vec::new(),
+            allow_decryption: false,
+            allow_certificate_signing: false,
+        };
+        assert!(policy.check(&userauth()).is_ok());
+        assert!(policy.check(&sshsig("git")).is_err());
+        assert!(policy.check(&SignPurpose::Unknown).is_err());
+        assert!(policy.check(&SignPurpose::AgeDecryption).is_err());
+    }
+
+    #[test]
+    fn sign_only_with_namespaces() {
+        let policy = KeyUsagePolicy {
+            allow_authentication: false,
+            allowed_namespaces: vec!["git".to_string()],
+            allow_decryption: true,
+            allow_certificate_signing: false,
+        };
+        assert!(policy.check(&userauth()).is_err());
+        assert!(policy.check(&sshsig("git")).is_ok());
+        assert!(policy.check(&sshsig("file")).is_err());
+        assert!(policy.check(&SignPurpose::AgeDecryption).is_ok());
+        assert!(policy
+            .check(&SignPurpose::Certificate {
+                key_id: "alice".to_string(),
+                principals: vec!["alice".to_string()],
+                is_host_certificate: false,
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use aes::cipher::{block_padding::NoPadding, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use ssh_encoding::{Decode, Encode};
+use ssh_key::{
+    private::{Ed25519Keypair, Ed25519PrivateKey, KeypairData, RsaKeypair, RsaPrivateKey},
+    public::KeyData,
+    Mpint, PrivateKey, PublicKey,
+};
+
+use super::importer::SshKeyImportError;
+
+/// Common prefix of the headers of all PPK versions
+pub const PPK_HEADER_PREFIX: &str = "PuTTY-User-Key-File-";
+pub const PPK_V3_HEADER: &str = "PuTTY-User-Key-File-3";
+const ENCRYPTION_NONE: &str = "none";
+const ENCRYPTION_AES256_CBC: &str = "aes256-cbc";
+const AES_BLOCK_SIZE: usize = 16;
+const LINE_LENGTH: usize = 64;
+
+const ARGON2_PARALLELISM: u32 = 1;
+const ARGON2_SALT_LENGTH: usize = 16;
+
+/// Upper bounds on the Argon2 parameters of imported files, so a crafted file cannot exhaust memory or CPU
+const ARGON2_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const ARGON2_MAX_PASSES: u32 = 1000;
+const ARGON2_MAX_PARALLELISM: u32 = 64;
+
+/// Argon2id work factors for encrypting new keys
+#[derive(Clone, Copy, Debug)]
+pub struct Argon2Cost {
+    /// Memory in KiB
+    pub memory: u32,
+    pub passes: u32,
+}
+
+impl Argon2Cost {
+    /// Parameters used by puttygen for new keys
+    pub const DEFAULT: Argon2Cost = Argon2Cost {
+        memory: 8192,
+        passes: 21,
+    };
+}
+
+/// AES-256 key, IV and MAC key derived from the passphrase
+struct DerivedKeys {
+    cipher_key: [u8; 32],
+    iv: [u8; AES_BLOCK_SIZE],
+    mac_key: Vec<u8>,
+}
+
+struct Argon2Parameters {
+    algorithm: argon2::Algorithm,
+    memory: u32,
+    passes: u32,
+    parallelism: u32,
+    salt: Vec<u8>,
+}
+
+/**
+* Encodes a key as a PuTTY PPK version 3 file. With a passphrase, the private part is encrypted with AES-256-CBC
+* under a key derived with Argon2id; without one it is stored in the clear. Either way, the file is authenticated
+* with HMAC-SHA-256.
+*/
+pub fn encode(
+    private_key: &PrivateKey,
+    passphrase: &str,
+    cost: Argon2Cost,
+) -> Result<String, anyhow::Error> {
+    let algorithm = private_key.algorithm().as_str().to_string();
+    let comment = private_key.comment().to_string();
+    let public_blob = private_key.public_key().to_bytes()?;
+    let mut private_blob = private_blob(private_key.key_data())?;
+
+    let (encryption, keys, argon2_parameters) = match passphrase.is_empty() {
+        true => (ENCRYPTION_NONE, None, None),
+        false => {
+            let mut salt = vec![0u8; ARGON2_SALT_LENGTH];
+            rand::thread_rng().fill_bytes(&mut salt);
+            let parameters = Argon2Parameters {
+                algorithm: argon2::Algorithm::Argon2id,
+                memory: cost.memory,
+                passes: cost.passes,
+                parallelism: ARGON2_PARALLELISM,
+                salt,
+            };
+            let keys = derive_keys(passphrase, &parameters)?;
+
+            // Random padding up to the cipher block size
+            let padded_length = private_blob.len().div_ceil(AES_BLOCK_SIZE) * AES_BLOCK_SIZE;
+            let mut padding = vec![0u8; padded_length - private_blob.len()];
+            rand::thread_rng().fill_bytes(&mut padding);
+            private_blob.extend_from_slice(&padding);
+
+            (ENCRYPTION_AES256_CBC, Some(keys), Some(parameters))
+        }
+    };
+
+    let mac_key = keys
+        .as_ref()
+        .map(|keys| keys.mac_key.as_slice())
+        .unwrap_or_default();
+    let mac = compute_mac(
+        mac_key,
+        &algorithm,
+        encryption,
+        &comment,
+        &public_blob,
+        &private_blob,
+    )?;
+    let stored_private_blob = match &keys {
+        Some(keys) => cbc::Encryptor::<aes::Aes256>::new(&keys.cipher_key.into(), &keys.iv.into())
+            .encrypt_padded_vec_mut::<NoPadding>(&private_blob),
+        None => private_blob.clone(),
+    };
+    private_blob.fill(0);
+
+    let mut encoded = format!(
+        "{}: {}\nEncryption: {}\nComment: {}\n",
+        PPK_V3_HEADER, algorithm, encryption, comment
+    );
+    push_lines(&mut encoded, "Public-Lines", &public_blob);
+    if let Some(parameters) = argon2_parameters {
+        encoded.push_str(&format!(
+            "Key-Derivation: Argon2id\nArgon2-Memory: {}\nArgon2-Passes: {}\nArgon2-Parallelism: {}\nArgon2-Salt: {}\n",
+            parameters.memory,
+            parameters.passes,
+            parameters.parallelism,
+            to_hex(&parameters.salt)
+        ));
+    }
+    push_lines(&mut encoded, "Private-Lines", &stored_private_blob);
+    encoded.push_str(&format!("Private-MAC: {}\n", to_hex(&mac)));
+    Ok(encoded)
+}
+
+/// Decodes a PuTTY PPK version 3 file, verifying its MAC and decrypting it with the passphrase if it is encrypted.
+pub fn decode(encoded: &str, passphrase: &str) -> Result<PrivateKey, SshKeyImportError> {
+    let mut lines = encoded.lines().map(|line| line.trim_end_matches('\r'));
+    let algorithm = header(&mut lines, PPK_V3_HEADER)?;
+    let encryption = header(&mut lines, "Encryption")?;
+    let comment = header(&mut lines, "Comment")?;
+    let public_blob = read_lines(&mut lines, "Public-Lines")?;
+
+    let keys = match encryption {
+        ENCRYPTION_NONE => None,
+        ENCRYPTION_AES256_CBC => {
+            let algorithm = match header(&mut lines, "Key-Derivation")? {
+                "Argon2id" => argon2::Algorithm::Argon2id,
+                "Argon2i" => argon2::Algorithm::Argon2i,
+                "Argon2d" => argon2::Algorithm::Argon2d,
+                _ => return Err(SshKeyImportError::ParsingError),
+            };
+            let parameters = Argon2Parameters {
+                algorithm,
+                memory: parse_number(header(&mut lines, "Argon2-Memory")?)?,
+                passes: parse_number(header(&mut lines, "Argon2-Passes")?)?,
+                parallelism: parse_number(header(&mut lines, "Argon2-Parallelism")?)?,
+                salt: from_hex(header(&mut lines, "Argon2-Salt")?)?,
+            };
+            if parameters.memory > ARGON2_MAX_MEMORY_KIB
+                || parameters.passes > ARGON2_MAX_PASSES
+                || parameters.parallelism > ARGON2_MAX_PARALLELISM
+            {
+                return Err(SshKeyImportError::ParsingError);
+            }
+            if passphrase.is_empty() {
+                return Err(SshKeyImportError::PasswordRequired);
+            }
+            Some(
+                derive_keys(passphrase, &parameters)
+                    .map_err(|_| SshKeyImportError::ParsingError)?,
+            )
+        }
+        _ => return Err(SshKeyImportError::ParsingError),
+    };
+
+    let stored_private_blob = read_lines(&mut lines, "Private-Lines")?;
+    let mac = from_hex(header(&mut lines, "Private-MAC")?)?;
+
+    let private_blob = match &keys {
+        Some(keys) => cbc::Decryptor::<aes::Aes256>::new(&keys.cipher_key.into(), &keys.iv.into())
+            .decrypt_padded_vec_mut::<NoPadding>(&stored_private_blob)
+            .map_err(|_| SshKeyImportError::ParsingError)?,
+        None => stored_private_blob,
+    };
+
+    let mac_key = keys
+        .as_ref()
+        .map(|keys| keys.mac_key.as_slice())
+        .unwrap_or_default();
+    let hmac = mac_hmac(
+        mac_key,
+        algorithm,
+        encryption,
+        comment,
+        &public_blob,
+        &private_blob,
+    )
+    .map_err(|_| SshKeyImportError::ParsingError)?;
+    if hmac.verify_slice(&mac).is_err() {
+        // A wrong passphrase yields a wrong MAC key, so the MAC can not be told apart from a corrupted file
+        return Err(match keys {
+            Some(_) => SshKeyImportError::WrongPassword,
+            None => SshKeyImportError::ParsingError,
+        });
+    }
+
+    let public_key =
+        PublicKey::from_bytes(&public_blob).map_err(|_| SshKeyImportError::ParsingError)?;
+    if public_key.algorithm().as_str() != algorithm {
+        return Err(SshKeyImportError::ParsingError);
+    }
+    let mut private_blob = private_blob;
+    let keypair = keypair(public_key.key_data(), &private_blob);
+    private_blob.fill(0);
+    PrivateKey::new(keypair?, comment).map_err(|_| SshKeyImportError::ParsingError)
+}
+
+/// The private part of a key in PuTTY's layout, which differs from OpenSSH's
+fn private_blob(key_data: &KeypairData) -> Result<Vec<u8>, anyhow::Error> {
+    let mut blob = Vec::new();
+    match key_data {
+        KeypairData::Ed25519(keypair) => {
+            // PuTTY stores the seed as a little-endian integer, which is the seed itself
+            let mut seed = keypair.private.to_bytes();
+            seed.as_slice().encode(&mut blob)?;
+            seed.fill(0);
+        }
+        KeypairData::Rsa(keypair) => {
+            keypair.private.d.encode(&mut blob)?;
+            keypair.private.p.encode(&mut blob)?;
+            keypair.private.q.encode(&mut blob)?;
+            keypair.private.iqmp.encode(&mut blob)?;
+        }
+        _ => return Err(anyhow::anyhow!("Unsupported key type")),
+    }
+    Ok(blob)
+}
+
+fn keypair(public: &KeyData, private_blob: &[u8]) -> Result<KeypairData, SshKeyImportError> {
+    let mut reader = private_blob;
+    match public {
+        KeyData::Ed25519(public) => {
+            let mut seed_bytes =
+                Vec::<u8>::decode(&mut reader).map_err(|_| SshKeyImportError::ParsingError)?;
+            // Shorter strings are integers with their most significant zero bytes left out
+            if seed_bytes.len() > 32 {
+                return Err(SshKeyImportError::ParsingError);
+            }
+            let mut seed = [0u8; 32];
+            seed[..seed_bytes.len()].copy_from_slice(&seed_bytes);
+            seed_bytes.fill(0);
+            let keypair = Ed25519Keypair::from(Ed25519PrivateKey::from_bytes(&seed));
+            seed.fill(0);
+            if keypair.public != *public {
+                return Err(SshKeyImportError::ParsingError);
+            }
+            Ok(KeypairData::Ed25519(keypair))
+        }
+        KeyData::Rsa(public) => {
+            let mut mpint =
+                || Mpint::decode(&mut reader).map_err(|_| SshKeyImportError::ParsingError);
+            let d = mpint()?;
+            let p = mpint()?;
+            let q = mpint()?;
+            let iqmp = mpint()?;
+            Ok(KeypairData::Rsa(RsaKeypair {
+                public: public.clone(),
+                private: RsaPrivateKey { d, iqmp, p, q },
+            }))
+        }
+        _ => Err(SshKeyImportError::UnsupportedKeyType),
+    }
+}
+
+fn derive_keys(
+    passphrase: &str,
+    parameters: &Argon2Parameters,
+) -> Result<DerivedKeys, anyhow::Error> {
+    let params = argon2::Params::new(
+        parameters.memory,
+        parameters.passes,
+        parameters.parallelism,
+        Some(80),
+    )
+    .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+    let mut output = [0u8; 80];
+    argon2::Argon2::new(parameters.algorithm, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &parameters.salt, &mut output)
+        .map_err(|e| anyhow::anyhow!("Argon2 hashing failed: {}", e))?;
+
+    let keys = DerivedKeys {
+        cipher_key: output[..32].try_into()?,
+        iv: output[32..48].try_into()?,
+        mac_key: output[48..].to_vec(),
+    };
+    output.fill(0);
+    Ok(keys)
+}
+
+fn mac_hmac(
+    mac_key: &[u8],
+    algorithm: &str,
+    encryption: &str,
+    comment: &str,
+    public_blob: &[u8],
+    private_blob: &[u8],
+) -> Result<Hmac<Sha256>, anyhow::Error> {
+    let mut data = Vec::new();
+    algorithm.encode(&mut data)?;
+    encryption.encode(&mut data)?;
+    comment.encode(&mut data)?;
+    public_blob.encode(&mut data)?;
+    private_blob.encode(&mut data)?;
+
+    let mut hmac = Hmac::<Sha256>::new_from_slice(mac_key)?;
+    hmac.update(&data);
+    data.fill(0);
+    Ok(hmac)
+}
+
+fn compute_mac(
+    mac_key: &[u8],
+    algorithm: &str,
+    encryption: &str,
+    comment: &str,
+    public_blob: &[u8],
+    private_blob: &[u8],
+) -> Result<Vec<u8>, anyhow::Error> {
+    Ok(mac_hmac(
+        mac_key,
+        algorithm,
+        encryption,
+        comment,
+        public_blob,
+        private_blob,
+    )?
+    .finalize()
+    .into_bytes()
+    .to_vec())
+}
+
+fn header<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+    name: &str,
+) -> Result<&'a str, SshKeyImportError> {
+    match lines.next().and_then(|line| line.split_once(": ")) {
+        Some((key, value)) if key == name => Ok(value),
+        _ => Err(SshKeyImportError::ParsingError),
+    }
+}
+
+fn read_lines<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+    name: &str,
+) -> Result<Vec<u8>, SshKeyImportError> {
+    let count: usize = parse_number(header(lines, name)?)?;
+    let mut encoded = String::new();
+    for _ in 0..count {
+        encoded.push_str(lines.next().ok_or(SshKeyImportError::ParsingError)?);
+    }
+    STANDARD
+        .decode(encoded)
+        .map_err(|_| SshKeyImportError::ParsingError)
+}
+
+fn push_lines(encoded: &mut String, name: &str, data: &[u8]) {
+    let base64 = STANDARD.encode(data);
+    let lines = base64
+        .as_bytes()
+        .chunks(LINE_LENGTH)
+        .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+        .collect::<Vec<_>>();
+    encoded.push_str(&format!("{}: {}\n", name, lines.len()));
+    for line in lines {
+        encoded.push_str(&line);
+        encoded.push('\n');
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, SshKeyImportError> {
+    value.parse().map_err(|_| SshKeyImportError::ParsingError)
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, SshKeyImportError> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(SshKeyImportError::ParsingError)
+        })
+        .collect()
+}
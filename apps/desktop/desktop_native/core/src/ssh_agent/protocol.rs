@@ -0,0 +1,441 @@
+use rsa::signature::Verifier;
+use ssh_encoding::{Decode, Encode, Reader};
+use ssh_key::{
+    private::{KeypairData, PrivateKey},
+    public::KeyData,
+    HashAlg, Signature,
+};
+
+/// Largest message accepted from a client, matching OpenSSH's `AGENT_MAX_LEN`.
+pub const MAX_MESSAGE_LENGTH: usize = 256 * 1024;
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENTC_ADD_IDENTITY: u8 = 17;
+const SSH_AGENTC_REMOVE_IDENTITY: u8 = 18;
+const SSH_AGENTC_REMOVE_ALL_IDENTITIES: u8 = 19;
+const SSH_AGENTC_LOCK: u8 = 22;
+const SSH_AGENTC_UNLOCK: u8 = 23;
+const SSH_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+const SSH_AGENTC_EXTENSION: u8 = 27;
+const SSH_AGENT_EXTENSION_FAILURE: u8 = 28;
+
+const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+const SSH_AGENT_CONSTRAIN_CONFIRM: u8 = 2;
+const SSH_AGENT_CONSTRAIN_EXTENSION: u8 = 255;
+
+pub const SSH_AGENT_RSA_SHA2_256: u32 = 2;
+pub const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+pub const SESSION_BIND_EXTENSION: &str = "session-bind@openssh.com";
+
+/// Constraints an agent client can attach to a key when adding it (`ssh-add -t` / `ssh-add -c`).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyConstraints {
+    /// Number of seconds after which the key is removed from the agent
+    pub lifetime: Option<u32>,
+    /// Whether every use of the key has to be confirmed by the user
+    pub confirm: bool,
+}
+
+/**
+* Binding of an agent connection to an ssh session, sent by OpenSSH 8.9+ clients after key exchange.
+* The destination host proves ownership of its host key by signing the session identifier.
+*/
+#[derive(Clone, Debug)]
+pub struct SessionBind {
+    pub host_key: KeyData,
+    pub host_key_blob: Vec<u8>,
+    pub session_id: Vec<u8>,
+    /// Whether the agent connection is forwarded to the bound host, rather than used to authenticate to it
+    pub is_forwarding: bool,
+}
+
+impl SessionBind {
+    /// Parses the contents of a `session-bind@openssh.com` extension message and verifies the host key signature.
+    pub fn parse(contents: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = contents;
+        let host_key_blob = Vec::decode(&mut reader)?;
+        let session_id = Vec::decode(&mut reader)?;
+        let signature_blob = Vec::decode(&mut reader)?;
+        let is_forwarding = u8::decode(&mut reader)? != 0;
+
+        let host_key = KeyData::decode(&mut host_key_blob.as_slice())?;
+        let signature = Signature::decode(&mut signature_blob.as_slice())?;
+        host_key
+            .verify(&session_id, &signature)
+            .map_err(|e| anyhow::anyhow!("Invalid session bind signature: {}", e))?;
+
+        Ok(SessionBind {
+            host_key,
+            host_key_blob,
+            session_id,
+            is_forwarding,
+        })
+    }
+
+    pub fn host_key_fingerprint(&self) -> String {
+        self.host_key.fingerprint(HashAlg::Sha256).to_string()
+    }
+}
+
+pub enum Request {
+    RequestIdentities,
+    SignRequest {
+        key_blob: Vec<u8>,
+        data: Vec<u8>,
+        flags: u32,
+    },
+    AddIdentity {
+        private_key: Box<PrivateKey>,
+        constraints: KeyConstraints,
+    },
+    RemoveIdentity {
+        key_blob: Vec<u8>,
+    },
+    RemoveAllIdentities,
+    Lock {
+        passphrase: Vec<u8>,
+    },
+    Unlock {
+        passphrase: Vec<u8>,
+    },
+    Extension {
+        name: String,
+        contents: Vec<u8>,
+    },
+    Unknown(u8),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Success,
+    Failure,
+    ExtensionFailure,
+    Identities(Vec<(Vec<u8>, String)>),
+    SignResponse(Vec<u8>),
+}
+
+impl Request {
+    pub fn parse(message: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = message;
+        let message_type = u8::decode(&mut reader)?;
+        let request = match message_type {
+            SSH_AGENTC_REQUEST_IDENTITIES => Request::RequestIdentities,
+            SSH_AGENTC_SIGN_REQUEST => Request::SignRequest {
+                key_blob: Vec::decode(&mut reader)?,
+                data: Vec::decode(&mut reader)?,
+                flags: u32::decode(&mut reader)?,
+            },
+            SSH_AGENTC_ADD_IDENTITY | SSH_AGENTC_ADD_ID_CONSTRAINED => {
+                let key_data = KeypairData::decode(&mut reader)?;
+                let comment = String::decode(&mut reader)?;
+                let constraints = if message_type == SSH_AGENTC_ADD_ID_CONSTRAINED {
+                    parse_constraints(&mut reader)?
+                } else {
+                    KeyConstraints::default()
+                };
+                Request::AddIdentity {
+                    private_key: Box::new(PrivateKey::new(key_data, comment)?),
+                    constraints,
+                }
+            }
+            SSH_AGENTC_REMOVE_IDENTITY => Request::RemoveIdentity {
+                key_blob: Vec::decode(&mut reader)?,
+            },
+            SSH_AGENTC_REMOVE_ALL_IDENTITIES => Request::RemoveAllIdentities,
+            SSH_AGENTC_LOCK => Request::Lock {
+                passphrase: Vec::decode(&mut reader)?,
+            },
+            SSH_AGENTC_UNLOCK => Request::Unlock {
+                passphrase: Vec::decode(&mut reader)?,
+            },
+            SSH_AGENTC_EXTENSION => Request::Extension {
+                name: String::decode(&mut reader)?,
+                contents: reader.to_vec(),
+            },
+            other => Request::Unknown(other),
+        };
+        Ok(request)
+    }
+
+    /// Serializes the request including the length prefix, for sending it to another agent.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let mut body = Vec::new();
+        match self {
+            Request::RequestIdentities => SSH_AGENTC_REQUEST_IDENTITIES.encode(&mut body)?,
+            Request::SignRequest {
+                key_blob,
+                data,
+                flags,
+            } => {
+                SSH_AGENTC_SIGN_REQUEST.encode(&mut body)?;
+                key_blob.encode(&mut body)?;
+                data.encode(&mut body)?;
+                flags.encode(&mut body)?;
+            }
+            Request::AddIdentity {
+                private_key,
+                constraints,
+            } => {
+                let constrained = *constraints != KeyConstraints::default();
+                match constrained {
+                    true => SSH_AGENTC_ADD_ID_CONSTRAINED.encode(&mut body)?,
+                    false => SSH_AGENTC_ADD_IDENTITY.encode(&mut body)?,
+                }
+                private_key.key_data().encode(&mut body)?;
+                private_key.comment().encode(&mut body)?;
+                if let Some(lifetime) = constraints.lifetime {
+                    SSH_AGENT_CONSTRAIN_LIFETIME.encode(&mut body)?;
+                    lifetime.encode(&mut body)?;
+                }
+                if constraints.confirm {
+                    SSH_AGENT_CONSTRAIN_CONFIRM.encode(&mut body)?;
+                }
+            }
+            Request::RemoveIdentity { key_blob } => {
+                SSH_AGENTC_REMOVE_IDENTITY.encode(&mut body)?;
+                key_blob.encode(&mut body)?;
+            }
+            Request::RemoveAllIdentities => SSH_AGENTC_REMOVE_ALL_IDENTITIES.encode(&mut body)?,
+            Request::Lock { passphrase } => {
+                SSH_AGENTC_LOCK.encode(&mut body)?;
+                passphrase.encode(&mut body)?;
+            }
+            Request::Unlock { passphrase } => {
+                SSH_AGENTC_UNLOCK.encode(&mut body)?;
+                passphrase.encode(&mut body)?;
+            }
+            Request::Extension { name, contents } => {
+                SSH_AGENTC_EXTENSION.encode(&mut body)?;
+                name.encode(&mut body)?;
+                body.extend_from_slice(contents);
+            }
+            Request::Unknown(message_type) => message_type.encode(&mut body)?,
+        }
+        frame(body)
+    }
+}
+
+fn parse_constraints(reader: &mut &[u8]) -> Result<KeyConstraints, anyhow::Error> {
+    let mut constraints = KeyConstraints::default();
+    while !reader.is_finished() {
+        match u8::decode(reader)? {
+            SSH_AGENT_CONSTRAIN_LIFETIME => {
+                constraints.lifetime = Some(u32::decode(reader)?);
+            }
+            SSH_AGENT_CONSTRAIN_CONFIRM => {
+                constraints.confirm = true;
+            }
+            SSH_AGENT_CONSTRAIN_EXTENSION => {
+                let name = String::decode(reader)?;
+                return Err(anyhow::anyhow!(
+                    "Unsupported key constraint extension: {}",
+                    name
+                ));
+            }
+            other => {
+                return Err(anyhow::anyhow!("Unknown key constraint: {}", other));
+            }
+        }
+    }
+    Ok(constraints)
+}
+
+impl Response {
+    /// Serializes the response including the length prefix used for framing on the socket.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let mut body = Vec::new();
+        match self {
+            Response::Success => SSH_AGENT_SUCCESS.encode(&mut body)?,
+            Response::Failure => SSH_AGENT_FAILURE.encode(&mut body)?,
+            Response::ExtensionFailure => SSH_AGENT_EXTENSION_FAILURE.encode(&mut body)?,
+            Response::Identities(identities) => {
+                SSH_AGENT_IDENTITIES_ANSWER.encode(&mut body)?;
+                (identities.len() as u32).encode(&mut body)?;
+                for (key_blob, comment) in identities {
+                    key_blob.encode(&mut body)?;
+                    comment.encode(&mut body)?;
+                }
+            }
+            Response::SignResponse(signature) => {
+                SSH_AGENT_SIGN_RESPONSE.encode(&mut body)?;
+                signature.encode(&mut body)?;
+            }
+        }
+
+        frame(body)
+    }
+
+    /// Parses a response received from another agent, without the length prefix.
+    pub fn parse(message: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = message;
+        let response = match u8::decode(&mut reader)? {
+            SSH_AGENT_SUCCESS => Response::Success,
+            SSH_AGENT_FAILURE => Response::Failure,
+            SSH_AGENT_EXTENSION_FAILURE => Response::ExtensionFailure,
+            SSH_AGENT_IDENTITIES_ANSWER => {
+                let count = u32::decode(&mut reader)?;
+                let mut identities = Vec::new();
+                for _ in 0..count {
+                    identities.push((Vec::decode(&mut reader)?, String::decode(&mut reader)?));
+                }
+                Response::Identities(identities)
+            }
+            SSH_AGENT_SIGN_RESPONSE => Response::SignResponse(Vec::decode(&mut reader)?),
+            other => return Err(anyhow::anyhow!("Unknown response type: {}", other)),
+        };
+        Ok(response)
+    }
+}
+
+fn frame(body: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut message = Vec::with_capacity(body.len() + 4);
+    (body.len() as u32).encode(&mut message)?;
+    message.extend_from_slice(&body);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_identity_message(message_type: u8, constraints: &[u8]) -> Vec<u8> {
+        let private_key =
+            PrivateKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted"))
+                .unwrap();
+        let mut message = vec![message_type];
+        private_key.key_data().encode(&mut message).unwrap();
+        "comment".encode(&mut message).unwrap();
+        message.extend_from_slice(constraints);
+        message
+    }
+
+    #[test]
+    fn parse_add_identity_with_constraints() {
+        let message = add_identity_message(
+            SSH_AGENTC_ADD_ID_CONSTRAINED,
+            &[
+                SSH_AGENT_CONSTRAIN_LIFETIME,
+                0,
+                0,
+                0,
+                60,
+                SSH_AGENT_CONSTRAIN_CONFIRM,
+            ],
+        );
+        match Request::parse(&message).unwrap() {
+            Request::AddIdentity {
+                private_key,
+                constraints,
+            } => {
+                assert_eq!(private_key.comment(), "comment");
+                assert_eq!(
+                    constraints,
+                    KeyConstraints {
+                        lifetime: Some(60),
+                        confirm: true,
+                    }
+                );
+            }
+            _ => panic!("Expected add identity request"),
+        }
+    }
+
+    #[test]
+    fn parse_add_identity_rejects_unknown_constraint() {
+        let message = add_identity_message(SSH_AGENTC_ADD_ID_CONSTRAINED, &[42]);
+        assert!(Request::parse(&message).is_err());
+    }
+
+    fn session_bind_contents(session_id: &[u8], signed_session_id: &[u8]) -> Vec<u8> {
+        let host_key =
+            PrivateKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted"))
+                .unwrap();
+        let signature: Signature = rsa::signature::Signer::sign(&host_key, signed_session_id);
+
+        let mut contents = Vec::new();
+        host_key
+            .public_key()
+            .to_bytes()
+            .unwrap()
+            .encode(&mut contents)
+            .unwrap();
+        session_id.encode(&mut contents).unwrap();
+        let mut signature_blob = Vec::new();
+        signature.encode(&mut signature_blob).unwrap();
+        signature_blob.encode(&mut contents).unwrap();
+        1u8.encode(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn parse_session_bind() {
+        let contents = session_bind_contents(b"session id", b"session id");
+        let session_bind = SessionBind::parse(&contents).unwrap();
+        assert!(session_bind.is_forwarding);
+        assert_eq!(session_bind.session_id, b"session id");
+        assert_eq!(
+            session_bind.host_key_fingerprint(),
+            "SHA256:oaEiIEZe8SyB9Dh+eHD/SRkUj8enzP39H/sctgzbDb8"
+        );
+    }
+
+    #[test]
+    fn parse_session_bind_rejects_invalid_signature() {
+        let contents = session_bind_contents(b"session id", b"other session id");
+        assert!(SessionBind::parse(&contents).is_err());
+    }
+
+    #[test]
+    fn parse_lock() {
+        let mut message = vec![SSH_AGENTC_LOCK];
+        "passphrase".encode(&mut message).unwrap();
+        match Request::parse(&message).unwrap() {
+            Request::Lock { passphrase } => assert_eq!(passphrase, b"passphrase"),
+            _ => panic!("Expected lock request"),
+        }
+    }
+
+    #[test]
+    fn sign_request_round_trip() {
+        let request = Request::SignRequest {
+            key_blob: vec![1, 2, 3],
+            data: b"data".to_vec(),
+            flags: SSH_AGENT_RSA_SHA2_512,
+        };
+        let message = request.to_bytes().unwrap();
+        match Request::parse(&message[4..]).unwrap() {
+            Request::SignRequest {
+                key_blob,
+                data,
+                flags,
+            } => {
+                assert_eq!(key_blob, vec![1, 2, 3]);
+                assert_eq!(data, b"data");
+                assert_eq!(flags, SSH_AGENT_RSA_SHA2_512);
+            }
+            _ => panic!("Expected sign request"),
+        }
+    }
+
+    #[test]
+    fn identities_answer_round_trip() {
+        let response = Response::Identities(vec![(vec![1, 2], "key".to_string())]);
+        let message = response.to_bytes().unwrap();
+        assert_eq!(Response::parse(&message[4..]).unwrap(), response);
+    }
+
+    #[test]
+    fn encode_identities_answer() {
+        let response = Response::Identities(vec![(vec![1, 2], "key".to_string())]);
+        assert_eq!(
+            response.to_bytes().unwrap(),
+            vec![0, 0, 0, 18, 12, 0, 0, 0, 1, 0, 0, 0, 2, 1, 2, 0, 0, 0, 3, b'k', b'e', b'y']
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::audit_log;
+
+/// Denied or unanswered prompts after which a program is blocked
+const MAX_FAILED_PROMPTS: usize = 3;
+/// Window in which the failed prompts are counted
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// How long requests of a blocked program are denied without prompting
+const COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+/// A program whose requests are denied without asking the user
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockedProcess {
+    /// Path of the executable, or the process name if it is unknown
+    pub executable: String,
+    pub process_name: String,
+    /// Seconds since the unix epoch at which requests are prompted for again
+    pub blocked_until: u64,
+}
+
+#[derive(Default)]
+struct ProcessState {
+    process_name: String,
+    failures: VecDeque<Instant>,
+    blocked_until: Option<Instant>,
+    /// `blocked_until` as seconds since the unix epoch, as reported to the UI
+    blocked_until_unix: u64,
+}
+
+/// Marks a prompt of an executable as open until it is dropped
+pub struct OpenPrompt {
+    open_prompts: Arc<Mutex<HashSet<String>>>,
+    executable: String,
+}
+
+impl Drop for OpenPrompt {
+    fn drop(&mut self) {
+        self.open_prompts
+            .lock()
+            .expect("Mutex is not poisoned")
+            .remove(&self.executable);
+    }
+}
+
+/**
+ * Keeps a program from flooding the user with prompts. Only one prompt per executable is shown at a time, and
+ * after `MAX_FAILED_PROMPTS` denied or unanswered prompts within `FAILURE_WINDOW`, the requests of the executable
+ * are denied without prompting until the cooldown ends or the user unblocks it.
+ */
+#[derive(Clone)]
+pub struct RateLimiter {
+    processes: Arc<Mutex<HashMap<String, ProcessState>>>,
+    /// Executables with a prompt waiting for the user
+    open_prompts: Arc<Mutex<HashSet<String>>>,
+    max_failures: usize,
+    window: Duration,
+    cooldown: Duration,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(MAX_FAILED_PROMPTS, FAILURE_WINDOW, COOLDOWN)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(max_failures: usize, window: Duration, cooldown: Duration) -> Self {
+        RateLimiter {
+            processes: Default::default(),
+            open_prompts: Default::default(),
+            max_failures,
+            window,
+            cooldown,
+        }
+    }
+
+    fn processes(&self) -> std::sync::MutexGuard<'_, HashMap<String, ProcessState>> {
+        self.processes.lock().expect("Mutex is not poisoned")
+    }
+
+    /// Whether requests of the executable are currently denied without prompting
+    pub fn is_blocked(&self, executable: &str) -> bool {
+        self.is_blocked_at(executable, Instant::now())
+    }
+
+    fn is_blocked_at(&self, executable: &str, now: Instant) -> bool {
+        self.processes()
+            .get(executable)
+            .and_then(|state| state.blocked_until)
+            .is_some_and(|blocked_until| now < blocked_until)
+    }
+
+    /**
+     * Marks a prompt of the executable as open until the returned guard is dropped. Returns `None` while another
+     * prompt of the executable is open, so a program cannot stack up prompts over many connections.
+     */
+    pub fn open_prompt(&self, executable: &str) -> Option<OpenPrompt> {
+        let mut open_prompts = self.open_prompts.lock().expect("Mutex is not poisoned");
+        if !open_prompts.insert(executable.to_string()) {
+            return None;
+        }
+        Some(OpenPrompt {
+            open_prompts: self.open_prompts.clone(),
+            executable: executable.to_string(),
+        })
+    }
+
+    /// Counts a denied or unanswered prompt. Returns the block if the executable just got blocked.
+    pub fn record_failure(&self, executable: &str, process_name: &str) -> Option<BlockedProcess> {
+        self.record_failure_at(executable, process_name, Instant::now())
+    }
+
+    fn record_failure_at(
+        &self,
+        executable: &str,
+        process_name: &str,
+        now: Instant,
+    ) -> Option<BlockedProcess> {
+        let mut processes = self.processes();
+        let state = processes.entry(executable.to_string()).or_default();
+        state.process_name = process_name.to_string();
+        state
+            .failures
+            .retain(|failure| now.duration_since(*failure) < self.window);
+        state.failures.push_back(now);
+        if state.failures.len() < self.max_failures {
+            return None;
+        }
+
+        state.failures.clear();
+        state.blocked_until = Some(now + self.cooldown);
+        state.blocked_until_unix = audit_log::now() + self.cooldown.as_secs();
+        Some(BlockedProcess {
+            executable: executable.to_string(),
+            process_name: state.process_name.clone(),
+            blocked_until: state.blocked_until_unix,
+        })
+    }
+
+    /// Lifts the block of the executable and forgets its failed prompts. Returns whether it was blocked.
+    pub fn unblock(&self, executable: &str) -> bool {
+        let now = Instant::now();
+        self.processes()
+            .remove(executable)
+            .and_then(|state| state.blocked_until)
+            .is_some_and(|blocked_until| now < blocked_until)
+    }
+
+    pub fn blocked_processes(&self) -> Vec<BlockedProcess> {
+        let now = Instant::now();
+        self.processes()
+            .iter()
+            .filter(|(_, state)| state.blocked_until.is_some_and(|until| now < until))
+            .map(|(executable, state)| BlockedProcess {
+                executable: executable.clone(),
+                process_name: state.process_name.clone(),
+                blocked_until: state.blocked_until_unix,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIT: &str = "/usr/bin/git";
+
+    #[test]
+    fn blocks_after_repeated_failures_until_cooldown_ends() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60), Duration::from_secs(300));
+        let start = Instant::now();
+
+        assert!(limiter.record_failure_at(GIT, "git", start).is_none());
+        assert!(limiter
+            .record_failure_at(GIT, "git", start + Duration::from_secs(10))
+            .is_none());
+        let blocked = limiter
+            .record_failure_at(GIT, "git", start + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(blocked.executable, GIT);
+        assert_eq!(blocked.process_name, "git");
+
+        assert!(limiter.is_blocked_at(GIT, start + Duration::from_secs(30)));
+        assert!(!limiter.is_blocked_at("/usr/bin/ssh", start + Duration::from_secs(30)));
+        assert!(!limiter.is_blocked_at(GIT, start + Duration::from_secs(321)));
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60), Duration::from_secs(300));
+        let start = Instant::now();
+
+        for seconds in [0, 50, 100, 150] {
+            assert!(limiter
+                .record_failure_at(GIT, "git", start + Duration::from_secs(seconds))
+                .is_none());
+        }
+        assert!(!limiter.is_blocked_at(GIT, start + Duration::from_secs(150)));
+    }
+
+    #[test]
+    fn unblock_lifts_the_block() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60), Duration::from_secs(300));
+        assert!(limiter.record_failure(GIT, "git").is_some());
+        assert!(limiter.is_blocked(GIT));
+        assert_eq!(limiter.blocked_processes().len(), 1);
+
+        assert!(limiter.unblock(GIT));
+        assert!(!limiter.is_blocked(GIT));
+        assert!(limiter.blocked_processes().is_empty());
+        assert!(!limiter.unblock(GIT));
+    }
+    #[test]
+    fn one_open_prompt_per_executable() {
+        let limiter = RateLimiter::default();
+        let open_prompt = limiter.open_prompt(GIT).unwrap();
+        assert!(limiter.open_prompt(GIT).is_none());
+        assert!(limiter.open_prompt("/usr/bin/ssh").is_some());
+
+        drop(open_prompt);
+        assert!(limiter.open_prompt(GIT).is_some());
+    }
+}
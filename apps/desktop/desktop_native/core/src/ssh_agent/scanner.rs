@@ -0,0 +1,316 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ssh_key::{PrivateKey, PublicKey};
+
+use super::{
+    importer::{
+        import_key, SshKey, SshKeyImportResult, SshKeyImportStatus, OPENSSH_HEADER, PKCS1_HEADER,
+        PKCS8_ENCRYPTED_HEADER, PKCS8_UNENCRYPTED_HEADER,
+    },
+    ppk::{PPK_HEADER_PREFIX, PPK_V3_HEADER},
+};
+
+/// Private keys are small; anything larger is not read
+const MAX_KEY_FILE_SIZE: u64 = 64 * 1024;
+const PKCS1_ENCRYPTED_MARKER: &str = "Proc-Type: 4,ENCRYPTED";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyFileFormat {
+    OpenSsh,
+    Pkcs1,
+    Pkcs8,
+    Putty,
+}
+
+/// A private key file found by [`scan_ssh_directory`]
+pub struct ScannedKeyFile {
+    pub path: PathBuf,
+    pub format: KeyFileFormat,
+    /// Whether the key is protected by a passphrase, in which case it is reported as `PasswordRequired`
+    pub encrypted: bool,
+    /// OpenSSH encoded public key, from the `.pub` file next to the key or from the key file itself
+    pub public_key: Option<String>,
+    pub result: SshKeyImportResult,
+}
+
+/**
+* Finds the private key files directly inside a directory such as `~/.ssh` and imports the unencrypted ones.
+* Encrypted keys are reported as `PasswordRequired` without asking for a passphrase, so they can be imported
+* one by one later with [`import_key_file`]. Files that are not private keys (`config`, `known_hosts`, `.pub`
+* files, ...) are skipped.
+*/
+pub fn scan_ssh_directory(directory: &Path) -> Result<Vec<ScannedKeyFile>, anyhow::Error> {
+    let mut paths = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_none_or(|extension| extension != "pub"))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        let contents = match read_key_file(&path) {
+            Ok(Some(contents)) => contents,
+            Ok(None) => continue,
+            Err(e) => {
+                println!(
+                    "[SSH Agent Native Module] Skipping {}: {}",
+                    path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        let Some((format, encrypted)) = detect_format(&contents) else {
+            continue;
+        };
+        let paired_public_key = read_public_key(&path);
+        let result = match import_contents(&contents, "", encrypted, paired_public_key.as_ref()) {
+            Ok(result) => result,
+            Err(e) => {
+                println!(
+                    "[SSH Agent Native Module] Could not import {}: {}",
+                    path.display(),
+                    e
+                );
+                SshKeyImportResult {
+                    status: SshKeyImportStatus::ParsingError,
+                    ssh_key: None,
+                }
+            }
+        };
+
+        let public_key = match &result.ssh_key {
+            Some(ssh_key) => Some(ssh_key.public_key.clone()),
+            None => paired_public_key
+                .or_else(|| embedded_public_key(&contents))
+                .map(|public_key| public_key.to_string()),
+        };
+        files.push(ScannedKeyFile {
+            path,
+            format,
+            encrypted,
+            public_key,
+            result,
+        });
+    }
+    Ok(files)
+}
+
+/// Imports a single key file, taking the comment from the `.pub` file next to it if the key has none.
+pub fn import_key_file(path: &Path, password: &str) -> Result<SshKeyImportResult, anyhow::Error> {
+    let contents = read_key_file(path)?.unwrap_or_default();
+    let encrypted = detect_format(&contents).is_some_and(|(_, encrypted)| encrypted);
+    import_contents(
+        &contents,
+        password,
+        encrypted,
+        read_public_key(path).as_ref(),
+    )
+}
+
+fn import_contents(
+    contents: &str,
+    password: &str,
+    encrypted: bool,
+    paired_public_key: Option<&PublicKey>,
+) -> Result<SshKeyImportResult, anyhow::Error> {
+    // Encrypted PKCS#8 keys report a wrong password rather than a missing one. Keys in formats that cannot be
+    // imported at all are left to the importer, which reports them as unsupported.
+    if encrypted && password.is_empty() && is_importable_format(contents) {
+        return Ok(SshKeyImportResult {
+            status: SshKeyImportStatus::PasswordRequired,
+            ssh_key: None,
+        });
+    }
+
+    let mut result = import_key(contents.to_string(), password.to_string())?;
+    if let (Some(ssh_key), Some(paired_public_key)) = (&result.ssh_key, paired_public_key) {
+        let mut private_key = PrivateKey::from_openssh(&ssh_key.private_key)?;
+        if private_key.comment().is_empty()
+            && private_key.public_key().key_data() == paired_public_key.key_data()
+        {
+            private_key.set_comment(paired_public_key.comment());
+            result.ssh_key = Some(SshKey::try_from(&private_key)?);
+        }
+    }
+    Ok(result)
+}
+
+/// Reads a file if it is a regular file small enough to be a key
+fn read_key_file(path: &Path) -> Result<Option<String>, anyhow::Error> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() || metadata.len() > MAX_KEY_FILE_SIZE {
+        return Ok(None);
+    }
+    match String::from_utf8(fs::read(path)?) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The format of a private key file and whether it is encrypted, or `None` if it is not a private key
+fn detect_format(contents: &str) -> Option<(KeyFileFormat, bool)> {
+    let first_line = contents.lines().next()?.trim_end();
+    match first_line {
+        OPENSSH_HEADER => {
+            let encrypted = PrivateKey::from_openssh(contents).is_ok_and(|key| key.is_encrypted());
+            Some((KeyFileFormat::OpenSsh, encrypted))
+        }
+        PKCS1_HEADER => Some((
+            KeyFileFormat::Pkcs1,
+            contents.contains(PKCS1_ENCRYPTED_MARKER),
+        )),
+        PKCS8_UNENCRYPTED_HEADER => Some((KeyFileFormat::Pkcs8, false)),
+        PKCS8_ENCRYPTED_HEADER => Some((KeyFileFormat::Pkcs8, true)),
+        line if line.starts_with(PPK_HEADER_PREFIX) => {
+            let encrypted = contents
+                .lines()
+                .find_map(|line| line.trim_end().strip_prefix("Encryption: "))
+                .is_some_and(|encryption| encryption != "none");
+            Some((KeyFileFormat::Putty, encrypted))
+        }
+        _ => None,
+    }
+}
+
+/// PKCS#1 keys and PuTTY files older than version 3 are detected, but cannot be imported
+fn is_importable_format(contents: &str) -> bool {
+    let first_line = contents.lines().next().unwrap_or_default().trim_end();
+    first_line != PKCS1_HEADER
+        && (!first_line.starts_with(PPK_HEADER_PREFIX)
+            || first_line.starts_with(&format!("{}: ", PPK_V3_HEADER)))
+}
+
+/// The public key in `<key>.pub`, which carries the comment that PKCS#8 files lose
+fn read_public_key(path: &Path) -> Option<PublicKey> {
+    let mut public_key_path = path.as_os_str().to_owned();
+    public_key_path.push(".pub");
+    let contents = fs::read_to_string(PathBuf::from(public_key_path)).ok()?;
+    PublicKey::from_openssh(contents.trim()).ok()
+}
+
+/// OpenSSH keys store the public key unencrypted, so it is known even before the passphrase is
+fn embedded_public_key(contents: &str) -> Option<PublicKey> {
+    PrivateKey::from_openssh(contents)
+        .ok()
+        .map(|key| key.public_key().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::test_support::TempDir;
+
+    #[test]
+    fn scan_reports_every_key_file() {
+        let directory = TempDir::new("scan");
+        let files = [
+            (
+                "id_ed25519",
+                include_str!("./test_keys/ed25519_openssh_unencrypted"),
+            ),
+            ("id_rsa", include_str!("./test_keys/rsa_openssh_encrypted")),
+            (
+                "legacy",
+                include_str!("./test_keys/rsa_putty_pkcs1_unencrypted"),
+            ),
+            (
+                "pkcs8",
+                include_str!("./test_keys/ed25519_pkcs8_unencrypted"),
+            ),
+            (
+                "putty_v2.ppk",
+                "PuTTY-User-Key-File-2: ssh-rsa\nEncryption: aes256-cbc\nComment: old\n",
+            ),
+            ("config", "Host *\n    IdentityAgent ~/agent.sock\n"),
+            ("known_hosts", "github.com ssh-ed25519 AAAA\n"),
+        ];
+        for (name, contents) in files {
+            fs::write(directory.join(name), contents).unwrap();
+        }
+        let public_key = include_str!("./test_keys/ed25519_pkcs8_unencrypted.pub").trim();
+        fs::write(
+            directory.join("pkcs8.pub"),
+            format!("{} work laptop\n", public_key),
+        )
+        .unwrap();
+
+        let scanned = scan_ssh_directory(directory.path()).unwrap();
+        let summary = scanned
+            .iter()
+            .map(|file| {
+                (
+                    file.path.file_name().unwrap().to_str().unwrap(),
+                    file.format,
+                    file.encrypted,
+                    &file.result.status,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "id_ed25519",
+                    KeyFileFormat::OpenSsh,
+                    false,
+                    &SshKeyImportStatus::Success
+                ),
+                (
+                    "id_rsa",
+                    KeyFileFormat::OpenSsh,
+                    true,
+                    &SshKeyImportStatus::PasswordRequired
+                ),
+                (
+                    "legacy",
+                    KeyFileFormat::Pkcs1,
+                    false,
+                    &SshKeyImportStatus::UnsupportedKeyType
+                ),
+                (
+                    "pkcs8",
+                    KeyFileFormat::Pkcs8,
+                    false,
+                    &SshKeyImportStatus::Success
+                ),
+                (
+                    "putty_v2.ppk",
+                    KeyFileFormat::Putty,
+                    true,
+                    &SshKeyImportStatus::UnsupportedKeyType
+                ),
+            ]
+        );
+
+        // The public key of an encrypted key is known without the passphrase, its comment is not
+        let (public_key, _comment) = include_str!("./test_keys/rsa_openssh_encrypted.pub")
+            .trim()
+            .rsplit_once(' ')
+            .unwrap();
+        assert_eq!(scanned[1].public_key.as_deref(), Some(public_key));
+        // PKCS#8 keys get their comment back from the .pub file
+        let pkcs8_key = scanned[3].result.ssh_key.as_ref().unwrap();
+        assert!(pkcs8_key.public_key.ends_with(" work laptop"));
+    }
+
+    #[test]
+    fn import_encrypted_key_file() {
+        let directory = TempDir::new("scan");
+        let path = directory.join("id_rsa");
+        fs::write(&path, include_str!("./test_keys/rsa_openssh_encrypted")).unwrap();
+
+        let result = import_key_file(&path, "").unwrap();
+        assert_eq!(result.status, SshKeyImportStatus::PasswordRequired);
+        let result = import_key_file(&path, "password").unwrap();
+        assert_eq!(result.status, SshKeyImportStatus::Success);
+        assert!(result
+            .ssh_key
+            .unwrap()
+            .private_key
+            .starts_with(OPENSSH_HEADER));
+    }
+}
@@ -0,0 +1,635 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use futures::{Stream, StreamExt};
+use rsa::signature::{SignatureEncoding, Signer};
+use ssh_encoding::Encode;
+use ssh_key::{private::KeypairData, Algorithm, HashAlg};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+use super::audit_log::{self, AuditDecision, AuditOperation};
+use super::certificate::Certificate;
+use super::policy::KeyUsagePolicy;
+use super::protocol::{
+    KeyConstraints, Request, Response, SessionBind, MAX_MESSAGE_LENGTH, SESSION_BIND_EXTENSION,
+    SSH_AGENT_RSA_SHA2_256,
+};
+use super::session_key::SealedKey;
+use super::sign_purpose::SignPurpose;
+use super::upstream::UpstreamAgent;
+
+#[derive(Clone, Default)]
+pub struct Key {
+    /// `None` while the agent is locked
+    pub private_key: Option<ssh_key::private::PrivateKey>,
+    /// The private key encrypted under the session key while the agent is locked
+    pub sealed_private_key: Option<SealedKey>,
+    pub name: String,
+    /// The vault item the key belongs to, or `None` for keys added by an agent client (`ssh-add`)
+    pub cipher_uuid: Option<String>,
+    /// Folder of the vault item, used to select the keys of a listener
+    pub folder_id: Option<String>,
+    /// Tags of the vault item, used to select the keys of a listener
+    pub tags: Vec<String>,
+    pub constraints: KeyConstraints,
+    pub expires_at: Option<Instant>,
+    /// SHA256 fingerprints of the host keys this key may sign in to, empty if any host is allowed
+    pub allowed_host_keys: Vec<String>,
+    /// Restricts what the key may sign, `None` if the key is unrestricted
+    pub usage_policy: Option<KeyUsagePolicy>,
+    /// OpenSSH certificates of the key, served as identities of their own and signed for with the key
+    pub certificates: Vec<Certificate>,
+    /// Whether every use has to be verified by the OS (Windows Hello, polkit) after the app approved it
+    pub require_user_verification: bool,
+}
+
+impl Key {
+    /// SHA256 fingerprint of the key, `None` while the private key is locked
+    pub fn fingerprint(&self) -> Option<String> {
+        self.private_key.as_ref().map(|private_key| {
+            private_key
+                .public_key()
+                .fingerprint(HashAlg::Sha256)
+                .to_string()
+        })
+    }
+}
+
+/// Keys served by the agent, indexed by their public key blob
+#[derive(Clone, Default)]
+pub struct KeyStore(pub Arc<RwLock<HashMap<Vec<u8>, Key>>>);
+
+/// Policy hooks the agent protocol server calls into before handing out keys or signatures.
+pub trait Agent<Info>: Clone + Send + Sync + 'static {
+    /// Asks whether the key may sign, `certificate` is set if the client addressed one of its certificates
+    fn confirm(
+        &self,
+        ssh_key: Key,
+        certificate: Option<&Certificate>,
+        purpose: &SignPurpose,
+        session_binds: &[SessionBind],
+        info: &Info,
+    ) -> impl Future<Output = bool> + Send;
+    fn can_list(&self, info: &Info) -> impl Future<Output = bool> + Send;
+    /// Asks whether a key sent by an agent client (`ssh-add`) should be imported into the vault
+    fn import_identity(
+        &self,
+        private_key: &ssh_key::private::PrivateKey,
+        constraints: &KeyConstraints,
+        info: &Info,
+    ) -> impl Future<Output = bool> + Send;
+    /// Asks whether a vault key an agent client tried to remove should be removed from the vault
+    fn remove_vault_identity(&self, ssh_key: Key, info: &Info)
+        -> impl Future<Output = bool> + Send;
+    fn lock_with_passphrase(&self, passphrase: &[u8]) -> impl Future<Output = bool> + Send;
+    fn unlock_with_passphrase(&self, passphrase: &[u8]) -> impl Future<Output = bool> + Send;
+    fn is_passphrase_locked(&self) -> bool;
+    /// Agent whose keys are served next to the keys of the keystore
+    fn upstream(&self) -> Option<&UpstreamAgent>;
+    /// Whether the key is exposed on the socket the connection arrived on
+    fn serves(&self, ssh_key: &Key) -> bool;
+    /// Called when a client connects and when its connection is closed, to count connections
+    fn connection_opened(&self);
+    fn connection_closed(&self);
+    /// Records an operation the server decided on itself, signing and listing are recorded by the agent
+    fn record_operation(
+        &self,
+        operation: AuditOperation,
+        ssh_key: Option<&Key>,
+        decision: AuditDecision,
+        info: &Info,
+    );
+    /// Records a sign request forwarded to the upstream agent, whose keys are only known by their blob
+    fn record_upstream_signature(
+        &self,
+        key_blob: &[u8],
+        purpose: &SignPurpose,
+        decision: AuditDecision,
+        info: &Info,
+    );
+}
+
+fn decision(allowed: bool) -> AuditDecision {
+    match allowed {
+        true => AuditDecision::AutoAllowed,
+        false => AuditDecision::Denied,
+    }
+}
+
+pub async fn serve<S, L, A, I>(
+    mut listener: L,
+    agent: A,
+    keystore: KeyStore,
+    cancellation_token: CancellationToken,
+) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    L: Stream<Item = io::Result<(S, I)>> + Unpin,
+    A: Agent<I>,
+    I: Send + Sync + 'static,
+{
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => {
+                break;
+            }
+            connection = listener.next() => {
+                match connection {
+                    Some(Ok((stream, info))) => {
+                        let agent = agent.clone();
+                        let keystore = keystore.clone();
+                        let cancellation_token = cancellation_token.clone();
+                        tokio::spawn(async move {
+                            agent.connection_opened();
+                            if let Err(e) =
+                                handle_connection(stream, info, agent.clone(), keystore, cancellation_token)
+                                    .await
+                            {
+                                println!("[SSH Agent Native Module] Connection closed with error: {}", e);
+                            }
+                            agent.connection_closed();
+                        });
+                    }
+                    Some(Err(e)) => {
+                        println!("[SSH Agent Native Module] Error while accepting connection: {}", e);
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_connection<S, A, I>(
+    mut stream: S,
+    info: I,
+    agent: A,
+    keystore: KeyStore,
+    cancellation_token: CancellationToken,
+) -> Result<(), anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+    A: Agent<I>,
+    I: Send + Sync,
+{
+    // Session binds are valid for the lifetime of the connection they were sent on
+    let mut session_binds = Vec::new();
+    loop {
+        let length = tokio::select! {
+            _ = cancellation_token.cancelled() => return Ok(()),
+            length = stream.read_u32() => match length {
+                Ok(length) => length as usize,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            },
+        };
+        if length == 0 || length > MAX_MESSAGE_LENGTH {
+            return Err(anyhow::anyhow!("Invalid message length: {}", length));
+        }
+
+        let mut message = vec![0u8; length];
+        stream.read_exact(&mut message).await?;
+
+        let response = match Request::parse(&message) {
+            Ok(request) => {
+                handle_request(request, &mut session_binds, &info, &agent, &keystore).await
+            }
+            Err(e) => {
+                println!("[SSH Agent Native Module] Could not parse request: {}", e);
+                Response::Failure
+            }
+        };
+        stream.write_all(&response.to_bytes()?).await?;
+    }
+}
+
+async fn handle_request<A, I>(
+    request: Request,
+    session_binds: &mut Vec<SessionBind>,
+    info: &I,
+    agent: &A,
+    keystore: &KeyStore,
+) -> Response
+where
+    A: Agent<I>,
+    I: Send + Sync,
+{
+    // A locked agent behaves as if it holds no keys, and only accepts the unlock message
+    if agent.is_passphrase_locked() {
+        return match request {
+            Request::RequestIdentities => Response::Identities(Vec::new()),
+            Request::Unlock { passphrase } => {
+                let unlocked = agent.unlock_with_passphrase(&passphrase).await;
+                agent.record_operation(AuditOperation::Unlock, None, decision(unlocked), info);
+                match unlocked {
+                    true => Response::Success,
+                    false => Response::Failure,
+                }
+            }
+            _ => Response::Failure,
+        };
+    }
+
+    match request {
+        Request::RequestIdentities => {
+            if !agent.can_list(info).await {
+                return Response::Failure;
+            }
+            let mut identities = keystore.identities(|key| agent.serves(key));
+            if let Some(upstream) = agent.upstream() {
+                // The vault keys are still served if the upstream agent is unavailable
+                match upstream.request_identities().await {
+                    Ok(upstream_identities) => {
+                        for (key_blob, comment) in upstream_identities {
+                            if keystore.get(&key_blob).is_none() {
+                                identities.push((key_blob, comment));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        println!(
+                            "[SSH Agent Native Module] Could not list keys of upstream agent: {}",
+                            e
+                        );
+                    }
+                }
+            }
+            Response::Identities(identities)
+        }
+        Request::SignRequest {
+            key_blob,
+            data,
+            flags,
+        } => {
+            let (key, certificate) = match (
+                keystore
+                    .get_with_certificate(&key_blob)
+                    .filter(|(key, _)| agent.serves(key)),
+                agent.upstream(),
+            ) {
+                (Some(key), _) => key,
+                (None, Some(upstream)) => {
+                    let purpose = SignPurpose::parse(&data);
+                    let signature = upstream.sign(key_blob.clone(), data, flags).await;
+                    agent.record_upstream_signature(
+                        &key_blob,
+                        &purpose,
+                        decision(signature.is_ok()),
+                        info,
+                    );
+                    return match signature {
+                        Ok(signature) => Response::SignResponse(signature),
+                        Err(e) => {
+                            println!(
+                                "[SSH Agent Native Module] Upstream agent could not sign: {}",
+                                e
+                            );
+                            Response::Failure
+                        }
+                    };
+                }
+                (None, None) => return Response::Failure,
+            };
+            let private_key = match key.private_key.clone() {
+                Some(private_key) => private_key,
+                None => return Response::Failure,
+            };
+            let purpose = SignPurpose::parse(&data);
+            if !agent
+                .confirm(key, certificate.as_ref(), &purpose, session_binds, info)
+                .await
+            {
+                return Response::Failure;
+            }
+
+            match sign(&private_key, &data, flags) {
+                Ok(signature) => Response::SignResponse(signature),
+                Err(e) => {
+                    println!("[SSH Agent Native Module] Error while signing: {}", e);
+                    Response::Failure
+                }
+            }
+        }
+        Request::AddIdentity {
+            private_key,
+            constraints,
+        } => {
+            if !agent
+                .import_identity(&private_key, &constraints, info)
+                .await
+            {
+                return Response::Failure;
+            }
+            // The key is served right away, until it arrives through the vault sync
+            match keystore.add_client_key(*private_key, constraints) {
+                Ok(()) => Response::Success,
+                Err(e) => {
+                    println!("[SSH Agent Native Module] Could not add key: {}", e);
+                    Response::Failure
+                }
+            }
+        }
+        Request::RemoveIdentity { key_blob } => {
+            let key = keystore.get(&key_blob).filter(|key| agent.serves(key));
+            if key.is_none() {
+                return Response::Failure;
+            }
+            if let Some(key) = key.clone().filter(|key| key.cipher_uuid.is_some()) {
+                return match agent.remove_vault_identity(key, info).await {
+                    true => Response::Success,
+                    false => Response::Failure,
+                };
+            }
+            let removed = keystore.remove_client_key(&key_blob);
+            agent.record_operation(
+                AuditOperation::Remove,
+                key.as_ref(),
+                decision(removed),
+                info,
+            );
+            match removed {
+                true => Response::Success,
+                false => Response::Failure,
+            }
+        }
+        Request::RemoveAllIdentities => {
+            // A listener only removes the keys it serves, not those added through other sockets
+            keystore.remove_all_client_keys(|key| agent.serves(key));
+            agent.record_operation(AuditOperation::Remove, None, decision(true), info);
+            Response::Success
+        }
+        Request::Lock { passphrase } => {
+            let locked = agent.lock_with_passphrase(&passphrase).await;
+            agent.record_operation(AuditOperation::Lock, None, decision(locked), info);
+            match locked {
+                true => Response::Success,
+                false => Response::Failure,
+            }
+        }
+        Request::Unlock { .. } => Response::Failure,
+        Request::Extension { name, contents } if name == SESSION_BIND_EXTENSION => {
+            let session_bind = match SessionBind::parse(&contents) {
+                Ok(session_bind) => session_bind,
+                Err(e) => {
+                    println!("[SSH Agent Native Module] Invalid session bind: {}", e);
+                    return Response::Failure;
+                }
+            };
+            // A connection that was bound for authentication must not be re-bound to another host
+            if session_binds.last().is_some_and(|bind| !bind.is_forwarding) {
+                return Response::Failure;
+            }
+            session_binds.push(session_bind);
+            Response::Success
+        }
+        Request::Extension { .. } | Request::Unknown(_) => Response::Failure,
+    }
+}
+
+impl KeyStore {
+    /// The served keys, each followed by its certificates that are currently valid
+    fn identities(&self, serves: impl Fn(&Key) -> bool) -> Vec<(Vec<u8>, String)> {
+        let now = audit_log::now();
+        let mut identities = Vec::new();
+        for (public_key_bytes, key) in self
+            .0
+            .read()
+            .expect("RwLock is not poisoned")
+            .iter()
+            .filter(|(_, key)| serves(key))
+        {
+            identities.push((public_key_bytes.clone(), key.name.clone()));
+            identities.extend(
+                key.certificates
+                    .iter()
+                    .filter(|certificate| certificate.is_valid_at(now))
+                    .map(|certificate| (certificate.as_bytes().to_vec(), key.name.clone())),
+            );
+        }
+        identities
+    }
+
+    fn get(&self, public_key_bytes: &[u8]) -> Option<Key> {
+        self.get_with_certificate(public_key_bytes)
+            .map(|(key, _)| key)
+    }
+
+    /// Finds the key of a public key or certificate blob, and the certificate if the blob is one
+    fn get_with_certificate(&self, key_blob: &[u8]) -> Option<(Key, Option<Certificate>)> {
+        let keys = self.0.read().expect("RwLock is not poisoned");
+        if let Some(key) = keys.get(key_blob) {
+            return Some((key.clone(), None));
+        }
+        keys.values().find_map(|key| {
+            key.certificates
+                .iter()
+                .find(|certificate| certificate.as_bytes() == key_blob)
+                .map(|certificate| (key.clone(), Some(certificate.clone())))
+        })
+    }
+
+    /// Adds a key sent by an agent client, and schedules its removal if it has a lifetime constraint.
+    fn add_client_key(
+        &self,
+        private_key: ssh_key::private::PrivateKey,
+        constraints: KeyConstraints,
+    ) -> Result<(), anyhow::Error> {
+        let public_key_bytes = private_key.public_key().to_bytes()?;
+        let expires_at = constraints
+            .lifetime
+            .map(|lifetime| Instant::now() + Duration::from_secs(lifetime.into()));
+
+        let mut keys = self.0.write().expect("RwLock is not poisoned");
+        // The vault key keeps its restrictions, which a key of the client would silently drop
+        if keys
+            .get(&public_key_bytes)
+            .is_some_and(|key| key.cipher_uuid.is_some())
+        {
+            return Ok(());
+        }
+        keys.insert(
+            public_key_bytes.clone(),
+            Key {
+                name: private_key.comment().to_string(),
+                private_key: Some(private_key),
+                constraints,
+                expires_at,
+                ..Default::default()
+            },
+        );
+        drop(keys);
+
+        if let Some(expires_at) = expires_at {
+            let keystore = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep_until(expires_at.into()).await;
+                let mut keys = keystore.0.write().expect("RwLock is not poisoned");
+                // The key might have been re-added with a different lifetime in the meantime
+                if keys
+                    .get(&public_key_bytes)
+                    .is_some_and(|key| key.expires_at == Some(expires_at))
+                {
+                    keys.remove(&public_key_bytes);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Vault keys are removed through the vault, only keys added by agent clients are removed here.
+    fn remove_client_key(&self, public_key_bytes: &[u8]) -> bool {
+        let mut keys = self.0.write().expect("RwLock is not poisoned");
+        match keys.get(public_key_bytes) {
+            Some(key) if key.cipher_uuid.is_none() => {
+                keys.remove(public_key_bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removing all keys never touches the vault, as a plain `ssh-add -D` should not delete vault items.
+    fn remove_all_client_keys(&self, serves: impl Fn(&Key) -> bool) {
+        self.0
+            .write()
+            .expect("RwLock is not poisoned")
+            .retain(|_, key| key.cipher_uuid.is_some() || !serves(key));
+    }
+}
+
+fn sign(
+    private_key: &ssh_key::private::PrivateKey,
+    data: &[u8],
+    flags: u32,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let signature = match private_key.key_data() {
+        // ssh-key always signs with rsa-sha2-512, but clients may ask for rsa-sha2-256
+        KeypairData::Rsa(keypair) if flags & SSH_AGENT_RSA_SHA2_256 != 0 => {
+            let signing_key = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(
+                rsa::RsaPrivateKey::try_from(keypair)?,
+            );
+            ssh_key::Signature::new(
+                Algorithm::Rsa {
+                    hash: Some(HashAlg::Sha256),
+                },
+                signing_key.try_sign(data)?.to_vec(),
+            )?
+        }
+        _ => private_key.try_sign(data)?,
+    };
+
+    let mut signature_bytes = Vec::new();
+    signature.encode(&mut signature_bytes)?;
+    Ok(signature_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::signature::Verifier;
+    use ssh_encoding::Decode;
+    use ssh_key::{rand_core::OsRng, PrivateKey};
+
+    use super::super::certificate::tests::certificate_for;
+    use super::*;
+
+    fn key_with_certificates(private_key: &PrivateKey, certificates: Vec<Certificate>) -> Key {
+        Key {
+            private_key: Some(private_key.clone()),
+            name: "deploy key".to_string(),
+            cipher_uuid: Some("cipher".to_string()),
+            certificates,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn certificates_are_served_and_signed_with_their_key() {
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let valid = certificate_for(&private_key, 0, u64::MAX);
+        let expired = certificate_for(&private_key, 0, 1);
+        let public_key_bytes = private_key.public_key().to_bytes().unwrap();
+        let keystore = KeyStore::default();
+        keystore.0.write().unwrap().insert(
+            public_key_bytes.clone(),
+            key_with_certificates(&private_key, vec![valid.clone(), expired]),
+        );
+
+        let identities = keystore.identities(|_| true);
+        assert_eq!(
+            identities,
+            vec![
+                (public_key_bytes, "deploy key".to_string()),
+                (valid.as_bytes().to_vec(), "deploy key".to_string()),
+            ]
+        );
+
+        let (key, certificate) = keystore.get_with_certificate(valid.as_bytes()).unwrap();
+        assert_eq!(certificate.as_ref(), Some(&valid));
+        let signature_bytes = sign(key.private_key.as_ref().unwrap(), b"data", 0).unwrap();
+        let signature = ssh_key::Signature::decode(&mut signature_bytes.as_slice()).unwrap();
+        assert!(private_key
+            .public_key()
+            .key_data()
+            .verify(b"data", &signature)
+            .is_ok());
+
+        let other_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let foreign = certificate_for(&other_key, 0, u64::MAX);
+        assert!(keystore.get_with_certificate(foreign.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn remove_all_only_removes_served_client_keys() {
+        let keystore = KeyStore::default();
+        let vault_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        keystore.0.write().unwrap().insert(
+            vault_key.public_key().to_bytes().unwrap(),
+            key_with_certificates(&vault_key, Vec::new()),
+        );
+        for comment in ["served", "other"] {
+            let mut client_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+            client_key.set_comment(comment);
+            keystore
+                .add_client_key(client_key, Default::default())
+                .unwrap();
+        }
+
+        keystore.remove_all_client_keys(|key| key.name != "other");
+        let mut names = keystore
+            .identities(|_| true)
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["deploy key", "other"]);
+    }
+
+    #[test]
+    fn client_keys_do_not_replace_vault_keys() {
+        let private_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let public_key_bytes = private_key.public_key().to_bytes().unwrap();
+        let keystore = KeyStore::default();
+        let mut vault_key = key_with_certificates(&private_key, Vec::new());
+        vault_key.require_user_verification = true;
+        keystore
+            .0
+            .write()
+            .unwrap()
+            .insert(public_key_bytes.clone(), vault_key);
+
+        keystore
+            .add_client_key(private_key, Default::default())
+            .unwrap();
+        let key = keystore.get(&public_key_bytes).unwrap();
+        assert_eq!(key.cipher_uuid.as_deref(), Some("cipher"));
+        assert!(key.require_user_verification);
+    }
+}
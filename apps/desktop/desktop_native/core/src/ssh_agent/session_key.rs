@@ -0,0 +1,174 @@
+use aes::cipher::generic_array::GenericArray;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use russh_cryptovec::CryptoVec;
+use sha2::Sha256;
+
+use crate::crypto;
+
+const ENC_KEY_LENGTH: usize = 32;
+const MAC_KEY_LENGTH: usize = 32;
+const IV_LENGTH: usize = 16;
+const MAC_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+
+/// Argon2id parameters for stretching the wrapping secret, which can be a short PIN
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_PARALLELISM: u32 = 4;
+
+/// A private key encrypted under a [`SessionKey`], with the same AES-256-CBC + HMAC-SHA256 construction as type 2 cipher strings.
+#[derive(Clone)]
+pub struct SealedKey {
+    iv: [u8; IV_LENGTH],
+    mac: [u8; MAC_LENGTH],
+    data: Vec<u8>,
+}
+
+/**
+* Random key the private keys of a locked agent are encrypted under. It only lives in a locked,
+* zeroed-on-drop buffer and leaves the process wrapped by a secret the app can recover on unlock,
+* such as a biometric secret or a PIN.
+*/
+pub struct SessionKey {
+    /// Encryption key followed by the MAC key
+    key: CryptoVec,
+}
+
+impl SessionKey {
+    pub fn generate() -> Self {
+        let mut key = CryptoVec::new_zeroed(ENC_KEY_LENGTH + MAC_KEY_LENGTH);
+        rand::thread_rng().fill_bytes(&mut key);
+        SessionKey { key }
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Result<SealedKey, anyhow::Error> {
+        encrypt(&self.key, plaintext)
+    }
+
+    /// Decrypts a sealed key into a protected buffer
+    pub fn open(&self, sealed: &SealedKey) -> Result<CryptoVec, anyhow::Error> {
+        decrypt(&self.key, sealed)
+    }
+
+    /// Encrypts the session key under a key derived from the secret, encoded as base64 of `salt | iv | mac | data`.
+    pub fn wrap(&self, secret: &[u8]) -> Result<String, anyhow::Error> {
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let wrapping_key = derive_wrapping_key(secret, &salt)?;
+        let sealed = encrypt(&wrapping_key, &self.key)?;
+
+        let mut wrapped =
+            Vec::with_capacity(SALT_LENGTH + IV_LENGTH + MAC_LENGTH + sealed.data.len());
+        wrapped.extend_from_slice(&salt);
+        wrapped.extend_from_slice(&sealed.iv);
+        wrapped.extend_from_slice(&sealed.mac);
+        wrapped.extend_from_slice(&sealed.data);
+        Ok(STANDARD.encode(wrapped))
+    }
+
+    pub fn unwrap(wrapped: &str, secret: &[u8]) -> Result<Self, anyhow::Error> {
+        let wrapped = STANDARD.decode(wrapped)?;
+        if wrapped.len() <= SALT_LENGTH + IV_LENGTH + MAC_LENGTH {
+            return Err(anyhow::anyhow!("Wrapped session key is too short"));
+        }
+        let (salt, rest) = wrapped.split_at(SALT_LENGTH);
+        let (iv, rest) = rest.split_at(IV_LENGTH);
+        let (mac, data) = rest.split_at(MAC_LENGTH);
+        let sealed = SealedKey {
+            iv: iv.try_into()?,
+            mac: mac.try_into()?,
+            data: data.to_vec(),
+        };
+
+        let wrapping_key = derive_wrapping_key(secret, salt)?;
+        let key = decrypt(&wrapping_key, &sealed)?;
+        if key.len() != ENC_KEY_LENGTH + MAC_KEY_LENGTH {
+            return Err(anyhow::anyhow!("Wrapped session key has an invalid length"));
+        }
+        Ok(SessionKey { key })
+    }
+}
+
+/// Stretches the secret with Argon2id, then expands it into an encryption and a MAC key with HKDF-Expand
+fn derive_wrapping_key(secret: &[u8], salt: &[u8]) -> Result<CryptoVec, anyhow::Error> {
+    let stretched = CryptoVec::from_slice(&crypto::argon2(
+        secret,
+        salt,
+        ARGON2_ITERATIONS,
+        ARGON2_MEMORY_KIB,
+        ARGON2_PARALLELISM,
+    )?);
+
+    let mut key = CryptoVec::new();
+    for info in [b"enc".as_slice(), b"mac".as_slice()] {
+        let mut hmac = Hmac::<Sha256>::new_from_slice(&stretched)?;
+        hmac.update(info);
+        hmac.update(&[1]);
+        key.extend(&hmac.finalize().into_bytes());
+    }
+    Ok(key)
+}
+
+fn encrypt(key: &[u8], plaintext: &[u8]) -> Result<SealedKey, anyhow::Error> {
+    let (enc_key, mac_key) = key.split_at(ENC_KEY_LENGTH);
+    let mut iv = [0u8; IV_LENGTH];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let data = match crypto::encrypt_aes256(plaintext, iv, *GenericArray::from_slice(enc_key))? {
+        crypto::CipherString::AesCbc256_B64 { data, .. } => data,
+        _ => return Err(anyhow::anyhow!("Unexpected cipher string type")),
+    };
+    let mac = compute_mac(mac_key, &iv, &data)?
+        .finalize()
+        .into_bytes()
+        .into();
+    Ok(SealedKey { iv, mac, data })
+}
+
+fn decrypt(key: &[u8], sealed: &SealedKey) -> Result<CryptoVec, anyhow::Error> {
+    let (enc_key, mac_key) = key.split_at(ENC_KEY_LENGTH);
+    compute_mac(mac_key, &sealed.iv, &sealed.data)?
+        .verify_slice(&sealed.mac)
+        .map_err(|_| anyhow::anyhow!("Invalid MAC, the key or the secret is wrong"))?;
+
+    let mut decrypted =
+        crypto::decrypt_aes256(&sealed.iv, &sealed.data, *GenericArray::from_slice(enc_key))?;
+    let plaintext = CryptoVec::from_slice(&decrypted);
+    decrypted.fill(0);
+    Ok(plaintext)
+}
+
+fn compute_mac(mac_key: &[u8], iv: &[u8], data: &[u8]) -> Result<Hmac<Sha256>, anyhow::Error> {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(mac_key)?;
+    hmac.update(iv);
+    hmac.update(data);
+    Ok(hmac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let session_key = SessionKey::generate();
+        let sealed = session_key.seal(b"private key").unwrap();
+        assert_ne!(sealed.data, b"private key");
+        assert_eq!(&session_key.open(&sealed).unwrap()[..], b"private key");
+
+        assert!(SessionKey::generate().open(&sealed).is_err());
+    }
+
+    #[test]
+    fn wrap_and_unwrap() {
+        let session_key = SessionKey::generate();
+        let sealed = session_key.seal(b"private key").unwrap();
+        let wrapped = session_key.wrap(b"1234").unwrap();
+
+        assert!(SessionKey::unwrap(&wrapped, b"4321").is_err());
+        let unwrapped = SessionKey::unwrap(&wrapped, b"1234").unwrap();
+        assert_eq!(&unwrapped.open(&sealed).unwrap()[..], b"private key");
+    }
+}
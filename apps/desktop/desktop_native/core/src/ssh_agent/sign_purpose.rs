@@ -0,0 +1,204 @@
+use ssh_encoding::{Decode, Reader};
+
+const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+const PUBLICKEY_METHOD: &str = "publickey";
+const PUBLICKEY_HOSTBOUND_METHOD: &str = "publickey-hostbound-v00@openssh.com";
+const SSHSIG_MAGIC_PREAMBLE: &[u8] = b"SSHSIG";
+
+/// What the data of a sign request is, decoded so the user can be told what they are approving.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignPurpose {
+    /// Public key authentication to a server (RFC 4252 section 7)
+    UserAuth {
+        session_id: Vec<u8>,
+        username: String,
+        service: String,
+        public_key_algorithm: String,
+        /// Host key of the server, sent by clients using `publickey-hostbound-v00@openssh.com`
+        host_key: Option<Vec<u8>>,
+    },
+    /// A signature in the SSHSIG format, as created by `ssh-keygen -Y sign` or git
+    SshSig {
+        namespace: String,
+        hash_algorithm: String,
+    },
+    /// Not a signature: the app asks to decrypt an age file encrypted to the key
+    AgeDecryption,
+    /// The app asks to sign an OpenSSH certificate with the key as the CA
+    Certificate {
+        key_id: String,
+        /// Users or hosts the certificate will be valid for, any if empty
+        principals: Vec<String>,
+        is_host_certificate: bool,
+    },
+    /// The data is neither of the above, signing it could be an attempt to forge a signature for another protocol
+    Unknown,
+}
+
+impl SignPurpose {
+    pub fn parse(data: &[u8]) -> Self {
+        if let Some(sshsig) = data.strip_prefix(SSHSIG_MAGIC_PREAMBLE) {
+            return parse_sshsig(sshsig).unwrap_or(SignPurpose::Unknown);
+        }
+        parse_userauth(data).unwrap_or(SignPurpose::Unknown)
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        matches!(self, SignPurpose::Unknown)
+    }
+}
+
+impl std::fmt::Display for SignPurpose {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignPurpose::UserAuth {
+                username, service, ..
+            } => write!(f, "authentication as {} ({})", username, service),
+            SignPurpose::SshSig { namespace, .. } => {
+                write!(f, "signature for namespace {}", namespace)
+            }
+            SignPurpose::AgeDecryption => write!(f, "decryption of an age file"),
+            SignPurpose::Certificate {
+                key_id,
+                principals,
+                is_host_certificate,
+            } => {
+                let kind = match is_host_certificate {
+                    true => "host",
+                    false => "user",
+                };
+                match principals.is_empty() {
+                    true => write!(f, "{} certificate {} for any principal", kind, key_id),
+                    false => write!(
+                        f,
+                        "{} certificate {} for {}",
+                        kind,
+                        key_id,
+                        principals.join(", ")
+                    ),
+                }
+            }
+            SignPurpose::Unknown => write!(f, "unknown data"),
+        }
+    }
+}
+
+fn parse_userauth(mut reader: &[u8]) -> Result<SignPurpose, anyhow::Error> {
+    let session_id = Vec::decode(&mut reader)?;
+    if u8::decode(&mut reader)? != SSH_MSG_USERAUTH_REQUEST {
+        return Err(anyhow::anyhow!("Not a userauth request"));
+    }
+    let username = String::decode(&mut reader)?;
+    let service = String::decode(&mut reader)?;
+    let method = String::decode(&mut reader)?;
+    if method != PUBLICKEY_METHOD && method != PUBLICKEY_HOSTBOUND_METHOD {
+        return Err(anyhow::anyhow!("Unexpected userauth method {}", method));
+    }
+    // Signatures are only ever requested for the variant of the message that contains one
+    if u8::decode(&mut reader)? != 1 {
+        return Err(anyhow::anyhow!("Userauth request without signature"));
+    }
+    let public_key_algorithm = String::decode(&mut reader)?;
+    let _public_key = Vec::<u8>::decode(&mut reader)?;
+    let host_key = if method == PUBLICKEY_HOSTBOUND_METHOD {
+        Some(Vec::decode(&mut reader)?)
+    } else {
+        None
+    };
+    if !reader.is_finished() {
+        return Err(anyhow::anyhow!("Trailing data after userauth request"));
+    }
+
+    Ok(SignPurpose::UserAuth {
+        session_id,
+        username,
+        service,
+        public_key_algorithm,
+        host_key,
+    })
+}
+
+fn parse_sshsig(mut reader: &[u8]) -> Result<SignPurpose, anyhow::Error> {
+    let namespace = String::decode(&mut reader)?;
+    let _reserved = Vec::<u8>::decode(&mut reader)?;
+    let hash_algorithm = String::decode(&mut reader)?;
+    let _hash = Vec::<u8>::decode(&mut reader)?;
+    if !reader.is_finished() {
+        return Err(anyhow::anyhow!("Trailing data after SSHSIG blob"));
+    }
+
+    Ok(SignPurpose::SshSig {
+        namespace,
+        hash_algorithm,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_encoding::Encode;
+    use ssh_key::{HashAlg, SshSig};
+
+    fn userauth_request(method: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        b"session id".as_slice().encode(&mut data).unwrap();
+        SSH_MSG_USERAUTH_REQUEST.encode(&mut data).unwrap();
+        "git".encode(&mut data).unwrap();
+        "ssh-connection".encode(&mut data).unwrap();
+        method.encode(&mut data).unwrap();
+        1u8.encode(&mut data).unwrap();
+        "ssh-ed25519".encode(&mut data).unwrap();
+        b"public key".as_slice().encode(&mut data).unwrap();
+        if method == PUBLICKEY_HOSTBOUND_METHOD {
+            b"host key".as_slice().encode(&mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn parse_userauth_request() {
+        assert_eq!(
+            SignPurpose::parse(&userauth_request(PUBLICKEY_METHOD)),
+            SignPurpose::UserAuth {
+                session_id: b"session id".to_vec(),
+                username: "git".to_string(),
+                service: "ssh-connection".to_string(),
+                public_key_algorithm: "ssh-ed25519".to_string(),
+                host_key: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_hostbound_userauth_request() {
+        match SignPurpose::parse(&userauth_request(PUBLICKEY_HOSTBOUND_METHOD)) {
+            SignPurpose::UserAuth { host_key, .. } => {
+                assert_eq!(host_key, Some(b"host key".to_vec()))
+            }
+            purpose => panic!("Expected userauth request, got {:?}", purpose),
+        }
+    }
+
+    #[test]
+    fn parse_sshsig() {
+        let data = SshSig::signed_data("git", HashAlg::Sha512, b"commit").unwrap();
+        assert_eq!(
+            SignPurpose::parse(&data),
+            SignPurpose::SshSig {
+                namespace: "git".to_string(),
+                hash_algorithm: "sha512".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_unknown_payload_is_suspicious() {
+        let purpose = SignPurpose::parse(b"arbitrary data");
+        assert_eq!(purpose, SignPurpose::Unknown);
+        assert!(purpose.is_suspicious());
+
+        let mut data = userauth_request(PUBLICKEY_METHOD);
+        data.push(0);
+        assert!(SignPurpose::parse(&data).is_suspicious());
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use homedir::my_home;
+
+/// Directory below `~/.ssh` that is owned by Bitwarden
+const MANAGED_DIRECTORY: &str = "bitwarden";
+const CONFIG_FILE: &str = "config";
+/// Lists the public key files written by Bitwarden, so that only those are removed when a key goes away
+const MANIFEST_FILE: &str = ".managed";
+
+/// A vault key to write a public key stub and host blocks for
+#[derive(Clone, Debug)]
+pub struct ConfigKey {
+    pub cipher_id: String,
+    pub name: String,
+    /// OpenSSH encoded public key
+    pub public_key: String,
+    /// `Host` patterns (e.g. `github.com`, `*.example.com`) the key is offered to, exclusively
+    pub host_patterns: Vec<String>,
+}
+
+/// Files changed by [`write_ssh_config`]
+#[derive(Clone, Debug, Default)]
+pub struct SshConfigUpdate {
+    /// The snippet to `Include` from `~/.ssh/config`
+    pub config_path: PathBuf,
+    /// Files that were created or whose contents changed
+    pub written: Vec<PathBuf>,
+    /// Public key files of keys that are no longer in the vault
+    pub removed: Vec<PathBuf>,
+}
+
+pub fn default_ssh_directory() -> Result<PathBuf, anyhow::Error> {
+    match my_home() {
+        Ok(Some(home)) => Ok(home.join(".ssh")),
+        _ => Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Could not determine home directory"
+        )),
+    }
+}
+
+/**
+* Writes the public key of every key to `<ssh_directory>/bitwarden/<name>.pub`, and a config snippet that points
+* ssh at the agent and offers only the assigned key to each host pattern, so that ssh does not try every vault
+* key and run into `MaxAuthTries`. The snippet has to be included by the user with `Include ~/.ssh/bitwarden/config`;
+* nothing outside the managed directory is touched. Files are only rewritten when their contents change.
+*/
+pub fn write_ssh_config(
+    ssh_directory: &Path,
+    agent_socket: &Path,
+    keys: &[ConfigKey],
+) -> Result<SshConfigUpdate, anyhow::Error> {
+    let directory = ssh_directory.join(MANAGED_DIRECTORY);
+    create_private_directory(&directory)?;
+
+    let mut update = SshConfigUpdate {
+        config_path: directory.join(CONFIG_FILE),
+        ..Default::default()
+    };
+
+    let mut keys = keys.to_vec();
+    keys.sort_by(|a, b| (&a.name, &a.cipher_id).cmp(&(&b.name, &b.cipher_id)));
+
+    let mut file_names = HashSet::new();
+    let mut host_blocks = Vec::new();
+    for key in keys.iter() {
+        let mut public_key = match ssh_key::PublicKey::from_openssh(&key.public_key) {
+            Ok(public_key) => public_key,
+            Err(e) => {
+                println!(
+                    "[SSH Agent Native Module] Skipping invalid public key of cipher {}: {}",
+                    key.cipher_id, e
+                );
+                continue;
+            }
+        };
+        public_key.set_comment(&key.name);
+
+        let mut file_name = format!("{}.pub", file_stem(&key.name));
+        if file_names.contains(&file_name) {
+            let suffix: String = key.cipher_id.chars().take(8).collect();
+            file_name = format!("{}-{}.pub", file_stem(&key.name), file_stem(&suffix));
+        }
+        let path = directory.join(&file_name);
+        if write_if_changed(&path, &format!("{}\n", public_key.to_openssh()?), 0o644)? {
+            update.written.push(path.clone());
+        }
+        file_names.insert(file_name);
+
+        let host_patterns = key
+            .host_patterns
+            .iter()
+            .filter(|pattern| {
+                let valid = is_valid_host_pattern(pattern);
+                if !valid {
+                    println!(
+                        "[SSH Agent Native Module] Skipping invalid host pattern {:?} of cipher {}",
+                        pattern, key.cipher_id
+                    );
+                }
+                valid
+            })
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !host_patterns.is_empty() {
+            host_blocks.push(format!(
+                "Host {}\n    IdentityFile {}\n    IdentitiesOnly yes\n",
+                host_patterns.join(" "),
+                quote(&path)?
+            ));
+        }
+    }
+
+    let config = format!(
+        "# Generated by Bitwarden, changes to this file are overwritten.\n\
+         # Include it at the top of ~/.ssh/config with: Include {}\n\n\
+         {}{}Host *\n    IdentityAgent {}\n",
+        quote(&update.config_path)?,
+        host_blocks.join("\n"),
+        if host_blocks.is_empty() { "" } else { "\n" },
+        quote(agent_socket)?
+    );
+    if write_if_changed(&update.config_path, &config, 0o600)? {
+        update.written.push(update.config_path.clone());
+    }
+
+    let manifest_path = directory.join(MANIFEST_FILE);
+    let previous = fs::read_to_string(&manifest_path).unwrap_or_default();
+    for stale in previous
+        .lines()
+        .filter(|name| !file_names.contains(*name) && is_managed_file_name(name))
+    {
+        let path = directory.join(stale);
+        match fs::remove_file(&path) {
+            Ok(()) => update.removed.push(path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let mut manifest = file_names.into_iter().collect::<Vec<_>>();
+    manifest.sort();
+    write_if_changed(
+        &manifest_path,
+        &manifest
+            .iter()
+            .map(|name| format!("{}\n", name))
+            .collect::<String>(),
+        0o600,
+    )?;
+
+    Ok(update)
+}
+
+/// Turns a key name into a file name, keeping letters, digits, `-`, `_` and `.`
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string();
+    match stem.is_empty() {
+        true => "key".to_string(),
+        false => stem,
+    }
+}
+
+/// Manifest entries are only trusted if they name a public key file directly in the managed directory
+fn is_managed_file_name(name: &str) -> bool {
+    name.ends_with(".pub") && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/**
+* Host patterns are written unquoted and separated by spaces, so a pattern containing whitespace, a quote or a
+* control character could end the `Host` line and add arbitrary options such as `ProxyCommand`.
+*/
+fn is_valid_host_pattern(pattern: &str) -> bool {
+    !pattern.is_empty()
+        && !pattern
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"')
+}
+
+/// ssh_config has no way to escape a quote, so paths containing one or a control character are rejected
+fn quote(path: &Path) -> Result<String, anyhow::Error> {
+    let path = path.to_string_lossy();
+    if path.contains(|c: char| c.is_control() || c == '"') {
+        return Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Cannot write path {:?} to the ssh config",
+            path
+        ));
+    }
+    match path.contains(char::is_whitespace) {
+        true => Ok(format!("\"{}\"", path)),
+        false => Ok(path.to_string()),
+    }
+}
+
+fn create_private_directory(path: &Path) -> Result<(), anyhow::Error> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(path)?;
+    Ok(())
+}
+
+/// Returns whether the file was written
+fn write_if_changed(path: &Path, contents: &str, mode: u32) -> Result<bool, anyhow::Error> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    #[cfg(unix)]
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(mode))?;
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::test_support::TempDir;
+
+    fn config_key(cipher_id: &str, name: &str, host_patterns: &[&str]) -> ConfigKey {
+        let private_key = ssh_key::private::PrivateKey::random(
+            &mut rand::thread_rng(),
+            ssh_key::Algorithm::Ed25519,
+        )
+        .unwrap();
+        ConfigKey {
+            cipher_id: cipher_id.to_string(),
+            name: name.to_string(),
+            public_key: private_key.public_key().to_openssh().unwrap(),
+            host_patterns: host_patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn writes_public_keys_and_host_blocks() {
+        let temp_dir = TempDir::new("config");
+        let ssh_directory = temp_dir.path();
+        let keys = vec![
+            config_key("1", "Work key", &["github.com", "*.example.com"]),
+            config_key("2", "Work key", &[]),
+        ];
+        let update = write_ssh_config(ssh_directory, Path::new("/run/agent.sock"), &keys).unwrap();
+        assert_eq!(update.written.len(), 3);
+
+        let directory = ssh_directory.join(MANAGED_DIRECTORY);
+        let public_key = fs::read_to_string(directory.join("Work_key.pub")).unwrap();
+        assert!(public_key.trim_end().ends_with("Work key"));
+        assert!(directory.join("Work_key-2.pub").exists());
+
+        let config = fs::read_to_string(&update.config_path).unwrap();
+        assert!(config.contains(&format!(
+            "Host github.com *.example.com\n    IdentityFile {}\n    IdentitiesOnly yes\n",
+            directory.join("Work_key.pub").display()
+        )));
+        assert!(config.ends_with("Host *\n    IdentityAgent /run/agent.sock\n"));
+
+        // Writing the same keys again changes nothing
+        let update = write_ssh_config(ssh_directory, Path::new("/run/agent.sock"), &keys).unwrap();
+        assert!(update.written.is_empty());
+    }
+
+    #[test]
+    fn removes_only_managed_files() {
+        let temp_dir = TempDir::new("config");
+        let ssh_directory = temp_dir.path();
+        let socket = Path::new("/run/agent.sock");
+        write_ssh_config(ssh_directory, socket, &[config_key("1", "old", &[])]).unwrap();
+        let directory = ssh_directory.join(MANAGED_DIRECTORY);
+        fs::write(directory.join("mine.pub"), "user file").unwrap();
+
+        let update =
+            write_ssh_config(ssh_directory, socket, &[config_key("2", "new", &[])]).unwrap();
+        assert_eq!(update.removed, vec![directory.join("old.pub")]);
+        assert!(directory.join("new.pub").exists());
+        assert!(directory.join("mine.pub").exists());
+    }
+    #[test]
+    fn skips_host_patterns_that_would_inject_options() {
+        let temp_dir = TempDir::new("config");
+        let keys = vec![config_key(
+            "1",
+            "key",
+            &[
+                "github.com",
+                "evil\n    ProxyCommand touch /tmp/pwned",
+                "a\"b",
+                "*.example.com Match exec \"true\"",
+                "",
+            ],
+        )];
+        let update =
+            write_ssh_config(temp_dir.path(), Path::new("/run/agent.sock"), &keys).unwrap();
+
+        let config = fs::read_to_string(&update.config_path).unwrap();
+        assert!(config.contains("Host github.com\n    IdentityFile"));
+        assert!(!config.contains("ProxyCommand"));
+        assert!(!config.contains("Match"));
+    }
+
+    #[test]
+    fn rejects_paths_with_quotes() {
+        let temp_dir = TempDir::new("config");
+        let result = write_ssh_config(
+            temp_dir.path(),
+            Path::new("/run/agent\"\n    ProxyCommand x.sock"),
+            &[],
+        );
+        assert!(result.is_err());
+        assert_eq!(quote(Path::new("/a b")).unwrap(), "\"/a b\"");
+    }
+}
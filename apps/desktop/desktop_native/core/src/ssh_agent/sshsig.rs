@@ -0,0 +1,348 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ssh_key::{Algorithm, HashAlg, PublicKey, SshSig};
+
+/// An entry of an `allowed_signers` file, as described in the ALLOWED SIGNERS section of ssh-keygen(1):
+/// `principals [options] keytype base64-key [comment]`
+#[derive(Clone, Debug)]
+pub struct AllowedSigner {
+    /// Comma separated patterns of the identities the key may sign as, e.g. `alice@example.com,*@ci.example.com`
+    pub principals: String,
+    /// The key is a certificate authority rather than a signing key
+    pub cert_authority: bool,
+    /// Patterns of the namespaces the key may sign in, `None` if it may sign in any
+    pub namespaces: Option<String>,
+    /// Unix time before which the key is not valid
+    pub valid_after: Option<u64>,
+    /// Unix time from which on the key is no longer valid
+    pub valid_before: Option<u64>,
+    pub public_key: PublicKey,
+}
+
+/// A signature that verified against an allowed signer
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedSignature {
+    /// Principals of every allowed signer entry that accepts the signature
+    pub principals: Vec<String>,
+    /// SHA256 fingerprint of the signing key
+    pub fingerprint: String,
+}
+
+/**
+* Verifies an armored SSHSIG signature (`-----BEGIN SSH SIGNATURE-----`) like `ssh-keygen -Y verify`, except that
+* instead of checking a given identity, it returns the principals of the allowed signers that accept the signature,
+* like `ssh-keygen -Y find-principals`. Fails if the signature is invalid or no allowed signer accepts it.
+*/
+pub fn verify_sshsig(
+    signature_armored: &str,
+    allowed_signers: &str,
+    namespace: &str,
+    data: &[u8],
+) -> Result<VerifiedSignature, anyhow::Error> {
+    let signature = SshSig::from_pem(signature_armored.trim())?;
+    let public_key = PublicKey::from(signature.public_key().clone());
+    public_key.verify(namespace, data, &signature)?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let principals = parse_allowed_signers(allowed_signers)?
+        .into_iter()
+        .filter(|signer| signer.accepts(&public_key, namespace, now))
+        .map(|signer| signer.principals)
+        .collect::<Vec<_>>();
+    if principals.is_empty() {
+        return Err(anyhow::anyhow!(
+            "The signing key {} is not an allowed signer",
+            public_key.fingerprint(HashAlg::Sha256)
+        ));
+    }
+
+    Ok(VerifiedSignature {
+        principals,
+        fingerprint: public_key.fingerprint(HashAlg::Sha256).to_string(),
+    })
+}
+
+impl AllowedSigner {
+    /// Whether the entry allows the key to sign in the namespace at the given unix time
+    fn accepts(&self, public_key: &PublicKey, namespace: &str, now: u64) -> bool {
+        !self.cert_authority
+            && self.public_key.key_data() == public_key.key_data()
+            && self
+                .namespaces
+                .as_ref()
+                .is_none_or(|namespaces| match_pattern_list(namespace, namespaces))
+            && self
+                .valid_after
+                .is_none_or(|valid_after| now >= valid_after)
+            && self
+                .valid_before
+                .is_none_or(|valid_before| now < valid_before)
+    }
+}
+
+/// Parses the contents of an `allowed_signers` file, skipping empty lines and comments
+pub fn parse_allowed_signers(contents: &str) -> Result<Vec<AllowedSigner>, anyhow::Error> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map(|(index, line)| {
+            parse_allowed_signer(line)
+                .map_err(|e| anyhow::anyhow!("Invalid allowed signer on line {}: {}", index + 1, e))
+        })
+        .collect()
+}
+
+fn parse_allowed_signer(line: &str) -> Result<AllowedSigner, anyhow::Error> {
+    let fields = split_fields(line);
+    let mut fields = fields.iter().map(String::as_str);
+    let principals = fields
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Missing principals"))?;
+
+    let mut cert_authority = false;
+    let mut namespaces = None;
+    let mut valid_after = None;
+    let mut valid_before = None;
+    let mut key_type = fields
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Missing key"))?;
+    // The options field is optional, a key type follows the principals directly
+    if key_type.parse::<Algorithm>().is_err() {
+        for option in split_options(key_type) {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim_matches('"'))),
+                None => (option.as_str(), None),
+            };
+            match (name.to_ascii_lowercase().as_str(), value) {
+                ("cert-authority", None) => cert_authority = true,
+                ("namespaces", Some(value)) => namespaces = Some(value.to_string()),
+                ("valid-after", Some(value)) => valid_after = Some(parse_time(value)?),
+                ("valid-before", Some(value)) => valid_before = Some(parse_time(value)?),
+                _ => return Err(anyhow::anyhow!("Unsupported option {}", option)),
+            }
+        }
+        key_type = fields
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Missing key"))?;
+    }
+    let key_data = fields
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Missing key data"))?;
+
+    Ok(AllowedSigner {
+        principals: principals.to_string(),
+        cert_authority,
+        namespaces,
+        valid_after,
+        valid_before,
+        public_key: PublicKey::from_openssh(&format!("{} {}", key_type, key_data))?,
+    })
+}
+
+/// Splits a line at whitespace, keeping whitespace inside double quotes
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                field.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !field.is_empty() {
+                    fields.push(std::mem::take(&mut field));
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() {
+        fields.push(field);
+    }
+    // A quoted principals field is unquoted, patterns never contain quotes
+    if let Some(principals) = fields.first_mut() {
+        *principals = principals.trim_matches('"').to_string();
+    }
+    fields
+}
+
+/// Splits the options field at commas, keeping commas inside double quotes (`namespaces="git,file"`)
+fn split_options(options: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut option = String::new();
+    let mut quoted = false;
+    for c in options.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                option.push(c);
+            }
+            ',' if !quoted => result.push(std::mem::take(&mut option)),
+            c => option.push(c),
+        }
+    }
+    result.push(option);
+    result
+}
+
+/// Parses `YYYYMMDD[HHMM[SS]][Z]` into unix time. Times are taken as UTC, with or without the `Z` suffix.
+fn parse_time(value: &str) -> Result<u64, anyhow::Error> {
+    let digits = value.strip_suffix(['Z', 'z']).unwrap_or(value);
+    if !matches!(digits.len(), 8 | 12 | 14) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("Invalid time {}", value));
+    }
+    let number = |range: std::ops::Range<usize>| -> u64 {
+        digits
+            .get(range)
+            .map_or(0, |part| part.parse().unwrap_or(0))
+    };
+    let (year, month, day) = (number(0..4), number(4..6), number(6..8));
+    let (hour, minute, second) = (number(8..10), number(10..12), number(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(anyhow::anyhow!("Invalid time {}", value));
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    if days < 0 {
+        return Err(anyhow::anyhow!("Invalid time {}", value));
+    }
+    Ok(days as u64 * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Matches against a comma separated list of patterns like OpenSSH, where a matching `!pattern` rejects
+pub(super) fn match_pattern_list(value: &str, patterns: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split(',').map(str::trim) {
+        match pattern.strip_prefix('!') {
+            Some(negated) if match_pattern(value, negated) => return false,
+            Some(_) => {}
+            None => matched |= match_pattern(value, pattern),
+        }
+    }
+    matched
+}
+
+/// Matches a pattern with `*` (any sequence) and `?` (any character) wildcards
+fn match_pattern(value: &str, pattern: &str) -> bool {
+    let value = value.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let (mut v, mut p) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                v += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((star, position)) => {
+                    p = star + 1;
+                    v = position + 1;
+                    backtrack = Some((star, position + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_key() -> ssh_key::PrivateKey {
+        ssh_key::PrivateKey::from_openssh(include_str!("./test_keys/ed25519_openssh_unencrypted"))
+            .unwrap()
+    }
+
+    fn sign(namespace: &str, data: &[u8]) -> String {
+        signing_key()
+            .sign(namespace, HashAlg::Sha512, data)
+            .unwrap()
+            .to_pem(ssh_key::LineEnding::LF)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_allowed_signers_file() {
+        let public_key = signing_key().public_key().to_openssh().unwrap();
+        let contents = format!(
+            "# comment\n\n\
+             alice@example.com {public_key}\n\
+             \"bob@example.com,carol@*\" namespaces=\"git,file\",valid-after=\"20240101\" {public_key}\n\
+             *@example.com cert-authority {public_key}\n"
+        );
+        let signers = parse_allowed_signers(&contents).unwrap();
+        assert_eq!(signers.len(), 3);
+        assert_eq!(signers[0].principals, "alice@example.com");
+        assert_eq!(signers[1].principals, "bob@example.com,carol@*");
+        assert_eq!(signers[1].namespaces.as_deref(), Some("git,file"));
+        assert_eq!(signers[1].valid_after, Some(1704067200));
+        assert!(signers[2].cert_authority);
+
+        assert!(
+            parse_allowed_signers("alice@example.com unknown-option ssh-ed25519 AAAA").is_err()
+        );
+    }
+
+    #[test]
+    fn verify_signature_against_allowed_signers() {
+        let public_key = signing_key().public_key().to_openssh().unwrap();
+        let allowed_signers = format!(
+            "alice@example.com namespaces=\"git\" {public_key}\n\
+             deploy@example.com namespaces=\"file\" {public_key}\n\
+             expired@example.com valid-before=\"20000101\" {public_key}\n"
+        );
+
+        let signature = sign("git", b"commit");
+        let verified = verify_sshsig(&signature, &allowed_signers, "git", b"commit").unwrap();
+        assert_eq!(verified.principals, vec!["alice@example.com"]);
+        assert_eq!(
+            verified.fingerprint,
+            "SHA256:oaEiIEZe8SyB9Dh+eHD/SRkUj8enzP39H/sctgzbDb8"
+        );
+
+        // Tampered data, a different namespace and unknown keys are rejected
+        assert!(verify_sshsig(&signature, &allowed_signers, "git", b"other commit").is_err());
+        assert!(verify_sshsig(&signature, &allowed_signers, "file", b"commit").is_err());
+        let signature = sign("ci", b"artifact");
+        assert!(verify_sshsig(&signature, &allowed_signers, "ci", b"artifact").is_err());
+    }
+
+    #[test]
+    fn pattern_lists() {
+        assert!(match_pattern_list("git", "git,file"));
+        assert!(match_pattern_list("alice@example.com", "*@example.com"));
+        assert!(match_pattern_list("file", "fil?"));
+        assert!(!match_pattern_list(
+            "root@example.com",
+            "*@example.com,!root@*"
+        ));
+        assert!(!match_pattern_list("git", "file"));
+    }
+}
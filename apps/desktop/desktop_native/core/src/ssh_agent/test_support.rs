@@ -0,0 +1,59 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use ssh_key::{LineEnding, PrivateKey};
+
+use super::AgentKey;
+
+static NEXT_DIRECTORY: AtomicU32 = AtomicU32::new(0);
+
+/// An empty directory under the system temp directory, removed with its contents when dropped
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates a directory unique to this test process; `name` only makes it recognizable
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "bitwarden-ssh-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT_DIRECTORY.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An enabled, unrestricted vault key as the app syncs it, named after the cipher without its `-cipher` suffix
+pub(crate) fn agent_key(cipher_id: &str, private_key: &PrivateKey) -> AgentKey {
+    AgentKey {
+        cipher_id: cipher_id.to_string(),
+        name: cipher_id.trim_end_matches("-cipher").to_string(),
+        private_key: private_key.to_openssh(LineEnding::LF).unwrap().to_string(),
+        folder_id: None,
+        tags: Vec::new(),
+        enabled: true,
+        allowed_host_keys: Vec::new(),
+        policy: None,
+        certificates: Vec::new(),
+        require_user_verification: false,
+    }
+}
@@ -1,115 +1,398 @@
+#[cfg(target_os = "linux")]
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
 use std::{
-    collections::HashMap,
     fs,
-    os::unix::fs::PermissionsExt,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicU32},
-        Arc, RwLock,
+        Arc,
     },
 };
 
-use bitwarden_russh::ssh_agent;
 use homedir::my_home;
 use tokio::{net::UnixListener, sync::Mutex};
 use tokio_util::sync::CancellationToken;
 
 use crate::ssh_agent::peercred_unix_listener_stream::PeercredUnixListenerStream;
 
-use super::{BitwardenDesktopAgent, SshAgentUIRequest};
+use super::{
+    audit_log::AuditLog, listeners::ListenerConfig, server, upstream_from_env,
+    BitwardenDesktopAgent, SshAgentUIRequest, UI_RESPONSE_TIMEOUT,
+};
+
+/// Directory below `XDG_RUNTIME_DIR` holding the agent socket
+const SOCKET_DIRECTORY: &str = "bitwarden";
+const SOCKET_NAME: &str = "ssh-agent.sock";
+/// First file descriptor passed by socket activation, see sd_listen_fds(3)
+#[cfg(target_os = "linux")]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Set once the socket passed by socket activation is taken, as its descriptor may only be owned once
+#[cfg(target_os = "linux")]
+static INHERITED_LISTENER_TAKEN: AtomicBool = AtomicBool::new(false);
 
 impl BitwardenDesktopAgent {
     pub async fn start_server(
         auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
         auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+        audit_log: Option<AuditLog>,
     ) -> Result<Self, anyhow::Error> {
-        let agent = BitwardenDesktopAgent {
-            keystore: ssh_agent::KeyStore(Arc::new(RwLock::new(HashMap::new()))),
+        let inherited_listener = inherited_listener()?;
+        let ssh_path = match &inherited_listener {
+            // The path the socket unit bound, as reported by getsockname
+            Some(listener) => listener
+                .local_addr()?
+                .as_pathname()
+                .map(Path::to_path_buf)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("[SSH Agent Native Module] Inherited socket has no path")
+                })?,
+            None => socket_path()?,
+        };
+
+        let agent = Self::new(
+            auth_request_tx,
+            auth_response_rx,
+            audit_log,
+            ssh_path,
+            inherited_listener,
+        );
+        agent.listen()?;
+        Ok(agent)
+    }
+
+    /// An agent serving `ssh_path`, or the inherited socket if there is one. It is started by `listen`.
+    pub(super) fn new(
+        auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
+        auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+        audit_log: Option<AuditLog>,
+        ssh_path: PathBuf,
+        inherited_listener: Option<std::os::unix::net::UnixListener>,
+    ) -> Self {
+        BitwardenDesktopAgent {
+            keystore: server::KeyStore::default(),
             cancellation_token: CancellationToken::new(),
             show_ui_request_tx: auth_request_tx,
             get_ui_response_rx: auth_response_rx,
             request_id: Arc::new(AtomicU32::new(0)),
             needs_unlock: Arc::new(AtomicBool::new(false)),
             is_running: Arc::new(AtomicBool::new(false)),
+            passphrase_lock: Default::default(),
+            audit_log,
+            upstream: upstream_from_env(&ssh_path),
+            socket_path: Some(ssh_path),
+            inherited_listener: inherited_listener.map(Arc::new),
+            listeners: Default::default(),
+            listener: None,
+            verification_window: Default::default(),
+            metrics: Default::default(),
+            ui_response_timeout: UI_RESPONSE_TIMEOUT,
+            rate_limiter: Default::default(),
+        }
+    }
+
+    /// Binds the socket and serves it until the agent is stopped
+    pub(super) fn listen(&self) -> Result<(), anyhow::Error> {
+        let listener = match &self.inherited_listener {
+            Some(listener) => {
+                println!("[SSH Agent Native Module] Starting SSH Agent server on inherited socket");
+                // The inherited socket is kept open across restarts, the server gets its own handle
+                let listener = listener.try_clone()?;
+                listener.set_nonblocking(true)?;
+                UnixListener::from_std(listener)?
+            }
+            None => {
+                let sockname = self
+                    .socket_path
+                    .as_deref()
+                    .expect("Agents without an inherited socket have a socket path");
+                bind(sockname)?
+            }
         };
-        let cloned_agent_state = agent.clone();
+
+        let stream = PeercredUnixListenerStream::new(listener);
+        let cloned_agent_state = self.clone();
+        cloned_agent_state
+            .is_running
+            .store(true, std::sync::atomic::Ordering::Relaxed);
         tokio::spawn(async move {
-            let ssh_path = match std::env::var("BITWARDEN_SSH_AUTH_SOCK") {
-                Ok(path) => path,
-                Err(_) => {
-                    println!("[SSH Agent Native Module] BITWARDEN_SSH_AUTH_SOCK not set, using default path");
-
-                    let ssh_agent_directory = match my_home() {
-                        Ok(Some(home)) => home,
-                        _ => {
-                            println!(
-                                "[SSH Agent Native Module] Could not determine home directory"
-                            );
-                            return;
-                        }
-                    };
-                    ssh_agent_directory
-                        .join(".bitwarden-ssh-agent.sock")
-                        .to_str()
-                        .expect("Path should be valid")
-                        .to_owned()
-                }
-            };
-
-            println!(
-                "[SSH Agent Native Module] Starting SSH Agent server on {:?}",
-                ssh_path
-            );
-            let sockname = std::path::Path::new(&ssh_path);
-            if let Err(e) = std::fs::remove_file(sockname) {
-                println!(
-                    "[SSH Agent Native Module] Could not remove existing socket file: {}",
-                    e
-                );
-                if e.kind() != std::io::ErrorKind::NotFound {
-                    return;
-                }
-            }
+            let cloned_keystore = cloned_agent_state.keystore.clone();
+            let cloned_cancellation_token = cloned_agent_state.cancellation_token.clone();
+            let _ = server::serve(
+                stream,
+                cloned_agent_state.clone(),
+                cloned_keystore,
+                cloned_cancellation_token,
+            )
+            .await;
+            cloned_agent_state
+                .is_running
+                .store(false, std::sync::atomic::Ordering::Relaxed);
+            println!("[SSH Agent Native Module] SSH Agent server exited");
+        });
 
-            match UnixListener::bind(sockname) {
-                Ok(listener) => {
-                    // Only the current user should be able to access the socket
-                    if let Err(e) = fs::set_permissions(sockname, fs::Permissions::from_mode(0o600))
-                    {
-                        println!(
-                            "[SSH Agent Native Module] Could not set socket permissions: {}",
-                            e
-                        );
-                        return;
-                    }
-
-                    let stream = PeercredUnixListenerStream::new(listener);
-
-                    let cloned_keystore = cloned_agent_state.keystore.clone();
-                    let cloned_cancellation_token = cloned_agent_state.cancellation_token.clone();
-                    cloned_agent_state
-                        .is_running
-                        .store(true, std::sync::atomic::Ordering::Relaxed);
-                    let _ = ssh_agent::serve(
-                        stream,
-                        cloned_agent_state.clone(),
-                        cloned_keystore,
-                        cloned_cancellation_token,
-                    )
-                    .await;
-                    cloned_agent_state
-                        .is_running
-                        .store(false, std::sync::atomic::Ordering::Relaxed);
-                    println!("[SSH Agent Native Module] SSH Agent server exited");
-                }
-                Err(e) => {
-                    eprintln!(
-                        "[SSH Agent Native Module] Error while starting agent server: {}",
-                        e
-                    );
-                }
-            }
+        Ok(())
+    }
+
+    /// Path of the default socket, including a socket inherited through socket activation
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        self.socket_path.clone()
+    }
+
+    /// Binds the socket of a listener and serves it until the token is cancelled
+    pub(super) fn serve_listener(
+        &self,
+        config: Arc<ListenerConfig>,
+        cancellation_token: CancellationToken,
+    ) -> Result<(), anyhow::Error> {
+        let stream = PeercredUnixListenerStream::new(bind(&config.path)?);
+        let agent = BitwardenDesktopAgent {
+            cancellation_token: cancellation_token.clone(),
+            listener: Some(config.clone()),
+            ..self.clone()
+        };
+        tokio::spawn(async move {
+            let keystore = agent.keystore.clone();
+            let _ = server::serve(stream, agent, keystore, cancellation_token).await;
+            println!("[SSH Agent Native Module] Listener {} exited", config.name);
         });
+        Ok(())
+    }
+}
 
-        Ok(agent)
+fn bind(sockname: &Path) -> Result<UnixListener, anyhow::Error> {
+    println!(
+        "[SSH Agent Native Module] Starting SSH Agent server on {:?}",
+        sockname
+    );
+    remove_stale_socket(sockname)?;
+
+    let listener = UnixListener::bind(sockname).map_err(|e| {
+        anyhow::anyhow!(
+            "[SSH Agent Native Module] Error while starting agent server: {}",
+            e
+        )
+    })?;
+    // Only the current user should be able to access the socket
+    if let Err(e) = fs::set_permissions(sockname, fs::Permissions::from_mode(0o600)) {
+        let _ = fs::remove_file(sockname);
+        return Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Could not set socket permissions: {}",
+            e
+        ));
+    }
+    Ok(listener)
+}
+
+/**
+* Path of the agent socket: `BITWARDEN_SSH_AUTH_SOCK` if set, otherwise `$XDG_RUNTIME_DIR/bitwarden/ssh-agent.sock`.
+* The runtime directory is local, private to the user and cleared on logout. Without one, such as on macOS,
+* the socket is placed in the home directory.
+*/
+fn socket_path() -> Result<PathBuf, anyhow::Error> {
+    if let Some(path) = std::env::var_os("BITWARDEN_SSH_AUTH_SOCK") {
+        return Ok(PathBuf::from(path));
+    }
+    println!("[SSH Agent Native Module] BITWARDEN_SSH_AUTH_SOCK not set, using default path");
+
+    if let Some(runtime_dir) = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+    {
+        let socket_dir = runtime_dir.join(SOCKET_DIRECTORY);
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&socket_dir)?;
+        // The directory may have been created with other permissions by an older version
+        fs::set_permissions(&socket_dir, fs::Permissions::from_mode(0o700))?;
+        return Ok(socket_dir.join(SOCKET_NAME));
+    }
+
+    match my_home() {
+        Ok(Some(home)) => Ok(home.join(".bitwarden-ssh-agent.sock")),
+        _ => Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Could not determine home directory"
+        )),
+    }
+}
+
+/**
+* Takes the listening socket passed by systemd socket activation (`LISTEN_FDS`/`LISTEN_PID`), so that a user
+* socket unit can own the agent path before the app starts. Returns `None` if the app was not socket activated.
+*/
+#[cfg(target_os = "linux")]
+fn inherited_listener() -> Result<Option<std::os::unix::net::UnixListener>, anyhow::Error> {
+    // The variables are left set, as changing the environment of a multithreaded process is unsound. Child
+    // processes ignore them, since LISTEN_PID names this process.
+    let (Ok(listen_pid), Ok(listen_fds)) =
+        (std::env::var("LISTEN_PID"), std::env::var("LISTEN_FDS"))
+    else {
+        return Ok(None);
+    };
+    if listen_pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+    match listen_fds.parse::<u32>() {
+        Ok(1) => {}
+        Ok(0) | Err(_) => return Ok(None),
+        Ok(n) => {
+            return Err(anyhow::anyhow!(
+                "[SSH Agent Native Module] Expected a single inherited socket, got {}",
+                n
+            ))
+        }
+    }
+    // An agent started again in the same process must not take the descriptor, which the first one closed
+    if INHERITED_LISTENER_TAKEN.swap(true, std::sync::atomic::Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    check_listening_unix_socket(SD_LISTEN_FDS_START)?;
+    // SAFETY: the descriptor is an open listening socket, and is only taken once
+    let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+    let listener = std::os::unix::net::UnixListener::from(fd);
+    if listener.local_addr()?.as_pathname().is_none() {
+        return Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Inherited socket is not a unix socket with a path"
+        ));
+    }
+    println!(
+        "[SSH Agent Native Module] Using socket inherited through socket activation: {:?}",
+        listener.local_addr()?
+    );
+    Ok(Some(listener))
+}
+
+/// Socket activation is a systemd feature, launchd passes sockets differently
+#[cfg(not(target_os = "linux"))]
+fn inherited_listener() -> Result<Option<std::os::unix::net::UnixListener>, anyhow::Error> {
+    Ok(None)
+}
+
+/// Checks that the descriptor is a listening unix stream socket before taking ownership of it
+#[cfg(target_os = "linux")]
+fn check_listening_unix_socket(fd: RawFd) -> Result<(), anyhow::Error> {
+    // SAFETY: fstat only writes to the buffer passed, and fails for descriptors that are not open
+    let is_socket = unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        libc::fstat(fd, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFSOCK
+    };
+    if !is_socket
+        || socket_option(fd, libc::SO_DOMAIN) != Some(libc::AF_UNIX)
+        || socket_option(fd, libc::SO_TYPE) != Some(libc::SOCK_STREAM)
+        || socket_option(fd, libc::SO_ACCEPTCONN) != Some(1)
+    {
+        return Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Inherited descriptor {} is not a listening unix socket",
+            fd
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn socket_option(fd: RawFd, option: libc::c_int) -> Option<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: getsockopt writes at most `length` bytes to `value`
+    let result = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    (result == 0).then_some(value)
+}
+
+/**
+* Removes a socket left behind by an agent that did not shut down cleanly. If a process still accepts
+* connections on the socket, it is left alone and an error is returned instead of taking over its path.
+*/
+fn remove_stale_socket(path: &Path) -> Result<(), anyhow::Error> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] {:?} exists and is not a socket",
+            path
+        ));
+    }
+
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(anyhow::anyhow!(
+            "[SSH Agent Native Module] Another agent is already listening on {:?}",
+            path
+        ));
+    }
+
+    println!(
+        "[SSH Agent Native Module] Removing stale socket file {:?}",
+        path
+    );
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_agent::test_support::TempDir;
+
+    #[test]
+    fn removes_stale_socket() {
+        let directory = TempDir::new("socket");
+        let path = directory.join("agent.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_live_socket() {
+        let directory = TempDir::new("socket");
+        let path = directory.join("agent.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn only_listening_unix_sockets_are_inherited() {
+        use std::os::fd::AsRawFd;
+
+        let directory = TempDir::new("socket");
+        let path = directory.join("agent.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(check_listening_unix_socket(listener.as_raw_fd()).is_ok());
+
+        let (stream, _) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(check_listening_unix_socket(stream.as_raw_fd()).is_err());
+        let tcp_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(check_listening_unix_socket(tcp_listener.as_raw_fd()).is_err());
+        let file = fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        assert!(check_listening_unix_socket(file.as_raw_fd()).is_err());
+    }
+
+    #[test]
+    fn refuses_regular_file() {
+        let directory = TempDir::new("socket");
+        let path = directory.join("agent.sock");
+        fs::write(&path, "not a socket").unwrap();
+
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
     }
 }
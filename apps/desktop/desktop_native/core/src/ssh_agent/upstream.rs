@@ -0,0 +1,145 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::protocol::{Request, Response, MAX_MESSAGE_LENGTH};
+
+/// Listing keys should not stall the vault keys behind an unresponsive upstream agent
+const IDENTITIES_TIMEOUT: Duration = Duration::from_secs(5);
+/// Signing can wait for user interaction in the upstream agent, such as touching a hardware token
+const SIGN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/**
+* Client for another ssh agent (gpg-agent, a hardware token agent, the system ssh-agent) whose keys are
+* served next to the vault keys. Every request opens a new connection, so a restarted upstream agent is
+* picked up without restarting Bitwarden.
+*/
+#[derive(Clone, Debug)]
+pub struct UpstreamAgent {
+    path: PathBuf,
+}
+
+impl UpstreamAgent {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn request_identities(&self) -> Result<Vec<(Vec<u8>, String)>, anyhow::Error> {
+        match self
+            .request(&Request::RequestIdentities, IDENTITIES_TIMEOUT)
+            .await?
+        {
+            Response::Identities(identities) => Ok(identities),
+            response => Err(anyhow::anyhow!(
+                "Unexpected response to identities request: {:?}",
+                response
+            )),
+        }
+    }
+
+    /// Forwards a sign request unchanged and returns the encoded signature.
+    pub async fn sign(
+        &self,
+        key_blob: Vec<u8>,
+        data: Vec<u8>,
+        flags: u32,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let request = Request::SignRequest {
+            key_blob,
+            data,
+            flags,
+        };
+        match self.request(&request, SIGN_TIMEOUT).await? {
+            Response::SignResponse(signature) => Ok(signature),
+            response => Err(anyhow::anyhow!(
+                "Upstream agent did not sign: {:?}",
+                response
+            )),
+        }
+    }
+
+    async fn request(
+        &self,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Response, anyhow::Error> {
+        tokio::time::timeout(timeout, async {
+            let mut stream = connect(&self.path).await?;
+            exchange(&mut stream, request).await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("Upstream agent did not respond in time"))?
+    }
+}
+
+async fn exchange<S>(stream: &mut S, request: &Request) -> Result<Response, anyhow::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(&request.to_bytes()?).await?;
+    let length = stream.read_u32().await? as usize;
+    if length == 0 || length > MAX_MESSAGE_LENGTH {
+        return Err(anyhow::anyhow!("Invalid message length: {}", length));
+    }
+    let mut message = vec![0u8; length];
+    stream.read_exact(&mut message).await?;
+    Response::parse(&message)
+}
+
+#[cfg(unix)]
+async fn connect(path: &Path) -> Result<tokio::net::UnixStream, anyhow::Error> {
+    Ok(tokio::net::UnixStream::connect(path).await?)
+}
+
+#[cfg(windows)]
+async fn connect(
+    path: &Path,
+) -> Result<tokio::net::windows::named_pipe::NamedPipeClient, anyhow::Error> {
+    Ok(tokio::net::windows::named_pipe::ClientOptions::new().open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exchange_reads_framed_response() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let length = server.read_u32().await.unwrap() as usize;
+            let mut message = vec![0u8; length];
+            server.read_exact(&mut message).await.unwrap();
+            assert!(matches!(
+                Request::parse(&message).unwrap(),
+                Request::RequestIdentities
+            ));
+            let response = Response::Identities(vec![(vec![1, 2, 3], "token".to_string())]);
+            server
+                .write_all(&response.to_bytes().unwrap())
+                .await
+                .unwrap();
+        });
+
+        let response = exchange(&mut client, &Request::RequestIdentities)
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            Response::Identities(vec![(vec![1, 2, 3], "token".to_string())])
+        );
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstream_fails() {
+        let upstream = UpstreamAgent::new(std::env::temp_dir().join("no-such-agent.sock"));
+        assert!(upstream.request_identities().await.is_err());
+    }
+}
@@ -1,43 +1,62 @@
-use bitwarden_russh::ssh_agent;
 pub mod named_pipe_listener_stream;
 
-use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicBool, AtomicU32},
-        Arc, RwLock,
-    },
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32},
+    Arc,
 };
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
-use super::{BitwardenDesktopAgent, SshAgentUIRequest};
+use super::{
+    audit_log::AuditLog, listeners::ListenerConfig, server, upstream_from_env,
+    BitwardenDesktopAgent, SshAgentUIRequest, UI_RESPONSE_TIMEOUT,
+};
 
 impl BitwardenDesktopAgent {
     pub async fn start_server(
         auth_request_tx: tokio::sync::mpsc::Sender<SshAgentUIRequest>,
         auth_response_rx: Arc<Mutex<tokio::sync::broadcast::Receiver<(u32, bool)>>>,
+        audit_log: Option<AuditLog>,
     ) -> Result<Self, anyhow::Error> {
         let agent_state = BitwardenDesktopAgent {
-            keystore: ssh_agent::KeyStore(Arc::new(RwLock::new(HashMap::new()))),
+            keystore: server::KeyStore::default(),
             show_ui_request_tx: auth_request_tx,
             get_ui_response_rx: auth_response_rx,
             cancellation_token: CancellationToken::new(),
             request_id: Arc::new(AtomicU32::new(0)),
             needs_unlock: Arc::new(AtomicBool::new(true)),
-            is_running: Arc::new(AtomicBool::new(true)),
+            is_running: Arc::new(AtomicBool::new(false)),
+            passphrase_lock: Default::default(),
+            audit_log,
+            upstream: upstream_from_env(std::path::Path::new(
+                named_pipe_listener_stream::PIPE_NAME,
+            )),
+            socket_path: None,
+            listeners: Default::default(),
+            listener: None,
+            verification_window: Default::default(),
+            metrics: Default::default(),
+            ui_response_timeout: UI_RESPONSE_TIMEOUT,
+            rate_limiter: Default::default(),
         };
+        agent_state.listen()?;
+        Ok(agent_state)
+    }
+
+    /// Creates the named pipe and serves it until the agent is stopped
+    pub(super) fn listen(&self) -> Result<(), anyhow::Error> {
         let stream = named_pipe_listener_stream::NamedPipeServerStream::new(
-            agent_state.cancellation_token.clone(),
-            agent_state.is_running.clone(),
-        );
+            named_pipe_listener_stream::PIPE_NAME.to_string(),
+            self.cancellation_token.clone(),
+            self.is_running.clone(),
+        )
+        .map_err(|e| pipe_in_use(named_pipe_listener_stream::PIPE_NAME, e))?;
+        self.is_running
+            .store(true, std::sync::atomic::Ordering::Relaxed);
 
-        let cloned_agent_state = agent_state.clone();
+        let cloned_agent_state = self.clone();
         tokio::spawn(async move {
-            cloned_agent_state
-                .is_running
-                .store(true, std::sync::atomic::Ordering::Relaxed);
-            let _ = ssh_agent::serve(
+            let _ = server::serve(
                 stream,
                 cloned_agent_state.clone(),
                 cloned_agent_state.keystore.clone(),
@@ -48,6 +67,81 @@ impl BitwardenDesktopAgent {
                 .is_running
                 .store(false, std::sync::atomic::Ordering::Relaxed);
         });
-        Ok(agent_state)
+        Ok(())
+    }
+
+    /// Name of the named pipe, which ssh accepts as `IdentityAgent`
+    pub fn socket_path(&self) -> Option<std::path::PathBuf> {
+        Some(named_pipe_listener_stream::PIPE_NAME.into())
+    }
+
+    /// Creates the named pipe of a listener and serves it until the token is cancelled
+    pub(super) fn serve_listener(
+        &self,
+        config: Arc<ListenerConfig>,
+        cancellation_token: CancellationToken,
+    ) -> Result<(), anyhow::Error> {
+        let pipe_name = config.path.to_string_lossy().to_string();
+        let stream = named_pipe_listener_stream::NamedPipeServerStream::new(
+            pipe_name.clone(),
+            cancellation_token.clone(),
+            // A listener failing to create its pipe does not stop the agent
+            Arc::new(AtomicBool::new(true)),
+        )
+        .map_err(|e| pipe_in_use(&pipe_name, e))?;
+        let agent = BitwardenDesktopAgent {
+            cancellation_token: cancellation_token.clone(),
+            listener: Some(config.clone()),
+            ..self.clone()
+        };
+        tokio::spawn(async move {
+            let keystore = agent.keystore.clone();
+            let _ = server::serve(stream, agent, keystore, cancellation_token).await;
+            println!("[SSH Agent Native Module] Listener {} exited", config.name);
+        });
+        Ok(())
+    }
+}
+
+/// Creating the first instance of a pipe fails with access denied if another server already holds it
+fn pipe_in_use(pipe_name: &str, error: std::io::Error) -> anyhow::Error {
+    anyhow::anyhow!(
+        "[SSH Agent Native Module] Could not create {}, another agent is likely listening on it: {}",
+        pipe_name,
+        error
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    use super::*;
+
+    // Restarting blocks the calling thread, the runtime needs other threads to close the old pipe instances
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restart_after_stop_with_open_connection() {
+        let (request_tx, _request_rx) = tokio::sync::mpsc::channel(1);
+        let (_response_tx, response_rx) = tokio::sync::broadcast::channel(1);
+        let mut agent = BitwardenDesktopAgent::start_server(
+            request_tx,
+            Arc::new(Mutex::new(response_rx)),
+            None,
+        )
+        .await
+        .unwrap();
+        let _connection = ClientOptions::new()
+            .open(named_pipe_listener_stream::PIPE_NAME)
+            .unwrap();
+
+        agent.stop();
+        assert!(!agent.is_running());
+        agent.restart().unwrap();
+        assert!(agent.is_running());
+        ClientOptions::new()
+            .open(named_pipe_listener_stream::PIPE_NAME)
+            .unwrap();
+
+        agent.stop();
     }
 }
@@ -41,15 +41,103 @@ export declare namespace clipboards {
   export function write(text: string, password: boolean): Promise<void>
 }
 export declare namespace sshagent {
-  export interface PrivateKey {
+  export interface AgentKey {
+    cipherId: string
+    name: string
     privateKey: string
+    folderId?: string
+    /** tags used to select the keys a listener serves */
+    tags?: Array<string>
+    /** disabled keys are removed from the agent */
+    enabled: boolean
+    /** SHA256 fingerprints of the host keys the key may sign in to, any host is allowed if empty */
+    allowedHostKeys?: Array<string>
+    /** restricts what the key may sign, the key is unrestricted if not set */
+    policy?: SshKeyUsagePolicy
+    /** OpenSSH encoded certificates issued for the key, served next to the plain key */
+    certificates?: Array<string>
+    /** whether every use has to be verified by the OS (Windows Hello, polkit) after the app approved it */
+    requireUserVerification?: boolean
+  }
+  export const enum SshConfirmationPolicy {
+    /** every signature has to be approved in the app */
+    Always = 0,
+    /** signatures are made without asking, usage policies and allowed host keys still apply */
+    Never = 1
+  }
+  /**
+   * An additional socket exposing a subset of the keys. A key is served if it matches any of the
+   * filters, every key is served if no filter is set.
+   */
+  export interface SshAgentListener {
     name: string
+    /** path of the unix socket, or name of the named pipe on Windows */
+    path: string
+    cipherIds?: Array<string>
+    folderIds?: Array<string>
+    tags?: Array<string>
+    confirmation: SshConfirmationPolicy
+  }
+  export interface SshConfigKey {
+    cipherId: string
+    name: string
+    publicKey: string
+    /** `Host` patterns the key is offered to, exclusively */
+    hostPatterns: Array<string>
+  }
+  export interface SshConfigUpdate {
+    /** the snippet to `Include` from `~/.ssh/config` */
+    configPath: string
+    /** files that were created or whose contents changed */
+    written: Array<string>
+    /** public key files of keys that are no longer in the vault */
+    removed: Array<string>
+  }
+  export const enum SshKeySyncStatus {
+    /** the key was not served before */
+    Added = 0,
+    /** the private key or the settings of the key changed */
+    Updated = 1,
+    /** the agent already served the key as is */
+    Unchanged = 2,
+    /** the key is disabled and no longer served */
+    Disabled = 3,
+    /** the private key could not be parsed, the key is not served */
+    ParsingError = 4,
+    /** another vault item holds the same private key, the key is only served for that item */
+    Duplicate = 5
+  }
+  export interface SshKeySyncResult {
     cipherId: string
+    status: SshKeySyncStatus
+    /** why the private key could not be parsed, or which vault item holds the same key */
+    error?: string
+  }
+  export interface SshKeyUsagePolicy {
+    /** whether the key may be used to authenticate to servers */
+    allowAuthentication: boolean
+    /** SSHSIG namespaces (e.g. git) the key may sign for */
+    allowedNamespaces: Array<string>
+    /** whether the key may decrypt age files encrypted to it */
+    allowDecryption: boolean
+    /** whether the key may sign OpenSSH certificates as a CA */
+    allowCertificateSigning: boolean
+  }
+  export interface SshKeyFingerprints {
+    /** `SHA256:` followed by the unpadded base64 digest */
+    sha256: string
+    /** `MD5:` followed by the colon separated hex digest */
+    md5: string
+    /** The "drunken bishop" randomart box, as printed by `ssh-keygen -lv` */
+    randomart: string
+    /** The bubble-babble encoding, as printed by `ssh-keygen -B` */
+    bubbleBabble: string
   }
   export interface SshKey {
     privateKey: string
     publicKey: string
     keyFingerprint: string
+    fingerprints: SshKeyFingerprints
   }
   export const enum SshKeyImportStatus {
     /** ssh key was parsed correctly and will be returned in the result */
@@ -67,12 +155,279 @@ export declare namespace sshagent {
     status: SshKeyImportStatus
     sshKey?: SshKey
   }
-  export function serve(callback: (err: Error | null, arg0: string | undefined | null, arg1: boolean, arg2: string) => any): Promise<SshAgentState>
+  export const enum SshKeyFileFormat {
+    OpenSsh = 0,
+    Pkcs1 = 1,
+    Pkcs8 = 2,
+    Putty = 3
+  }
+  export interface SshScannedKeyFile {
+    path: string
+    format: SshKeyFileFormat
+    /** Whether the key is protected by a passphrase, in which case the status is `PasswordRequired` */
+    encrypted: boolean
+    /** OpenSSH encoded public key, if it is known without the passphrase */
+    publicKey?: string
+    status: SshKeyImportStatus
+    sshKey?: SshKey
+  }
+  export const enum SshKeyExportFormat {
+    /** OpenSSH private key format, encrypted with aes256-ctr and bcrypt-pbkdf */
+    OpenSsh = 0,
+    /** PKCS#8 PEM, encrypted with PBES2 (PBKDF2-HMAC-SHA256, AES-256-CBC) */
+    Pkcs8 = 1,
+    /** PuTTY PPK version 3, encrypted with aes256-cbc and Argon2id */
+    Putty = 2
+  }
+  export const enum SshSignPurposeType {
+    /** public key authentication to a server */
+    UserAuth = 0,
+    /** an SSHSIG signature, such as a git commit signature */
+    SshSig = 1,
+    /** the data is neither an authentication request nor an SSHSIG signature */
+    Unknown = 2,
+    /** not a signature: the app asks to decrypt an age file encrypted to the key */
+    AgeDecryption = 3,
+    /** the app asks to sign an OpenSSH certificate with the key as the CA */
+    Certificate = 4
+  }
+  export interface SshSignPurpose {
+    purposeType: SshSignPurposeType
+    username?: string
+    service?: string
+    publicKeyAlgorithm?: string
+    namespace?: string
+    hashAlgorithm?: string
+    keyId?: string
+    principals?: Array<string>
+    isHostCertificate?: boolean
+    /** signing data of an unknown format could be an attempt to forge a signature for another protocol */
+    isSuspicious: boolean
+  }
+  export interface SshAgentUIRequest {
+    cipherId?: string
+    isList: boolean
+    processName: string
+    /** SHA256 fingerprint of the host key of the server the signature is for */
+    hostKeyFingerprint?: string
+    /** names the destination host key is known under in known_hosts */
+    hostNames: Array<string>
+    isForwarding: boolean
+    purpose?: SshSignPurpose
+    /** certificate the client signs with, not set if it uses the plain key */
+    certificate?: SshCertificateInfo
+    /** key an agent client (`ssh-add`) asks to import into the vault */
+    importRequest?: SshKeyImportRequest
+    /** whether an agent client asks to remove the vault key `cipher_id` */
+    isRemove: boolean
+    /** name of the listener the request arrived on, not set for the default socket */
+    listenerName?: string
+    /** not a request but a notice: requests of the program are denied without prompting for a while */
+    blockedProcess?: SshBlockedProcess
+  }
+  export interface SshBlockedProcess {
+    /** path of the executable, or the process name if it is unknown */
+    executable: string
+    processName: string
+    /** seconds since the unix epoch at which requests are prompted for again */
+    blockedUntil: number
+  }
+  export interface SshCertificateInfo {
+    /** e.g. `ssh-ed25519-cert-v01@openssh.com` */
+    algorithm: string
+    keyId: string
+    /** decimal, as serials can exceed the range of JS numbers */
+    serial: string
+    isHostCertificate: boolean
+    /** users or hosts the certificate is valid for, any if empty */
+    principals: Array<string>
+    /** unix timestamp in seconds */
+    validAfter: number
+    /** unix timestamp in seconds, not set if the certificate never expires */
+    validBefore?: number
+    /** SHA256 fingerprint of the CA key */
+    caFingerprint: string
+    /** `name value`, or only the name for options without a value */
+    criticalOptions: Array<string>
+    extensions: Array<string>
+  }
+  export interface SshKeyImportRequest {
+    sshKey: SshKey
+    comment: string
+    /** seconds the client asked the key to be usable for (`ssh-add -t`) */
+    lifetime?: number
+    /** whether the client asked for every use to be confirmed (`ssh-add -c`) */
+    confirm: boolean
+  }
+  export const enum SshAuditOperation {
+    List = 0,
+    Sign = 1,
+    Add = 2,
+    Remove = 3,
+    Lock = 4,
+    Unlock = 5,
+    Decrypt = 6
+  }
+  export const enum SshAuditDecision {
+    AutoAllowed = 0,
+    UserApproved = 1,
+    Denied = 2,
+    TimedOut = 3,
+    /** the app approved, but the user verification by the OS failed */
+    VerificationFailed = 4
+  }
+  export interface SshAuditEntry {
+    /** seconds since the unix epoch */
+    timestamp: number
+    operation: SshAuditOperation
+    decision: SshAuditDecision
+    keyName?: string
+    fingerprint?: string
+    cipherId?: string
+    processName: string
+    pid: number
+    uid: number
+    /** what the signed data was decoded as, only set for sign operations */
+    purpose?: string
+  }
+  export interface SshAuditFilter {
+    cipherId?: string
+    processName?: string
+    operation?: SshAuditOperation
+    decision?: SshAuditDecision
+    /** only return entries at or after this unix timestamp in seconds */
+    since?: number
+    /** maximum number of entries, the most recent entries are returned */
+    limit?: number
+  }
+  export function serve(callback: (err: Error | null, arg: SshAgentUIRequest) => any, auditLogPath?: string | undefined | null): Promise<SshAgentState>
   export function stop(agentState: SshAgentState): void
+  /** Stops the agent if it is running and serves the socket again. The keys have to be set again afterwards. */
+  export function restart(agentState: SshAgentState): void
   export function isRunning(agentState: SshAgentState): boolean
-  export function setKeys(agentState: SshAgentState, newKeys: Array<PrivateKey>): void
-  export function lock(agentState: SshAgentState): void
+  /** Sets the native handle of the app window, in which Windows Hello verifies the user for keys requiring it */
+  export function setVerificationWindow(agentState: SshAgentState, window: Buffer): void
+  /** Whether an agent client locked the agent with a passphrase (`ssh-add -x`) */
+  export function isPassphraseLocked(agentState: SshAgentState): boolean
+  /** Adds or updates vault keys, keeping the keys that did not change. Returns one result per key. */
+  export function upsertKeys(agentState: SshAgentState, keys: Array<AgentKey>): Array<SshKeySyncResult>
+  /** Stops serving the keys of the given vault items */
+  export function removeKeys(agentState: SshAgentState, cipherIds: Array<string>): void
+  /** Serves an additional socket exposing the keys selected by the listener's filters */
+  export function addListener(agentState: SshAgentState, listener: SshAgentListener): void
+  /** Stops serving the listener and removes its socket */
+  export function removeListener(agentState: SshAgentState, name: string): void
+  export function listeners(agentState: SshAgentState): Array<SshAgentListener>
+  /**
+   * Writes the public keys to `~/.ssh/bitwarden` and generates a config snippet using the agent socket,
+   * to be included from `~/.ssh/config`. The ssh directory defaults to `~/.ssh`.
+   */
+  export function writeSshConfig(agentState: SshAgentState, keys: Array<SshConfigKey>, sshDirectory?: string | undefined | null): SshConfigUpdate
+  /** Returns the recorded agent operations matching the filter, newest first */
+  export function queryAuditLog(agentState: SshAgentState, filter: SshAuditFilter): Array<SshAuditEntry>
+  export interface SshRequestCount {
+    operation: SshAuditOperation
+    count: number
+  }
+  export interface SshKeyUsage {
+    cipherId?: string
+    name: string
+    fingerprint: string
+    /** signatures and decryptions allowed with the key this session */
+    useCount: number
+    /** requests for the key that were denied or timed out this session */
+    deniedCount: number
+    /** seconds since the unix epoch, kept when the session counters are reset */
+    lastUsed?: number
+    /** process that last used the key */
+    lastUsedBy?: string
+  }
+  export interface SshAgentStatus {
+    isRunning: boolean
+    /** socket or named pipe the agent listens on */
+    socketPath?: string
+    activeConnections: number
+    /** connections accepted this session */
+    totalConnections: number
+    /** requests handled this session, by operation */
+    requests: Array<SshRequestCount>
+    /** requests denied or timed out this session */
+    deniedRequests: number
+    /** keys used or asked for this session, most recently used first */
+    keys: Array<SshKeyUsage>
+    /** seconds since the unix epoch at which the session counters were last reset */
+    sessionStarted: number
+  }
+  /** Snapshot of the connections, requests and key usage of the agent */
+  export function agentStatus(agentState: SshAgentState): SshAgentStatus
+  /** Programs whose requests are currently denied without prompting, after repeatedly being denied */
+  export function blockedProcesses(agentState: SshAgentState): Array<SshBlockedProcess>
+  /** Prompts for the requests of a blocked program again. Returns whether it was blocked. */
+  export function unblockProcess(agentState: SshAgentState, executable: string): boolean
+  /** Resets the request and connection counters of the session, e.g. when the system is locked */
+  export function resetSessionMetrics(agentState: SshAgentState): void
+  export interface SshVerifiedSignature {
+    /** Principals of every allowed signer entry that accepts the signature */
+    principals: Array<string>
+    /** SHA256 fingerprint of the signing key */
+    fingerprint: string
+  }
+  /** Signs the data with a vault key as an armored SSHSIG signature, after the same confirmation as agent clients */
+  export function signSshsig(agentState: SshAgentState, cipherId: string, namespace: string, data: Buffer): Promise<string>
+  /**
+   * Verifies an armored SSHSIG signature against the contents of an `allowed_signers` file and returns the
+   * principals it is valid for. Fails if the signature is invalid or no allowed signer accepts it.
+   */
+  export function verifySshsig(signatureArmored: string, allowedSigners: string, namespace: string, data: Buffer): SshVerifiedSignature
+  /** Key id, principals and validity of an OpenSSH encoded certificate, to show next to the key it belongs to */
+  export function certificateInfo(certificate: string): SshCertificateInfo
+  export interface SshCertificateOptions {
+    isHostCertificate: boolean
+    keyId: string
+    /** decimal, as serials can exceed the range of JS numbers; 0 if not set */
+    serial?: string
+    /** users or host names the certificate is valid for, any if empty */
+    principals: Array<string>
+    /** unix timestamp in seconds, valid from any time if not set */
+    validAfter?: number
+    /** unix timestamp in seconds, the certificate never expires if not set */
+    validBefore?: number
+    /** e.g. `force-command`, only allowed in user certificates */
+    criticalOptions?: Record<string, string>
+    /** defaults to the extensions `ssh-keygen` grants user certificates */
+    extensions?: Array<string>
+  }
+  /**
+   * Signs an OpenSSH certificate for the public key with a vault key as the CA, after the same confirmation as
+   * agent clients. Returns the certificate in the OpenSSH format, as written to `id_*-cert.pub` files.
+   */
+  export function issueCertificate(agentState: SshAgentState, caCipherId: string, publicKey: string, options: SshCertificateOptions): Promise<string>
+  /** Encrypts a file to SSH public keys (`ssh-ed25519` or `ssh-rsa`) in the age format, armored or binary */
+  export function ageEncryptFile(recipients: Array<string>, inputPath: string, outputPath: string, armored: boolean): Promise<void>
+  /**
+   * Decrypts an age file encrypted to a vault key, after the same confirmation as agent clients. Returns the
+   * cipher id of the key that decrypted it.
+   */
+  export function ageDecryptFile(agentState: SshAgentState, inputPath: string, outputPath: string): Promise<string>
+  /**
+   * Removes the plaintext keys from memory. With a wrapping secret (biometric secret or PIN), the keys stay
+   * encrypted under a session key, which is returned wrapped by the secret for `unlock`. Stretching the
+   * secret takes a while, so it runs off the main thread.
+   */
+  export function lock(agentState: SshAgentState, wrappingSecret?: string | undefined | null): Promise<string | null>
+  /** Restores the keys sealed by `lock` */
+  export function unlock(agentState: SshAgentState, wrappedSessionKey: string, wrappingSecret: string): Promise<void>
   export function importKey(encodedKey: string, password: string): SshKeyImportResult
+  /**
+   * Finds and imports the private key files in a directory, `~/.ssh` by default. Encrypted keys are reported
+   * as `PasswordRequired` and can be imported with `import_key_file`.
+   */
+  export function scanSshDirectory(path?: string | undefined | null): Array<SshScannedKeyFile>
+  export function importKeyFile(path: string, password: string): SshKeyImportResult
+  /** Computes the fingerprints of an OpenSSH encoded public key, e.g. of a key already stored in the vault */
+  export function keyFingerprints(publicKey: string): SshKeyFingerprints
+  /** Exports an OpenSSH encoded private key to a file format, encrypted with the passphrase unless it is empty */
+  export function exportKey(privateKey: string, format: SshKeyExportFormat, passphrase: string): string
   export function clearKeys(agentState: SshAgentState): void
   export class SshAgentState {   }
 }
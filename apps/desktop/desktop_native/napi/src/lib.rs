@@ -155,11 +155,16 @@ pub mod clipboards {
 
 #[napi]
 pub mod sshagent {
-    use std::sync::Arc;
+    use std::{io::Write, sync::Arc};
 
+    use desktop_core::ssh_agent::audit_log::{
+        AuditDecision, AuditEntry, AuditFilter, AuditLog, AuditOperation,
+    };
+    use desktop_core::ssh_agent::listeners::ConfirmationPolicy;
     use napi::{
-        bindgen_prelude::Promise,
+        bindgen_prelude::{Buffer, Promise},
         threadsafe_function::{ErrorStrategy::CalleeHandled, ThreadsafeFunction},
+        Env, JsObject,
     };
     use tokio::{self, sync::Mutex};
 
@@ -169,10 +174,231 @@ pub mod sshagent {
     }
 
     #[napi(object)]
-    pub struct PrivateKey {
+    pub struct AgentKey {
+        pub cipher_id: String,
+        pub name: String,
         pub private_key: String,
+        pub folder_id: Option<String>,
+        /// tags used to select the keys a listener serves
+        pub tags: Option<Vec<String>>,
+        /// disabled keys are removed from the agent
+        pub enabled: bool,
+        /// SHA256 fingerprints of the host keys the key may sign in to, any host is allowed if empty
+        pub allowed_host_keys: Option<Vec<String>>,
+        /// restricts what the key may sign, the key is unrestricted if not set
+        pub policy: Option<SshKeyUsagePolicy>,
+        /// OpenSSH encoded certificates issued for the key, served next to the plain key
+        pub certificates: Option<Vec<String>>,
+        /// whether every use has to be verified by the OS (Windows Hello, polkit) after the app approved it
+        pub require_user_verification: Option<bool>,
+    }
+
+    impl From<AgentKey> for desktop_core::ssh_agent::AgentKey {
+        fn from(key: AgentKey) -> Self {
+            desktop_core::ssh_agent::AgentKey {
+                cipher_id: key.cipher_id,
+                name: key.name,
+                private_key: key.private_key,
+                folder_id: key.folder_id,
+                tags: key.tags.unwrap_or_default(),
+                enabled: key.enabled,
+                allowed_host_keys: key.allowed_host_keys.unwrap_or_default(),
+                policy: key.policy.as_ref().map(|policy| policy.into()),
+                certificates: key.certificates.unwrap_or_default(),
+                require_user_verification: key.require_user_verification.unwrap_or(false),
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshConfirmationPolicy {
+        /// every signature has to be approved in the app
+        Always,
+        /// signatures are made without asking, usage policies and allowed host keys still apply
+        Never,
+    }
+
+    /// An additional socket exposing a subset of the keys. A key is served if it matches any of the
+    /// filters, every key is served if no filter is set.
+    #[napi(object)]
+    pub struct SshAgentListener {
         pub name: String,
+        /// path of the unix socket, or name of the named pipe on Windows
+        pub path: String,
+        pub cipher_ids: Option<Vec<String>>,
+        pub folder_ids: Option<Vec<String>>,
+        pub tags: Option<Vec<String>>,
+        pub confirmation: SshConfirmationPolicy,
+    }
+
+    impl From<SshAgentListener> for desktop_core::ssh_agent::listeners::ListenerConfig {
+        fn from(listener: SshAgentListener) -> Self {
+            desktop_core::ssh_agent::listeners::ListenerConfig {
+                name: listener.name,
+                path: listener.path.into(),
+                filter: desktop_core::ssh_agent::listeners::KeyFilter {
+                    cipher_ids: listener.cipher_ids.unwrap_or_default(),
+                    folder_ids: listener.folder_ids.unwrap_or_default(),
+                    tags: listener.tags.unwrap_or_default(),
+                },
+                confirmation: match listener.confirmation {
+                    SshConfirmationPolicy::Always => ConfirmationPolicy::Always,
+                    SshConfirmationPolicy::Never => ConfirmationPolicy::Never,
+                },
+            }
+        }
+    }
+
+    impl From<desktop_core::ssh_agent::listeners::ListenerConfig> for SshAgentListener {
+        fn from(listener: desktop_core::ssh_agent::listeners::ListenerConfig) -> Self {
+            SshAgentListener {
+                name: listener.name,
+                path: listener.path.to_string_lossy().to_string(),
+                cipher_ids: Some(listener.filter.cipher_ids),
+                folder_ids: Some(listener.filter.folder_ids),
+                tags: Some(listener.filter.tags),
+                confirmation: match listener.confirmation {
+                    ConfirmationPolicy::Always => SshConfirmationPolicy::Always,
+                    ConfirmationPolicy::Never => SshConfirmationPolicy::Never,
+                },
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshConfigKey {
         pub cipher_id: String,
+        pub name: String,
+        pub public_key: String,
+        /// `Host` patterns the key is offered to, exclusively
+        pub host_patterns: Vec<String>,
+    }
+
+    impl From<SshConfigKey> for desktop_core::ssh_agent::ssh_config::ConfigKey {
+        fn from(key: SshConfigKey) -> Self {
+            desktop_core::ssh_agent::ssh_config::ConfigKey {
+                cipher_id: key.cipher_id,
+                name: key.name,
+                public_key: key.public_key,
+                host_patterns: key.host_patterns,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshConfigUpdate {
+        /// the snippet to `Include` from `~/.ssh/config`
+        pub config_path: String,
+        /// files that were created or whose contents changed
+        pub written: Vec<String>,
+        /// public key files of keys that are no longer in the vault
+        pub removed: Vec<String>,
+    }
+
+    impl From<desktop_core::ssh_agent::ssh_config::SshConfigUpdate> for SshConfigUpdate {
+        fn from(update: desktop_core::ssh_agent::ssh_config::SshConfigUpdate) -> Self {
+            let to_string = |path: std::path::PathBuf| path.to_string_lossy().to_string();
+            SshConfigUpdate {
+                config_path: to_string(update.config_path),
+                written: update.written.into_iter().map(to_string).collect(),
+                removed: update.removed.into_iter().map(to_string).collect(),
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshKeySyncStatus {
+        /// the key was not served before
+        Added,
+        /// the private key or the settings of the key changed
+        Updated,
+        /// the agent already served the key as is
+        Unchanged,
+        /// the key is disabled and no longer served
+        Disabled,
+        /// the private key could not be parsed, the key is not served
+        ParsingError,
+        /// another vault item holds the same private key, the key is only served for that item
+        Duplicate,
+    }
+
+    impl From<desktop_core::ssh_agent::KeySyncStatus> for SshKeySyncStatus {
+        fn from(status: desktop_core::ssh_agent::KeySyncStatus) -> Self {
+            match status {
+                desktop_core::ssh_agent::KeySyncStatus::Added => SshKeySyncStatus::Added,
+                desktop_core::ssh_agent::KeySyncStatus::Updated => SshKeySyncStatus::Updated,
+                desktop_core::ssh_agent::KeySyncStatus::Unchanged => SshKeySyncStatus::Unchanged,
+                desktop_core::ssh_agent::KeySyncStatus::Disabled => SshKeySyncStatus::Disabled,
+                desktop_core::ssh_agent::KeySyncStatus::ParsingError => {
+                    SshKeySyncStatus::ParsingError
+                }
+                desktop_core::ssh_agent::KeySyncStatus::Duplicate => SshKeySyncStatus::Duplicate,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshKeySyncResult {
+        pub cipher_id: String,
+        pub status: SshKeySyncStatus,
+        /// why the private key could not be parsed, or which vault item holds the same key
+        pub error: Option<String>,
+    }
+
+    impl From<desktop_core::ssh_agent::KeySyncResult> for SshKeySyncResult {
+        fn from(result: desktop_core::ssh_agent::KeySyncResult) -> Self {
+            SshKeySyncResult {
+                cipher_id: result.cipher_id,
+                status: result.status.into(),
+                error: result.error,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshKeyUsagePolicy {
+        /// whether the key may be used to authenticate to servers
+        pub allow_authentication: bool,
+        /// SSHSIG namespaces (e.g. git) the key may sign for
+        pub allowed_namespaces: Vec<String>,
+        /// whether the key may decrypt age files encrypted to it
+        pub allow_decryption: bool,
+        /// whether the key may sign OpenSSH certificates as a CA
+        pub allow_certificate_signing: bool,
+    }
+
+    impl From<&SshKeyUsagePolicy> for desktop_core::ssh_agent::policy::KeyUsagePolicy {
+        fn from(policy: &SshKeyUsagePolicy) -> Self {
+            desktop_core::ssh_agent::policy::KeyUsagePolicy {
+                allow_authentication: policy.allow_authentication,
+                allowed_namespaces: policy.allowed_namespaces.clone(),
+                allow_decryption: policy.allow_decryption,
+                allow_certificate_signing: policy.allow_certificate_signing,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshKeyFingerprints {
+        /// `SHA256:` followed by the unpadded base64 digest
+        pub sha256: String,
+        /// `MD5:` followed by the colon separated hex digest
+        pub md5: String,
+        /// The "drunken bishop" randomart box, as printed by `ssh-keygen -lv`
+        pub randomart: String,
+        /// The bubble-babble encoding, as printed by `ssh-keygen -B`
+        pub bubble_babble: String,
+    }
+
+    impl From<desktop_core::ssh_agent::fingerprint::KeyFingerprints> for SshKeyFingerprints {
+        fn from(fingerprints: desktop_core::ssh_agent::fingerprint::KeyFingerprints) -> Self {
+            SshKeyFingerprints {
+                sha256: fingerprints.sha256,
+                md5: fingerprints.md5,
+                randomart: fingerprints.randomart,
+                bubble_babble: fingerprints.bubble_babble,
+            }
+        }
     }
 
     #[napi(object)]
@@ -180,6 +406,7 @@ pub mod sshagent {
         pub private_key: String,
         pub public_key: String,
         pub key_fingerprint: String,
+        pub fingerprints: SshKeyFingerprints,
     }
 
     impl From<desktop_core::ssh_agent::importer::SshKey> for SshKey {
@@ -188,6 +415,7 @@ pub mod sshagent {
                 private_key: key.private_key,
                 public_key: key.public_key,
                 key_fingerprint: key.key_fingerprint,
+                fingerprints: key.fingerprints.into(),
             }
         }
     }
@@ -243,9 +471,419 @@ pub mod sshagent {
         }
     }
 
+    #[napi]
+    pub enum SshKeyFileFormat {
+        OpenSsh,
+        Pkcs1,
+        Pkcs8,
+        Putty,
+    }
+
+    impl From<desktop_core::ssh_agent::scanner::KeyFileFormat> for SshKeyFileFormat {
+        fn from(format: desktop_core::ssh_agent::scanner::KeyFileFormat) -> Self {
+            match format {
+                desktop_core::ssh_agent::scanner::KeyFileFormat::OpenSsh => {
+                    SshKeyFileFormat::OpenSsh
+                }
+                desktop_core::ssh_agent::scanner::KeyFileFormat::Pkcs1 => SshKeyFileFormat::Pkcs1,
+                desktop_core::ssh_agent::scanner::KeyFileFormat::Pkcs8 => SshKeyFileFormat::Pkcs8,
+                desktop_core::ssh_agent::scanner::KeyFileFormat::Putty => SshKeyFileFormat::Putty,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshScannedKeyFile {
+        pub path: String,
+        pub format: SshKeyFileFormat,
+        /// Whether the key is protected by a passphrase, in which case the status is `PasswordRequired`
+        pub encrypted: bool,
+        /// OpenSSH encoded public key, if it is known without the passphrase
+        pub public_key: Option<String>,
+        pub status: SshKeyImportStatus,
+        pub ssh_key: Option<SshKey>,
+    }
+
+    impl From<desktop_core::ssh_agent::scanner::ScannedKeyFile> for SshScannedKeyFile {
+        fn from(file: desktop_core::ssh_agent::scanner::ScannedKeyFile) -> Self {
+            SshScannedKeyFile {
+                path: file.path.to_string_lossy().to_string(),
+                format: file.format.into(),
+                encrypted: file.encrypted,
+                public_key: file.public_key,
+                status: file.result.status.into(),
+                ssh_key: file.result.ssh_key.map(|k| k.into()),
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshKeyExportFormat {
+        /// OpenSSH private key format, encrypted with aes256-ctr and bcrypt-pbkdf
+        OpenSsh,
+        /// PKCS#8 PEM, encrypted with PBES2 (PBKDF2-HMAC-SHA256, AES-256-CBC)
+        Pkcs8,
+        /// PuTTY PPK version 3, encrypted with aes256-cbc and Argon2id
+        Putty,
+    }
+
+    impl From<SshKeyExportFormat> for desktop_core::ssh_agent::exporter::ExportFormat {
+        fn from(format: SshKeyExportFormat) -> Self {
+            match format {
+                SshKeyExportFormat::OpenSsh => {
+                    desktop_core::ssh_agent::exporter::ExportFormat::OpenSsh
+                }
+                SshKeyExportFormat::Pkcs8 => desktop_core::ssh_agent::exporter::ExportFormat::Pkcs8,
+                SshKeyExportFormat::Putty => desktop_core::ssh_agent::exporter::ExportFormat::Putty,
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshSignPurposeType {
+        /// public key authentication to a server
+        UserAuth,
+        /// an SSHSIG signature, such as a git commit signature
+        SshSig,
+        /// the data is neither an authentication request nor an SSHSIG signature
+        Unknown,
+        /// not a signature: the app asks to decrypt an age file encrypted to the key
+        AgeDecryption,
+        /// the app asks to sign an OpenSSH certificate with the key as the CA
+        Certificate,
+    }
+
+    #[napi(object)]
+    pub struct SshSignPurpose {
+        pub purpose_type: SshSignPurposeType,
+        pub username: Option<String>,
+        pub service: Option<String>,
+        pub public_key_algorithm: Option<String>,
+        pub namespace: Option<String>,
+        pub hash_algorithm: Option<String>,
+        pub key_id: Option<String>,
+        pub principals: Option<Vec<String>>,
+        pub is_host_certificate: Option<bool>,
+        /// signing data of an unknown format could be an attempt to forge a signature for another protocol
+        pub is_suspicious: bool,
+    }
+
+    impl From<desktop_core::ssh_agent::sign_purpose::SignPurpose> for SshSignPurpose {
+        fn from(purpose: desktop_core::ssh_agent::sign_purpose::SignPurpose) -> Self {
+            let is_suspicious = purpose.is_suspicious();
+            let mut result = SshSignPurpose {
+                purpose_type: SshSignPurposeType::Unknown,
+                username: None,
+                service: None,
+                public_key_algorithm: None,
+                namespace: None,
+                hash_algorithm: None,
+                key_id: None,
+                principals: None,
+                is_host_certificate: None,
+                is_suspicious,
+            };
+            match purpose {
+                desktop_core::ssh_agent::sign_purpose::SignPurpose::UserAuth {
+                    username,
+                    service,
+                    public_key_algorithm,
+                    ..
+                } => {
+                    result.purpose_type = SshSignPurposeType::UserAuth;
+                    result.username = Some(username);
+                    result.service = Some(service);
+                    result.public_key_algorithm = Some(public_key_algorithm);
+                }
+                desktop_core::ssh_agent::sign_purpose::SignPurpose::SshSig {
+                    namespace,
+                    hash_algorithm,
+                } => {
+                    result.purpose_type = SshSignPurposeType::SshSig;
+                    result.namespace = Some(namespace);
+                    result.hash_algorithm = Some(hash_algorithm);
+                }
+                desktop_core::ssh_agent::sign_purpose::SignPurpose::AgeDecryption => {
+                    result.purpose_type = SshSignPurposeType::AgeDecryption;
+                }
+                desktop_core::ssh_agent::sign_purpose::SignPurpose::Certificate {
+                    key_id,
+                    principals,
+                    is_host_certificate,
+                } => {
+                    result.purpose_type = SshSignPurposeType::Certificate;
+                    result.key_id = Some(key_id);
+                    result.principals = Some(principals);
+                    result.is_host_certificate = Some(is_host_certificate);
+                }
+                desktop_core::ssh_agent::sign_purpose::SignPurpose::Unknown => {}
+            }
+            result
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshAgentUIRequest {
+        pub cipher_id: Option<String>,
+        pub is_list: bool,
+        pub process_name: String,
+        /// SHA256 fingerprint of the host key of the server the signature is for
+        pub host_key_fingerprint: Option<String>,
+        /// names the destination host key is known under in known_hosts
+        pub host_names: Vec<String>,
+        pub is_forwarding: bool,
+        pub purpose: Option<SshSignPurpose>,
+        /// certificate the client signs with, not set if it uses the plain key
+        pub certificate: Option<SshCertificateInfo>,
+        /// key an agent client (`ssh-add`) asks to import into the vault
+        pub import_request: Option<SshKeyImportRequest>,
+        /// whether an agent client asks to remove the vault key `cipher_id`
+        pub is_remove: bool,
+        /// name of the listener the request arrived on, not set for the default socket
+        pub listener_name: Option<String>,
+        /// not a request but a notice: requests of the program are denied without prompting for a while
+        pub blocked_process: Option<SshBlockedProcess>,
+    }
+
+    #[napi(object)]
+    pub struct SshBlockedProcess {
+        /// path of the executable, or the process name if it is unknown
+        pub executable: String,
+        pub process_name: String,
+        /// seconds since the unix epoch at which requests are prompted for again
+        pub blocked_until: i64,
+    }
+
+    impl From<desktop_core::ssh_agent::rate_limit::BlockedProcess> for SshBlockedProcess {
+        fn from(blocked: desktop_core::ssh_agent::rate_limit::BlockedProcess) -> Self {
+            SshBlockedProcess {
+                executable: blocked.executable,
+                process_name: blocked.process_name,
+                blocked_until: blocked.blocked_until as i64,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshCertificateInfo {
+        /// e.g. `ssh-ed25519-cert-v01@openssh.com`
+        pub algorithm: String,
+        pub key_id: String,
+        /// decimal, as serials can exceed the range of JS numbers
+        pub serial: String,
+        pub is_host_certificate: bool,
+        /// users or hosts the certificate is valid for, any if empty
+        pub principals: Vec<String>,
+        /// unix timestamp in seconds
+        pub valid_after: i64,
+        /// unix timestamp in seconds, not set if the certificate never expires
+        pub valid_before: Option<i64>,
+        /// SHA256 fingerprint of the CA key
+        pub ca_fingerprint: String,
+        /// `name value`, or only the name for options without a value
+        pub critical_options: Vec<String>,
+        pub extensions: Vec<String>,
+    }
+
+    impl From<desktop_core::ssh_agent::certificate::CertificateInfo> for SshCertificateInfo {
+        fn from(info: desktop_core::ssh_agent::certificate::CertificateInfo) -> Self {
+            SshCertificateInfo {
+                algorithm: info.algorithm,
+                key_id: info.key_id,
+                serial: info.serial.to_string(),
+                is_host_certificate: info.is_host_certificate,
+                principals: info.principals,
+                valid_after: i64::try_from(info.valid_after).unwrap_or(i64::MAX),
+                valid_before: i64::try_from(info.valid_before).ok(),
+                ca_fingerprint: info.ca_fingerprint,
+                critical_options: info
+                    .critical_options
+                    .into_iter()
+                    .map(|(name, value)| match value.is_empty() {
+                        true => name,
+                        false => format!("{} {}", name, value),
+                    })
+                    .collect(),
+                extensions: info.extensions,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshKeyImportRequest {
+        pub ssh_key: SshKey,
+        pub comment: String,
+        /// seconds the client asked the key to be usable for (`ssh-add -t`)
+        pub lifetime: Option<u32>,
+        /// whether the client asked for every use to be confirmed (`ssh-add -c`)
+        pub confirm: bool,
+    }
+
+    impl From<desktop_core::ssh_agent::ImportRequest> for SshKeyImportRequest {
+        fn from(request: desktop_core::ssh_agent::ImportRequest) -> Self {
+            SshKeyImportRequest {
+                ssh_key: request.ssh_key.into(),
+                comment: request.comment,
+                lifetime: request.constraints.lifetime,
+                confirm: request.constraints.confirm,
+            }
+        }
+    }
+
+    impl From<desktop_core::ssh_agent::SshAgentUIRequest> for SshAgentUIRequest {
+        fn from(request: desktop_core::ssh_agent::SshAgentUIRequest) -> Self {
+            SshAgentUIRequest {
+                cipher_id: request.cipher_id,
+                is_list: request.is_list,
+                process_name: request.process_name,
+                host_key_fingerprint: request.host_key_fingerprint,
+                host_names: request.host_names,
+                is_forwarding: request.is_forwarding,
+                purpose: request.purpose.map(|purpose| purpose.into()),
+                certificate: request.certificate.map(|certificate| certificate.into()),
+                import_request: request.import_request.map(|request| request.into()),
+                is_remove: request.is_remove,
+                listener_name: request.listener_name,
+                blocked_process: request.blocked_process.map(|blocked| blocked.into()),
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshAuditOperation {
+        List,
+        Sign,
+        Add,
+        Remove,
+        Lock,
+        Unlock,
+        Decrypt,
+    }
+
+    impl From<AuditOperation> for SshAuditOperation {
+        fn from(operation: AuditOperation) -> Self {
+            match operation {
+                AuditOperation::List => SshAuditOperation::List,
+                AuditOperation::Sign => SshAuditOperation::Sign,
+                AuditOperation::Add => SshAuditOperation::Add,
+                AuditOperation::Remove => SshAuditOperation::Remove,
+                AuditOperation::Lock => SshAuditOperation::Lock,
+                AuditOperation::Unlock => SshAuditOperation::Unlock,
+                AuditOperation::Decrypt => SshAuditOperation::Decrypt,
+            }
+        }
+    }
+
+    impl From<SshAuditOperation> for AuditOperation {
+        fn from(operation: SshAuditOperation) -> Self {
+            match operation {
+                SshAuditOperation::List => AuditOperation::List,
+                SshAuditOperation::Sign => AuditOperation::Sign,
+                SshAuditOperation::Add => AuditOperation::Add,
+                SshAuditOperation::Remove => AuditOperation::Remove,
+                SshAuditOperation::Lock => AuditOperation::Lock,
+                SshAuditOperation::Unlock => AuditOperation::Unlock,
+                SshAuditOperation::Decrypt => AuditOperation::Decrypt,
+            }
+        }
+    }
+
+    #[napi]
+    pub enum SshAuditDecision {
+        AutoAllowed,
+        UserApproved,
+        Denied,
+        TimedOut,
+        /// the app approved, but the user verification by the OS failed
+        VerificationFailed,
+    }
+
+    impl From<AuditDecision> for SshAuditDecision {
+        fn from(decision: AuditDecision) -> Self {
+            match decision {
+                AuditDecision::AutoAllowed => SshAuditDecision::AutoAllowed,
+                AuditDecision::UserApproved => SshAuditDecision::UserApproved,
+                AuditDecision::Denied => SshAuditDecision::Denied,
+                AuditDecision::TimedOut => SshAuditDecision::TimedOut,
+                AuditDecision::VerificationFailed => SshAuditDecision::VerificationFailed,
+            }
+        }
+    }
+
+    impl From<SshAuditDecision> for AuditDecision {
+        fn from(decision: SshAuditDecision) -> Self {
+            match decision {
+                SshAuditDecision::AutoAllowed => AuditDecision::AutoAllowed,
+                SshAuditDecision::UserApproved => AuditDecision::UserApproved,
+                SshAuditDecision::Denied => AuditDecision::Denied,
+                SshAuditDecision::TimedOut => AuditDecision::TimedOut,
+                SshAuditDecision::VerificationFailed => AuditDecision::VerificationFailed,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshAuditEntry {
+        /// seconds since the unix epoch
+        pub timestamp: i64,
+        pub operation: SshAuditOperation,
+        pub decision: SshAuditDecision,
+        pub key_name: Option<String>,
+        pub fingerprint: Option<String>,
+        pub cipher_id: Option<String>,
+        pub process_name: String,
+        pub pid: u32,
+        pub uid: u32,
+        /// what the signed data was decoded as, only set for sign operations
+        pub purpose: Option<String>,
+    }
+
+    impl From<AuditEntry> for SshAuditEntry {
+        fn from(entry: AuditEntry) -> Self {
+            SshAuditEntry {
+                timestamp: entry.timestamp as i64,
+                operation: entry.operation.into(),
+                decision: entry.decision.into(),
+                key_name: entry.key_name,
+                fingerprint: entry.fingerprint,
+                cipher_id: entry.cipher_id,
+                process_name: entry.process_name,
+                pid: entry.pid,
+                uid: entry.uid,
+                purpose: entry.purpose,
+            }
+        }
+    }
+
+    #[napi(object)]
+    pub struct SshAuditFilter {
+        pub cipher_id: Option<String>,
+        pub process_name: Option<String>,
+        pub operation: Option<SshAuditOperation>,
+        pub decision: Option<SshAuditDecision>,
+        /// only return entries at or after this unix timestamp in seconds
+        pub since: Option<i64>,
+        /// maximum number of entries, the most recent entries are returned
+        pub limit: Option<u32>,
+    }
+
+    impl From<SshAuditFilter> for AuditFilter {
+        fn from(filter: SshAuditFilter) -> Self {
+            AuditFilter {
+                cipher_id: filter.cipher_id,
+                process_name: filter.process_name,
+                operation: filter.operation.map(|operation| operation.into()),
+                decision: filter.decision.map(|decision| decision.into()),
+                since: filter.since.map(|since| since.max(0) as u64),
+                limit: filter.limit.map(|limit| limit as usize),
+            }
+        }
+    }
+
     #[napi]
     pub async fn serve(
-        callback: ThreadsafeFunction<(Option<String>, bool, String), CalleeHandled>,
+        callback: ThreadsafeFunction<SshAgentUIRequest, CalleeHandled>,
+        audit_log_path: Option<String>,
     ) -> napi::Result<SshAgentState> {
         let (auth_request_tx, mut auth_request_rx) =
             tokio::sync::mpsc::channel::<desktop_core::ssh_agent::SshAgentUIRequest>(32);
@@ -261,20 +899,16 @@ pub mod sshagent {
                 tokio::spawn(async move {
                     let auth_response_tx_arc = cloned_response_tx_arc;
                     let callback = cloned_callback;
-                    let promise_result: Result<Promise<bool>, napi::Error> = callback
-                        .call_async(Ok((
-                            request.cipher_id,
-                            request.is_list,
-                            request.process_name,
-                        )))
-                        .await;
+                    let request_id = request.request_id;
+                    let promise_result: Result<Promise<bool>, napi::Error> =
+                        callback.call_async(Ok(request.into())).await;
                     match promise_result {
                         Ok(promise_result) => match promise_result.await {
                             Ok(result) => {
                                 let _ = auth_response_tx_arc
                                     .lock()
                                     .await
-                                    .send((request.request_id, result))
+                                    .send((request_id, result))
                                     .expect("should be able to send auth response to agent");
                             }
                             Err(e) => {
@@ -282,7 +916,7 @@ pub mod sshagent {
                                 let _ = auth_response_tx_arc
                                     .lock()
                                     .await
-                                    .send((request.request_id, false))
+                                    .send((request_id, false))
                                     .expect("should be able to send auth response to agent");
                             }
                         },
@@ -291,7 +925,7 @@ pub mod sshagent {
                             let _ = auth_response_tx_arc
                                 .lock()
                                 .await
-                                .send((request.request_id, false))
+                                .send((request_id, false))
                                 .expect("should be able to send auth response to agent");
                         }
                     }
@@ -302,6 +936,7 @@ pub mod sshagent {
         match desktop_core::ssh_agent::BitwardenDesktopAgent::start_server(
             auth_request_tx,
             Arc::new(Mutex::new(auth_response_rx)),
+            audit_log_path.map(|path| AuditLog::new(path.into())),
         )
         .await
         {
@@ -317,35 +952,477 @@ pub mod sshagent {
         Ok(())
     }
 
+    /// Stops the agent if it is running and serves the socket again. The keys have to be set again afterwards.
+    #[napi]
+    pub fn restart(agent_state: &mut SshAgentState) -> napi::Result<()> {
+        let bitwarden_agent_state = &mut agent_state.state;
+        napi::bindgen_prelude::within_runtime_if_available(|| bitwarden_agent_state.restart())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
     #[napi]
     pub fn is_running(agent_state: &mut SshAgentState) -> bool {
         let bitwarden_agent_state = agent_state.state.clone();
         bitwarden_agent_state.is_running()
     }
 
+    /// Sets the native handle of the app window, in which Windows Hello verifies the user for keys requiring it
     #[napi]
-    pub fn set_keys(
+    pub fn set_verification_window(agent_state: &mut SshAgentState, window: Buffer) {
+        agent_state.state.set_verification_window(window.into());
+    }
+
+    /// Whether an agent client locked the agent with a passphrase (`ssh-add -x`)
+    #[napi]
+    pub fn is_passphrase_locked(agent_state: &mut SshAgentState) -> bool {
+        let bitwarden_agent_state = agent_state.state.clone();
+        bitwarden_agent_state.is_passphrase_locked()
+    }
+
+    /// Adds or updates vault keys, keeping the keys that did not change. Returns one result per key.
+    #[napi]
+    pub fn upsert_keys(
+        agent_state: &mut SshAgentState,
+        keys: Vec<AgentKey>,
+    ) -> napi::Result<Vec<SshKeySyncResult>> {
+        let bitwarden_agent_state = &mut agent_state.state;
+        let results = bitwarden_agent_state
+            .upsert_keys(keys.into_iter().map(|key| key.into()).collect())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        Ok(results.into_iter().map(|result| result.into()).collect())
+    }
+
+    /// Stops serving the keys of the given vault items
+    #[napi]
+    pub fn remove_keys(
         agent_state: &mut SshAgentState,
-        new_keys: Vec<PrivateKey>,
+        cipher_ids: Vec<String>,
     ) -> napi::Result<()> {
         let bitwarden_agent_state = &mut agent_state.state;
         bitwarden_agent_state
-            .set_keys(
-                new_keys
+            .remove_keys(&cipher_ids)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Serves an additional socket exposing the keys selected by the listener's filters
+    #[napi]
+    pub fn add_listener(
+        agent_state: &mut SshAgentState,
+        listener: SshAgentListener,
+    ) -> napi::Result<()> {
+        let bitwarden_agent_state = &agent_state.state;
+        napi::bindgen_prelude::within_runtime_if_available(|| {
+            bitwarden_agent_state.add_listener(listener.into())
+        })
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Stops serving the listener and removes its socket
+    #[napi]
+    pub fn remove_listener(agent_state: &mut SshAgentState, name: String) -> napi::Result<()> {
+        let bitwarden_agent_state = &agent_state.state;
+        bitwarden_agent_state
+            .remove_listener(&name)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub fn listeners(agent_state: &mut SshAgentState) -> Vec<SshAgentListener> {
+        let bitwarden_agent_state = &agent_state.state;
+        bitwarden_agent_state
+            .listeners()
+            .into_iter()
+            .map(|listener| listener.into())
+            .collect()
+    }
+
+    /// Writes the public keys to `~/.ssh/bitwarden` and generates a config snippet using the agent socket,
+    /// to be included from `~/.ssh/config`. The ssh directory defaults to `~/.ssh`.
+    #[napi]
+    pub fn write_ssh_config(
+        agent_state: &mut SshAgentState,
+        keys: Vec<SshConfigKey>,
+        ssh_directory: Option<String>,
+    ) -> napi::Result<SshConfigUpdate> {
+        let bitwarden_agent_state = &agent_state.state;
+        let agent_socket = bitwarden_agent_state
+            .socket_path()
+            .ok_or_else(|| napi::Error::from_reason("The SSH agent is not running"))?;
+        let ssh_directory = match ssh_directory {
+            Some(ssh_directory) => ssh_directory.into(),
+            None => desktop_core::ssh_agent::ssh_config::default_ssh_directory()
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?,
+        };
+        desktop_core::ssh_agent::ssh_config::write_ssh_config(
+            &ssh_directory,
+            &agent_socket,
+            &keys.into_iter().map(|key| key.into()).collect::<Vec<_>>(),
+        )
+        .map(|update| update.into())
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Returns the recorded agent operations matching the filter, newest first
+    #[napi]
+    pub fn query_audit_log(
+        agent_state: &mut SshAgentState,
+        filter: SshAuditFilter,
+    ) -> napi::Result<Vec<SshAuditEntry>> {
+        let bitwarden_agent_state = &agent_state.state;
+        let entries = bitwarden_agent_state
+            .query_audit_log(&filter.into())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+        Ok(entries.into_iter().map(|entry| entry.into()).collect())
+    }
+
+    #[napi(object)]
+    pub struct SshRequestCount {
+        pub operation: SshAuditOperation,
+        pub count: i64,
+    }
+
+    #[napi(object)]
+    pub struct SshKeyUsage {
+        pub cipher_id: Option<String>,
+        pub name: String,
+        pub fingerprint: String,
+        /// signatures and decryptions allowed with the key this session
+        pub use_count: i64,
+        /// requests for the key that were denied or timed out this session
+        pub denied_count: i64,
+        /// seconds since the unix epoch, kept when the session counters are reset
+        pub last_used: Option<i64>,
+        /// process that last used the key
+        pub last_used_by: Option<String>,
+    }
+
+    #[napi(object)]
+    pub struct SshAgentStatus {
+        pub is_running: bool,
+        /// socket or named pipe the agent listens on
+        pub socket_path: Option<String>,
+        pub active_connections: i64,
+        /// connections accepted this session
+        pub total_connections: i64,
+        /// requests handled this session, by operation
+        pub requests: Vec<SshRequestCount>,
+        /// requests denied or timed out this session
+        pub denied_requests: i64,
+        /// keys used or asked for this session, most recently used first
+        pub keys: Vec<SshKeyUsage>,
+        /// seconds since the unix epoch at which the session counters were last reset
+        pub session_started: i64,
+    }
+
+    impl From<desktop_core::ssh_agent::metrics::AgentStatus> for SshAgentStatus {
+        fn from(status: desktop_core::ssh_agent::metrics::AgentStatus) -> Self {
+            SshAgentStatus {
+                is_running: status.is_running,
+                socket_path: status.socket_path,
+                active_connections: status.active_connections as i64,
+                total_connections: status.total_connections as i64,
+                requests: status
+                    .requests
+                    .into_iter()
+                    .map(|(operation, count)| SshRequestCount {
+                        operation: operation.into(),
+                        count: count as i64,
+                    })
+                    .collect(),
+                denied_requests: status.denied_requests as i64,
+                keys: status
+                    .keys
+                    .into_iter()
+                    .map(|usage| SshKeyUsage {
+                        cipher_id: usage.cipher_id,
+                        name: usage.name,
+                        fingerprint: usage.fingerprint,
+                        use_count: usage.use_count as i64,
+                        denied_count: usage.denied_count as i64,
+                        last_used: usage.last_used.map(|timestamp| timestamp as i64),
+                        last_used_by: usage.last_used_by,
+                    })
+                    .collect(),
+                session_started: status.session_started as i64,
+            }
+        }
+    }
+
+    /// Snapshot of the connections, requests and key usage of the agent
+    #[napi]
+    pub fn agent_status(agent_state: &mut SshAgentState) -> SshAgentStatus {
+        agent_state.state.status().into()
+    }
+
+    /// Programs whose requests are currently denied without prompting, after repeatedly being denied
+    #[napi]
+    pub fn blocked_processes(agent_state: &mut SshAgentState) -> Vec<SshBlockedProcess> {
+        agent_state
+            .state
+            .blocked_processes()
+            .into_iter()
+            .map(|blocked| blocked.into())
+            .collect()
+    }
+
+    /// Prompts for the requests of a blocked program again. Returns whether it was blocked.
+    #[napi]
+    pub fn unblock_process(agent_state: &mut SshAgentState, executable: String) -> bool {
+        agent_state.state.unblock_process(&executable)
+    }
+
+    /// Resets the request and connection counters of the session, e.g. when the system is locked
+    #[napi]
+    pub fn reset_session_metrics(agent_state: &mut SshAgentState) {
+        agent_state.state.reset_session_metrics();
+    }
+
+    #[napi(object)]
+    pub struct SshVerifiedSignature {
+        /// Principals of every allowed signer entry that accepts the signature
+        pub principals: Vec<String>,
+        /// SHA256 fingerprint of the signing key
+        pub fingerprint: String,
+    }
+
+    /// Signs the data with a vault key as an armored SSHSIG signature, after the same confirmation as agent clients
+    #[napi(ts_return_type = "Promise<string>")]
+    pub fn sign_sshsig(
+        env: Env,
+        agent_state: &mut SshAgentState,
+        cipher_id: String,
+        namespace: String,
+        data: Buffer,
+    ) -> napi::Result<JsObject> {
+        let bitwarden_agent_state = agent_state.state.clone();
+        let data = data.to_vec();
+        env.spawn_future(async move {
+            bitwarden_agent_state
+                .sign_sshsig(&cipher_id, &namespace, &data)
+                .await
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        })
+    }
+
+    /// Verifies an armored SSHSIG signature against the contents of an `allowed_signers` file and returns the
+    /// principals it is valid for. Fails if the signature is invalid or no allowed signer accepts it.
+    #[napi]
+    pub fn verify_sshsig(
+        signature_armored: String,
+        allowed_signers: String,
+        namespace: String,
+        data: Buffer,
+    ) -> napi::Result<SshVerifiedSignature> {
+        desktop_core::ssh_agent::sshsig::verify_sshsig(
+            &signature_armored,
+            &allowed_signers,
+            &namespace,
+            &data,
+        )
+        .map(|verified| SshVerifiedSignature {
+            principals: verified.principals,
+            fingerprint: verified.fingerprint,
+        })
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Key id, principals and validity of an OpenSSH encoded certificate, to show next to the key it belongs to
+    #[napi]
+    pub fn certificate_info(certificate: String) -> napi::Result<SshCertificateInfo> {
+        desktop_core::ssh_agent::certificate::CertificateInfo::from_openssh(&certificate)
+            .map(|info| info.into())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi(object)]
+    pub struct SshCertificateOptions {
+        pub is_host_certificate: bool,
+        pub key_id: String,
+        /// decimal, as serials can exceed the range of JS numbers; 0 if not set
+        pub serial: Option<String>,
+        /// users or host names the certificate is valid for, any if empty
+        pub principals: Vec<String>,
+        /// unix timestamp in seconds, valid from any time if not set
+        pub valid_after: Option<i64>,
+        /// unix timestamp in seconds, the certificate never expires if not set
+        pub valid_before: Option<i64>,
+        /// e.g. `force-command`, only allowed in user certificates
+        pub critical_options: Option<std::collections::HashMap<String, String>>,
+        /// defaults to the extensions `ssh-keygen` grants user certificates
+        pub extensions: Option<Vec<String>>,
+    }
+
+    impl TryFrom<SshCertificateOptions> for desktop_core::ssh_agent::certificate::CertificateOptions {
+        type Error = napi::Error;
+
+        fn try_from(options: SshCertificateOptions) -> napi::Result<Self> {
+            let timestamp = |timestamp: i64| {
+                u64::try_from(timestamp)
+                    .map_err(|_| napi::Error::from_reason("Invalid certificate validity"))
+            };
+            let serial = match options.serial {
+                Some(serial) => serial
+                    .parse()
+                    .map_err(|_| napi::Error::from_reason("Invalid certificate serial"))?,
+                None => 0,
+            };
+            let extensions = match (options.extensions, options.is_host_certificate) {
+                (Some(extensions), _) => extensions,
+                (None, true) => Vec::new(),
+                (None, false) => desktop_core::ssh_agent::certificate::DEFAULT_USER_EXTENSIONS
                     .iter()
-                    .map(|k| (k.private_key.clone(), k.name.clone(), k.cipher_id.clone()))
+                    .map(|extension| extension.to_string())
                     .collect(),
-            )
-            .map_err(|e| napi::Error::from_reason(e.to_string()))?;
-        Ok(())
+            };
+            Ok(desktop_core::ssh_agent::certificate::CertificateOptions {
+                is_host_certificate: options.is_host_certificate,
+                key_id: options.key_id,
+                serial,
+                principals: options.principals,
+                valid_after: options.valid_after.map(timestamp).transpose()?.unwrap_or(0),
+                valid_before: options
+                    .valid_before
+                    .map(timestamp)
+                    .transpose()?
+                    .unwrap_or(u64::MAX),
+                critical_options: options
+                    .critical_options
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                extensions,
+            })
+        }
     }
 
+    /// Signs an OpenSSH certificate for the public key with a vault key as the CA, after the same confirmation as
+    /// agent clients. Returns the certificate in the OpenSSH format, as written to `id_*-cert.pub` files.
+    #[napi(ts_return_type = "Promise<string>")]
+    pub fn issue_certificate(
+        env: Env,
+        agent_state: &mut SshAgentState,
+        ca_cipher_id: String,
+        public_key: String,
+        options: SshCertificateOptions,
+    ) -> napi::Result<JsObject> {
+        let bitwarden_agent_state = agent_state.state.clone();
+        let options: desktop_core::ssh_agent::certificate::CertificateOptions =
+            options.try_into()?;
+        env.spawn_future(async move {
+            bitwarden_agent_state
+                .issue_certificate(&ca_cipher_id, &public_key, &options)
+                .await
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        })
+    }
+
+    /// Encrypts a file to SSH public keys (`ssh-ed25519` or `ssh-rsa`) in the age format, armored or binary
     #[napi]
-    pub fn lock(agent_state: &mut SshAgentState) -> napi::Result<()> {
-        let bitwarden_agent_state = &mut agent_state.state;
-        bitwarden_agent_state
-            .lock()
+    pub async fn age_encrypt_file(
+        recipients: Vec<String>,
+        input_path: String,
+        output_path: String,
+        armored: bool,
+    ) -> napi::Result<()> {
+        tokio::task::spawn_blocking(move || {
+            let input = std::fs::File::open(&input_path)?;
+            write_output(&output_path, |output| {
+                desktop_core::ssh_agent::age_encryption::encrypt(
+                    &recipients,
+                    armored,
+                    input,
+                    output,
+                )
+            })
+        })
+        .await
+        .map_err(|e| napi::Error::from_reason(e.to_string()))?
+        .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Decrypts an age file encrypted to a vault key, after the same confirmation as agent clients. Returns the
+    /// cipher id of the key that decrypted it.
+    #[napi(ts_return_type = "Promise<string>")]
+    pub fn age_decrypt_file(
+        env: Env,
+        agent_state: &mut SshAgentState,
+        input_path: String,
+        output_path: String,
+    ) -> napi::Result<JsObject> {
+        let bitwarden_agent_state = agent_state.state.clone();
+        env.spawn_future(async move {
+            let decrypt = async {
+                let input = std::fs::File::open(&input_path)?;
+                let mut decryptor = bitwarden_agent_state.decrypt_age(input).await?;
+                let cipher_id = decryptor.cipher_id.clone();
+                tokio::task::spawn_blocking(move || {
+                    write_output(&output_path, |output| {
+                        std::io::copy(&mut decryptor, output)?;
+                        Ok(())
+                    })
+                })
+                .await??;
+                Ok::<_, anyhow::Error>(cipher_id)
+            };
+            decrypt
+                .await
+                .map_err(|e| napi::Error::from_reason(e.to_string()))
+        })
+    }
+
+    /// Streams into a new file, which is removed again if writing fails part way
+    fn write_output(
+        path: &str,
+        write: impl FnOnce(&mut std::io::BufWriter<std::fs::File>) -> Result<(), anyhow::Error>,
+    ) -> Result<(), anyhow::Error> {
+        let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let result = write(&mut output).and_then(|()| {
+            output.flush()?;
+            Ok(output.get_ref().sync_all()?)
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    /// Removes the plaintext keys from memory. With a wrapping secret (biometric secret or PIN), the keys stay
+    /// encrypted under a session key, which is returned wrapped by the secret for `unlock`. Stretching the
+    /// secret takes a while, so it runs off the main thread.
+    #[napi(ts_return_type = "Promise<string | null>")]
+    pub fn lock(
+        env: Env,
+        agent_state: &mut SshAgentState,
+        wrapping_secret: Option<String>,
+    ) -> napi::Result<JsObject> {
+        let mut bitwarden_agent_state = agent_state.state.clone();
+        env.spawn_future(async move {
+            tokio::task::spawn_blocking(move || {
+                bitwarden_agent_state.lock(wrapping_secret.as_deref().map(str::as_bytes))
+            })
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+        })
+    }
+
+    /// Restores the keys sealed by `lock`
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn unlock(
+        env: Env,
+        agent_state: &mut SshAgentState,
+        wrapped_session_key: String,
+        wrapping_secret: String,
+    ) -> napi::Result<JsObject> {
+        let mut bitwarden_agent_state = agent_state.state.clone();
+        env.spawn_future(async move {
+            tokio::task::spawn_blocking(move || {
+                bitwarden_agent_state.unlock(&wrapped_session_key, wrapping_secret.as_bytes())
+            })
+            .await
+            .map_err(|e| napi::Error::from_reason(e.to_string()))?
             .map_err(|e| napi::Error::from_reason(e.to_string()))
+        })
     }
 
     #[napi]
@@ -355,6 +1432,46 @@ pub mod sshagent {
         Ok(result.into())
     }
 
+    /// Finds and imports the private key files in a directory, `~/.ssh` by default. Encrypted keys are reported
+    /// as `PasswordRequired` and can be imported with `import_key_file`.
+    #[napi]
+    pub fn scan_ssh_directory(path: Option<String>) -> napi::Result<Vec<SshScannedKeyFile>> {
+        let path = match path {
+            Some(path) => path.into(),
+            None => desktop_core::ssh_agent::ssh_config::default_ssh_directory()
+                .map_err(|e| napi::Error::from_reason(e.to_string()))?,
+        };
+        desktop_core::ssh_agent::scanner::scan_ssh_directory(&path)
+            .map(|files| files.into_iter().map(|file| file.into()).collect())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    #[napi]
+    pub fn import_key_file(path: String, password: String) -> napi::Result<SshKeyImportResult> {
+        desktop_core::ssh_agent::scanner::import_key_file(std::path::Path::new(&path), &password)
+            .map(|result| result.into())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Computes the fingerprints of an OpenSSH encoded public key, e.g. of a key already stored in the vault
+    #[napi]
+    pub fn key_fingerprints(public_key: String) -> napi::Result<SshKeyFingerprints> {
+        desktop_core::ssh_agent::fingerprint::KeyFingerprints::from_openssh(&public_key)
+            .map(|fingerprints| fingerprints.into())
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
+    /// Exports an OpenSSH encoded private key to a file format, encrypted with the passphrase unless it is empty
+    #[napi]
+    pub fn export_key(
+        private_key: String,
+        format: SshKeyExportFormat,
+        passphrase: String,
+    ) -> napi::Result<String> {
+        desktop_core::ssh_agent::exporter::export_key(&private_key, format.into(), &passphrase)
+            .map_err(|e| napi::Error::from_reason(e.to_string()))
+    }
+
     #[napi]
     pub fn clear_keys(agent_state: &mut SshAgentState) -> napi::Result<()> {
         let bitwarden_agent_state = &mut agent_state.state;
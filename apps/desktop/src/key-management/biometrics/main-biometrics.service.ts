@@ -148,6 +148,45 @@ export class MainBiometricsService extends DesktopBiometricsService {
     );
   }
 
+  /**
+   * Stores a secret of a user under biometric protection, next to the user's biometric unlock key.
+   * The value must be a serialized symmetric key, as the Windows key store only accepts those.
+   * @param userId the user the secret belongs to
+   * @param name the name of the secret, unique per user
+   * @param value the secret to store
+   */
+  async setBiometricProtectedSecretForUser(
+    userId: UserId,
+    name: string,
+    value: string,
+  ): Promise<void> {
+    return await this.osBiometricsService.setBiometricKey(
+      "Bitwarden_biometric",
+      `${userId}_${name}`,
+      value,
+      this.clientKeyHalves.get(userId),
+    );
+  }
+
+  /**
+   * Gets a secret stored by `setBiometricProtectedSecretForUser`, this prompts for biometrics.
+   * @returns the secret, or null if none is stored under the name
+   */
+  async getBiometricProtectedSecretForUser(userId: UserId, name: string): Promise<string | null> {
+    return await this.osBiometricsService.getBiometricKey(
+      "Bitwarden_biometric",
+      `${userId}_${name}`,
+      this.clientKeyHalves.get(userId),
+    );
+  }
+
+  async deleteBiometricProtectedSecretForUser(userId: UserId, name: string): Promise<void> {
+    return await this.osBiometricsService.deleteBiometricKey(
+      "Bitwarden_biometric",
+      `${userId}_${name}`,
+    );
+  }
+
   /**
    * Set whether to auto-prompt the user for biometric unlock; this can be used to prevent auto-prompting being initiated by a process reload.
    * Reasons for enabling auto prompt include: Starting the app, un-minimizing the app, manually account switching
@@ -178,7 +178,7 @@ export class Main {
     this.desktopSettingsService = new DesktopSettingsService(stateProvider);
     const biometricStateService = new DefaultBiometricStateService(stateProvider);
 
-    this.biometricsService = new MainBiometricsService(
+    const mainBiometricsService = new MainBiometricsService(
       this.i18nService,
       this.windowMain,
       this.logService,
@@ -186,6 +186,7 @@ export class Main {
       process.platform,
       biometricStateService,
     );
+    this.biometricsService = mainBiometricsService;
 
     this.windowMain = new WindowMain(
       biometricStateService,
@@ -257,7 +258,12 @@ export class Main {
     this.clipboardMain = new ClipboardMain();
     this.clipboardMain.init();
 
-    this.sshAgentService = new MainSshAgentService(this.logService, this.messagingService);
+    this.sshAgentService = new MainSshAgentService(
+      this.logService,
+      this.messagingService,
+      this.windowMain,
+      mainBiometricsService,
+    );
 
     new EphemeralValueStorageService();
     new SSOLocalhostCallbackService(this.environmentService, this.messagingService);
@@ -79,6 +79,9 @@ export class MessagingMain {
       case "getWindowIsFocused":
         this.windowIsFocused();
         break;
+      case "systemLocked":
+        this.main.sshAgentService.resetSessionMetrics();
+        break;
       default:
         break;
     }
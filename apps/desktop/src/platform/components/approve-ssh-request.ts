@@ -2,11 +2,13 @@ import { DIALOG_DATA, DialogRef } from "@angular/cdk/dialog";
 import { CommonModule } from "@angular/common";
 import { Component, Inject } from "@angular/core";
 import { FormBuilder, ReactiveFormsModule } from "@angular/forms";
+import { sshagent } from "desktop_native/napi";
 
 import { JslibModule } from "@bitwarden/angular/jslib.module";
 import {
   AsyncActionsModule,
   ButtonModule,
+  CalloutModule,
   DialogModule,
   FormFieldModule,
   IconButtonModule,
@@ -14,9 +16,21 @@ import {
 } from "@bitwarden/components";
 import { CipherFormGeneratorComponent } from "@bitwarden/vault";
 
+/** What the agent could tell about the destination and purpose of a sign request */
+export interface SshRequestDetails {
+  /** names the destination host key is known under in known_hosts */
+  hostNames?: string[];
+  hostKeyFingerprint?: string;
+  isForwarding?: boolean;
+  purpose?: sshagent.SshSignPurpose;
+  /** certificate the client signs with, not set if it uses the plain key */
+  certificate?: sshagent.SshCertificateInfo;
+}
+
 export interface ApproveSshRequestParams {
   cipherName: string;
   applicationName: string;
+  details: SshRequestDetails;
 }
 
 @Component({
@@ -29,6 +43,7 @@ export interface ApproveSshRequestParams {
     JslibModule,
     CipherFormGeneratorComponent,
     ButtonModule,
+    CalloutModule,
     IconButtonModule,
     ReactiveFormsModule,
     AsyncActionsModule,
@@ -37,6 +52,7 @@ export interface ApproveSshRequestParams {
 })
 export class ApproveSshRequestComponent {
   approveSshRequestForm = this.formBuilder.group({});
+  protected readonly PurposeType = sshagent.SshSignPurposeType;
 
   constructor(
     @Inject(DIALOG_DATA) protected params: ApproveSshRequestParams,
@@ -44,15 +60,30 @@ export class ApproveSshRequestComponent {
     private formBuilder: FormBuilder,
   ) {}
 
-  static open(dialogService: DialogService, cipherName: string, applicationName: string) {
+  static open(
+    dialogService: DialogService,
+    cipherName: string,
+    applicationName: string,
+    details: SshRequestDetails = {},
+  ) {
     return dialogService.open<boolean, ApproveSshRequestParams>(ApproveSshRequestComponent, {
       data: {
         cipherName,
         applicationName,
+        details,
       },
     });
   }
 
+  /** The known_hosts names of the destination, or its host key fingerprint if it is not known */
+  protected get destination(): string | null {
+    const { hostNames, hostKeyFingerprint } = this.params.details;
+    if (hostNames != null && hostNames.length > 0) {
+      return hostNames.join(", ");
+    }
+    return hostKeyFingerprint ?? null;
+  }
+
   submit = async () => {
     this.dialogRef.close(true);
   };
@@ -1,11 +1,19 @@
 // FIXME: Update this file to be type safe and remove this and next line
 // @ts-strict-ignore
-import { ipcMain } from "electron";
+import { randomBytes } from "crypto";
+import * as path from "path";
+
+import { app, ipcMain } from "electron";
 import { concatMap, delay, filter, firstValueFrom, from, race, take, timer } from "rxjs";
 
 import { LogService } from "@bitwarden/common/platform/abstractions/log.service";
 import { MessagingService } from "@bitwarden/common/platform/abstractions/messaging.service";
+import { UserId } from "@bitwarden/common/types/guid";
 import { sshagent } from "@bitwarden/desktop-napi";
+import { BiometricsStatus } from "@bitwarden/key-management";
+
+import { MainBiometricsService } from "../../key-management/biometrics/main-biometrics.service";
+import { WindowMain } from "../../main/window.main";
 
 class AgentResponse {
   requestId: number;
@@ -16,14 +24,22 @@ class AgentResponse {
 export class MainSshAgentService {
   SIGN_TIMEOUT = 60_000;
   REQUEST_POLL_INTERVAL = 50;
+  AUDIT_LOG_FILE = "ssh-agent-audit.log";
+  WRAPPING_SECRET_NAME = "ssh_agent_wrapping_secret";
 
   private requestResponses: AgentResponse[] = [];
   private request_id = 0;
   private agentState: sshagent.SshAgentState;
+  /** Secret the keys are sealed with on the next vault lock, only held while unlocked */
+  private wrappingSecret: { userId: UserId; secret: string } | null = null;
+  /** Session key the agent sealed its keys under, wrapped by a secret in the biometric store */
+  private sealedSession: { userId: UserId; wrappedSessionKey: string } | null = null;
 
   constructor(
     private logService: LogService,
     private messagingService: MessagingService,
+    private windowMain: WindowMain,
+    private biometricsService: MainBiometricsService,
   ) {
     ipcMain.handle(
       "sshagent.importkey",
@@ -35,6 +51,51 @@ export class MainSshAgentService {
       },
     );
 
+    ipcMain.handle(
+      "sshagent.scansshdirectory",
+      async (event: any, path?: string): Promise<sshagent.SshScannedKeyFile[]> => {
+        return sshagent.scanSshDirectory(path);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.importkeyfile",
+      async (
+        event: any,
+        { path, password }: { path: string; password: string },
+      ): Promise<sshagent.SshKeyImportResult> => {
+        return sshagent.importKeyFile(path, password);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.keyfingerprints",
+      async (event: any, publicKey: string): Promise<sshagent.SshKeyFingerprints> => {
+        return sshagent.keyFingerprints(publicKey);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.certificateinfo",
+      async (event: any, certificate: string): Promise<sshagent.SshCertificateInfo> => {
+        return sshagent.certificateInfo(certificate);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.exportkey",
+      async (
+        event: any,
+        {
+          privateKey,
+          format,
+          passphrase,
+        }: { privateKey: string; format: sshagent.SshKeyExportFormat; passphrase: string },
+      ): Promise<string> => {
+        return sshagent.exportKey(privateKey, format, passphrase);
+      },
+    );
+
     ipcMain.handle("sshagent.init", async (event: any, message: any) => {
       this.init();
     });
@@ -42,56 +103,101 @@ export class MainSshAgentService {
     ipcMain.handle("sshagent.isloaded", async (event: any) => {
       return this.agentState != null;
     });
+
+    ipcMain.handle("sshagent.restart", async (event: any) => {
+      if (this.agentState != null) {
+        sshagent.restart(this.agentState);
+      }
+    });
+
+    // Stopping the agent removes its socket, so that the next start does not find a stale one
+    app.on("will-quit", () => {
+      if (this.agentState != null && sshagent.isRunning(this.agentState)) {
+        sshagent.stop(this.agentState);
+      }
+    });
+  }
+
+  /** Starts a new usage session of the agent, e.g. when the system is locked */
+  resetSessionMetrics() {
+    if (this.agentState != null) {
+      sshagent.resetSessionMetrics(this.agentState);
+    }
   }
 
   init() {
     // handle sign request passing to UI
     sshagent
-      .serve(async (err: Error, cipherId: string, isListRequest: boolean, processName: string) => {
-        // clear all old (> SIGN_TIMEOUT) requests
-        this.requestResponses = this.requestResponses.filter(
-          (response) => response.timestamp > new Date(Date.now() - this.SIGN_TIMEOUT),
-        );
+      .serve(
+        async (err: Error, request: sshagent.SshAgentUIRequest) => {
+          // a program was blocked after repeatedly denied requests, nothing to respond to
+          if (request.blockedProcess != null) {
+            this.messagingService.send("sshagent.processblocked", {
+              executable: request.blockedProcess.executable,
+              processName: request.blockedProcess.processName,
+              blockedUntil: request.blockedProcess.blockedUntil,
+            });
+            return true;
+          }
 
-        this.request_id += 1;
-        const id_for_this_request = this.request_id;
-        this.messagingService.send("sshagent.signrequest", {
-          cipherId,
-          isListRequest,
-          requestId: id_for_this_request,
-          processName,
-        });
-
-        const result = await firstValueFrom(
-          race(
-            from([false]).pipe(delay(this.SIGN_TIMEOUT)),
-
-            //poll for response
-            timer(0, this.REQUEST_POLL_INTERVAL).pipe(
-              concatMap(() => from(this.requestResponses)),
-              filter((response) => response.requestId == id_for_this_request),
-              take(1),
-              concatMap(() => from([true])),
+          // clear all old (> SIGN_TIMEOUT) requests
+          this.requestResponses = this.requestResponses.filter(
+            (response) => response.timestamp > new Date(Date.now() - this.SIGN_TIMEOUT),
+          );
+
+          this.request_id += 1;
+          const id_for_this_request = this.request_id;
+          this.messagingService.send("sshagent.signrequest", {
+            cipherId: request.cipherId,
+            isListRequest: request.isList,
+            requestId: id_for_this_request,
+            processName: request.processName,
+            hostKeyFingerprint: request.hostKeyFingerprint,
+            hostNames: request.hostNames,
+            isForwarding: request.isForwarding,
+            purpose: request.purpose,
+            certificate: request.certificate,
+            importRequest: request.importRequest,
+            isRemoveRequest: request.isRemove,
+            listenerName: request.listenerName,
+          });
+
+          const result = await firstValueFrom(
+            race(
+              from([false]).pipe(delay(this.SIGN_TIMEOUT)),
+
+              //poll for response
+              timer(0, this.REQUEST_POLL_INTERVAL).pipe(
+                concatMap(() => from(this.requestResponses)),
+                filter((response) => response.requestId == id_for_this_request),
+                take(1),
+                concatMap(() => from([true])),
+              ),
             ),
-          ),
-        );
+          );
 
-        if (!result) {
-          return false;
-        }
+          if (!result) {
+            return false;
+          }
 
-        const response = this.requestResponses.find(
-          (response) => response.requestId == id_for_this_request,
-        );
+          const response = this.requestResponses.find(
+            (response) => response.requestId == id_for_this_request,
+          );
 
-        this.requestResponses = this.requestResponses.filter(
-          (response) => response.requestId != id_for_this_request,
-        );
+          this.requestResponses = this.requestResponses.filter(
+            (response) => response.requestId != id_for_this_request,
+          );
 
-        return response.accepted;
-      })
+          return response.accepted;
+        },
+        path.join(app.getPath("userData"), this.AUDIT_LOG_FILE),
+      )
       .then((agentState: sshagent.SshAgentState) => {
         this.agentState = agentState;
+        if (this.windowMain.win != null) {
+          // Windows Hello shows the prompt of keys requiring user verification in the app window
+          sshagent.setVerificationWindow(agentState, this.windowMain.win.getNativeWindowHandle());
+        }
         this.logService.info("SSH agent started");
       })
       .catch((e) => {
@@ -99,13 +205,50 @@ export class MainSshAgentService {
       });
 
     ipcMain.handle(
-      "sshagent.setkeys",
-      async (event: any, keys: { name: string; privateKey: string; cipherId: string }[]) => {
+      "sshagent.upsertkeys",
+      async (event: any, keys: sshagent.AgentKey[]): Promise<sshagent.SshKeySyncResult[]> => {
         if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
-          sshagent.setKeys(this.agentState, keys);
+          return sshagent.upsertKeys(this.agentState, keys);
         }
+        return [];
       },
     );
+    ipcMain.handle(
+      "sshagent.addlistener",
+      async (event: any, listener: sshagent.SshAgentListener) => {
+        if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
+          sshagent.addListener(this.agentState, listener);
+        }
+      },
+    );
+    ipcMain.handle("sshagent.removelistener", async (event: any, name: string) => {
+      if (this.agentState != null) {
+        sshagent.removeListener(this.agentState, name);
+      }
+    });
+    ipcMain.handle(
+      "sshagent.listeners",
+      async (event: any): Promise<sshagent.SshAgentListener[]> => {
+        if (this.agentState == null) {
+          return [];
+        }
+        return sshagent.listeners(this.agentState);
+      },
+    );
+    ipcMain.handle(
+      "sshagent.writesshconfig",
+      async (event: any, keys: sshagent.SshConfigKey[]): Promise<sshagent.SshConfigUpdate> => {
+        if (this.agentState == null) {
+          throw new Error("SSH agent is not running");
+        }
+        return sshagent.writeSshConfig(this.agentState, keys);
+      },
+    );
+    ipcMain.handle("sshagent.removekeys", async (event: any, cipherIds: string[]) => {
+      if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
+        sshagent.removeKeys(this.agentState, cipherIds);
+      }
+    });
     ipcMain.handle(
       "sshagent.signrequestresponse",
       async (event: any, { requestId, accepted }: { requestId: number; accepted: boolean }) => {
@@ -113,16 +256,227 @@ export class MainSshAgentService {
       },
     );
 
-    ipcMain.handle("sshagent.lock", async (event: any) => {
+    ipcMain.handle(
+      "sshagent.queryauditlog",
+      async (event: any, filter: sshagent.SshAuditFilter): Promise<sshagent.SshAuditEntry[]> => {
+        if (this.agentState == null) {
+          return [];
+        }
+        return sshagent.queryAuditLog(this.agentState, filter ?? {});
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.agentstatus",
+      async (event: any): Promise<sshagent.SshAgentStatus | null> => {
+        if (this.agentState == null) {
+          return null;
+        }
+        return sshagent.agentStatus(this.agentState);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.blockedprocesses",
+      async (event: any): Promise<sshagent.SshBlockedProcess[]> => {
+        if (this.agentState == null) {
+          return [];
+        }
+        return sshagent.blockedProcesses(this.agentState);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.unblockprocess",
+      async (event: any, executable: string): Promise<boolean> => {
+        if (this.agentState == null) {
+          return false;
+        }
+        return sshagent.unblockProcess(this.agentState, executable);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.signsshsig",
+      async (
+        event: any,
+        {
+          cipherId,
+          namespace,
+          data,
+        }: { cipherId: string; namespace: string; data: Uint8Array },
+      ): Promise<string> => {
+        if (this.agentState == null) {
+          throw new Error("SSH agent is not running");
+        }
+        return await sshagent.signSshsig(this.agentState, cipherId, namespace, Buffer.from(data));
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.verifysshsig",
+      async (
+        event: any,
+        {
+          signature,
+          allowedSigners,
+          namespace,
+          data,
+        }: { signature: string; allowedSigners: string; namespace: string; data: Uint8Array },
+      ): Promise<sshagent.SshVerifiedSignature> => {
+        return sshagent.verifySshsig(signature, allowedSigners, namespace, Buffer.from(data));
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.ageencryptfile",
+      async (
+        event: any,
+        {
+          recipients,
+          inputPath,
+          outputPath,
+          armored,
+        }: { recipients: string[]; inputPath: string; outputPath: string; armored: boolean },
+      ): Promise<void> => {
+        await sshagent.ageEncryptFile(recipients, inputPath, outputPath, armored);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.agedecryptfile",
+      async (
+        event: any,
+        { inputPath, outputPath }: { inputPath: string; outputPath: string },
+      ): Promise<string> => {
+        if (this.agentState == null) {
+          throw new Error("SSH agent is not running");
+        }
+        return await sshagent.ageDecryptFile(this.agentState, inputPath, outputPath);
+      },
+    );
+
+    ipcMain.handle(
+      "sshagent.issuecertificate",
+      async (
+        event: any,
+        {
+          caCipherId,
+          publicKey,
+          options,
+        }: { caCipherId: string; publicKey: string; options: sshagent.SshCertificateOptions },
+      ): Promise<string> => {
+        if (this.agentState == null) {
+          throw new Error("SSH agent is not running");
+        }
+        return await sshagent.issueCertificate(this.agentState, caCipherId, publicKey, options);
+      },
+    );
+
+    ipcMain.handle("sshagent.lock", async (event: any, userId: UserId) => {
       if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
-        sshagent.lock(this.agentState);
+        await this.sealKeys(userId);
+      }
+    });
+
+    ipcMain.handle("sshagent.unlock", async (event: any, userId: UserId) => {
+      if (this.agentState != null && (await sshagent.isRunning(this.agentState))) {
+        await this.unsealKeys(userId);
+        await this.prepareWrappingSecret(userId);
       }
     });
 
     ipcMain.handle("sshagent.clearkeys", async (event: any) => {
+      this.wrappingSecret = null;
+      this.sealedSession = null;
       if (this.agentState != null) {
         sshagent.clearKeys(this.agentState);
       }
     });
   }
+
+  /**
+   * Seals the agent's keys under a new session key wrapped by the secret prepared on unlock, which
+   * is then only kept in the biometric store. Without a prepared secret the keys are dropped.
+   */
+  private async sealKeys(userId: UserId) {
+    if (this.sealedSession != null) {
+      return;
+    }
+
+    const wrappingSecret = this.wrappingSecret;
+    this.wrappingSecret = null;
+    if (wrappingSecret == null || wrappingSecret.userId !== userId) {
+      await sshagent.lock(this.agentState);
+      return;
+    }
+
+    try {
+      const wrappedSessionKey = await sshagent.lock(this.agentState, wrappingSecret.secret);
+      this.sealedSession = { userId, wrappedSessionKey };
+    } catch (e) {
+      this.logService.error("Failed to seal SSH keys, dropping them", e);
+      await sshagent.lock(this.agentState);
+    }
+  }
+
+  /** Restores the sealed keys with the secret from the biometric store, this prompts the user */
+  private async unsealKeys(userId: UserId) {
+    const sealedSession = this.sealedSession;
+    this.sealedSession = null;
+    if (sealedSession == null) {
+      return;
+    }
+
+    try {
+      if (sealedSession.userId !== userId) {
+        throw new Error("Keys were sealed by another user");
+      }
+      const wrappingSecret = await this.biometricsService.getBiometricProtectedSecretForUser(
+        userId,
+        this.WRAPPING_SECRET_NAME,
+      );
+      if (wrappingSecret == null) {
+        throw new Error("No wrapping secret in the biometric store");
+      }
+      await sshagent.unlock(this.agentState, sealedSession.wrappedSessionKey, wrappingSecret);
+    } catch (e) {
+      // The renderer sends the keys again on the next sync
+      this.logService.error("Failed to restore sealed SSH keys, dropping them", e);
+      await sshagent.lock(this.agentState);
+    }
+  }
+
+  /**
+   * Stores a new wrapping secret in the biometric store while the user is present, so locking the
+   * vault does not prompt. Users without biometric unlock get their keys dropped on lock instead.
+   */
+  private async prepareWrappingSecret(userId: UserId) {
+    if (this.wrappingSecret?.userId === userId) {
+      return;
+    }
+
+    this.wrappingSecret = null;
+    try {
+      const status = await this.biometricsService.getBiometricsStatusForUser(userId);
+      if (status !== BiometricsStatus.Available) {
+        await this.biometricsService.deleteBiometricProtectedSecretForUser(
+          userId,
+          this.WRAPPING_SECRET_NAME,
+        );
+        return;
+      }
+
+      // Stored as a serialized 512 bit symmetric key, the only format the Windows store accepts
+      const secret = randomBytes(64).toString("base64");
+      await this.biometricsService.setBiometricProtectedSecretForUser(
+        userId,
+        this.WRAPPING_SECRET_NAME,
+        secret,
+      );
+      this.wrappingSecret = { userId, secret };
+    } catch (e) {
+      this.logService.error("Failed to store the SSH key wrapping secret", e);
+    }
+  }
 }
@@ -53,14 +53,50 @@ const sshAgent = {
   init: async () => {
     await ipcRenderer.invoke("sshagent.init");
   },
-  setKeys: (keys: { name: string; privateKey: string; cipherId: string }[]): Promise<void> =>
-    ipcRenderer.invoke("sshagent.setkeys", keys),
+  upsertKeys: (keys: ssh.AgentKey[]): Promise<ssh.SshKeySyncResult[]> =>
+    ipcRenderer.invoke("sshagent.upsertkeys", keys),
+  removeKeys: (cipherIds: string[]): Promise<void> =>
+    ipcRenderer.invoke("sshagent.removekeys", cipherIds),
+  addListener: (listener: ssh.SshAgentListener): Promise<void> =>
+    ipcRenderer.invoke("sshagent.addlistener", listener),
+  removeListener: (name: string): Promise<void> =>
+    ipcRenderer.invoke("sshagent.removelistener", name),
+  listeners: (): Promise<ssh.SshAgentListener[]> => ipcRenderer.invoke("sshagent.listeners"),
+  writeSshConfig: (keys: ssh.SshConfigKey[]): Promise<ssh.SshConfigUpdate> =>
+    ipcRenderer.invoke("sshagent.writesshconfig", keys),
   signRequestResponse: async (requestId: number, accepted: boolean) => {
     await ipcRenderer.invoke("sshagent.signrequestresponse", { requestId, accepted });
   },
-  lock: async () => {
-    return await ipcRenderer.invoke("sshagent.lock");
+  lock: async (userId: string) => {
+    await ipcRenderer.invoke("sshagent.lock", userId);
   },
+  unlock: async (userId: string) => {
+    await ipcRenderer.invoke("sshagent.unlock", userId);
+  },
+  signSshsig: (cipherId: string, namespace: string, data: Uint8Array): Promise<string> =>
+    ipcRenderer.invoke("sshagent.signsshsig", { cipherId, namespace, data }),
+  verifySshsig: (
+    signature: string,
+    allowedSigners: string,
+    namespace: string,
+    data: Uint8Array,
+  ): Promise<ssh.SshVerifiedSignature> =>
+    ipcRenderer.invoke("sshagent.verifysshsig", { signature, allowedSigners, namespace, data }),
+  ageEncryptFile: (
+    recipients: string[],
+    inputPath: string,
+    outputPath: string,
+    armored: boolean,
+  ): Promise<void> =>
+    ipcRenderer.invoke("sshagent.ageencryptfile", { recipients, inputPath, outputPath, armored }),
+  ageDecryptFile: (inputPath: string, outputPath: string): Promise<string> =>
+    ipcRenderer.invoke("sshagent.agedecryptfile", { inputPath, outputPath }),
+  issueCertificate: (
+    caCipherId: string,
+    publicKey: string,
+    options: ssh.SshCertificateOptions,
+  ): Promise<string> =>
+    ipcRenderer.invoke("sshagent.issuecertificate", { caCipherId, publicKey, options }),
   clearKeys: async () => {
     return await ipcRenderer.invoke("sshagent.clearkeys");
   },
@@ -71,9 +107,33 @@ const sshAgent = {
     });
     return res;
   },
+  scanSshDirectory: (path?: string): Promise<ssh.SshScannedKeyFile[]> =>
+    ipcRenderer.invoke("sshagent.scansshdirectory", path),
+  importKeyFile: (path: string, password: string): Promise<ssh.SshKeyImportResult> =>
+    ipcRenderer.invoke("sshagent.importkeyfile", { path, password }),
+  keyFingerprints: (publicKey: string): Promise<ssh.SshKeyFingerprints> =>
+    ipcRenderer.invoke("sshagent.keyfingerprints", publicKey),
+  certificateInfo: (certificate: string): Promise<ssh.SshCertificateInfo> =>
+    ipcRenderer.invoke("sshagent.certificateinfo", certificate),
+  exportKey: (
+    privateKey: string,
+    format: ssh.SshKeyExportFormat,
+    passphrase: string,
+  ): Promise<string> =>
+    ipcRenderer.invoke("sshagent.exportkey", { privateKey, format, passphrase }),
   isLoaded(): Promise<boolean> {
     return ipcRenderer.invoke("sshagent.isloaded");
   },
+  restart: async () => {
+    await ipcRenderer.invoke("sshagent.restart");
+  },
+  queryAuditLog: (filter?: ssh.SshAuditFilter): Promise<ssh.SshAuditEntry[]> =>
+    ipcRenderer.invoke("sshagent.queryauditlog", filter),
+  agentStatus: (): Promise<ssh.SshAgentStatus | null> => ipcRenderer.invoke("sshagent.agentstatus"),
+  blockedProcesses: (): Promise<ssh.SshBlockedProcess[]> =>
+    ipcRenderer.invoke("sshagent.blockedprocesses"),
+  unblockProcess: (executable: string): Promise<boolean> =>
+    ipcRenderer.invoke("sshagent.unblockprocess", executable),
 };
 
 const powermonitor = {
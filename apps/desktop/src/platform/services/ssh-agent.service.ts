@@ -29,14 +29,23 @@ import { ConfigService } from "@bitwarden/common/platform/abstractions/config/co
 import { I18nService } from "@bitwarden/common/platform/abstractions/i18n.service";
 import { LogService } from "@bitwarden/common/platform/abstractions/log.service";
 import { CommandDefinition, MessageListener } from "@bitwarden/common/platform/messaging";
+import { UserId } from "@bitwarden/common/types/guid";
 import { CipherService } from "@bitwarden/common/vault/abstractions/cipher.service";
 import { CipherType } from "@bitwarden/common/vault/enums";
+import { CipherView } from "@bitwarden/common/vault/models/view/cipher.view";
+import { SshKeyView } from "@bitwarden/common/vault/models/view/ssh-key.view";
 import { DialogService, ToastService } from "@bitwarden/components";
+import { KeyService } from "@bitwarden/key-management";
 
-import { ApproveSshRequestComponent } from "../components/approve-ssh-request";
+import { ApproveSshRequestComponent, SshRequestDetails } from "../components/approve-ssh-request";
 
 import { DesktopSettingsService } from "./desktop-settings.service";
 
+type ImportRequest = {
+  sshKey: { privateKey: string; publicKey: string; keyFingerprint: string };
+  comment: string;
+};
+
 @Injectable({
   providedIn: "root",
 })
@@ -48,6 +57,10 @@ export class SshAgentService implements OnDestroy {
   private isFeatureFlagEnabled = false;
 
   private destroy$ = new Subject<void>();
+  /** Ids of the ciphers whose keys were last sent to the agent */
+  private syncedCipherIds = new Set<string>();
+  /** Whether the agent's keys were sealed or dropped since the vault was last unlocked */
+  private keysLocked = true;
 
   constructor(
     private cipherService: CipherService,
@@ -60,6 +73,7 @@ export class SshAgentService implements OnDestroy {
     private desktopSettingsService: DesktopSettingsService,
     private configService: ConfigService,
     private accountService: AccountService,
+    private keyService: KeyService,
   ) {}
 
   async init() {
@@ -151,18 +165,27 @@ export class SshAgentService implements OnDestroy {
             application = this.i18nService.t("unknownApplication");
           }
 
+          if (message.importRequest != null) {
+            const imported = await this.importKey(
+              message.importRequest as ImportRequest,
+              application,
+            );
+            await ipc.platform.sshAgent.signRequestResponse(requestId, imported);
+            return;
+          }
+
+          if (message.isRemoveRequest as boolean) {
+            const cipher = ciphers.find((cipher) => cipher.id == cipherId);
+            const removed = cipher != null && (await this.removeKey(cipher, application));
+            await ipc.platform.sshAgent.signRequestResponse(requestId, removed);
+            return;
+          }
+
           if (isListRequest) {
             const sshCiphers = ciphers.filter(
               (cipher) => cipher.type === CipherType.SshKey && !cipher.isDeleted,
             );
-            const keys = sshCiphers.map((cipher) => {
-              return {
-                name: cipher.name,
-                privateKey: cipher.sshKey.privateKey,
-                cipherId: cipher.id,
-              };
-            });
-            await ipc.platform.sshAgent.setKeys(keys);
+            await this.syncKeys(sshCiphers);
             await ipc.platform.sshAgent.signRequestResponse(requestId, true);
             return;
           }
@@ -180,6 +203,13 @@ export class SshAgentService implements OnDestroy {
             this.dialogService,
             cipher.name,
             application,
+            {
+              hostNames: message.hostNames as string[],
+              hostKeyFingerprint: message.hostKeyFingerprint as string,
+              isForwarding: message.isForwarding as boolean,
+              purpose: message.purpose as SshRequestDetails["purpose"],
+              certificate: message.certificate as SshRequestDetails["certificate"],
+            },
           );
 
           const result = await firstValueFrom(dialogRef.closed);
@@ -189,6 +219,27 @@ export class SshAgentService implements OnDestroy {
       )
       .subscribe();
 
+    // Tell the user why a program whose requests were denied repeatedly stops prompting
+    this.messageListener
+      .messages$(new CommandDefinition("sshagent.processblocked"))
+      .pipe(takeUntil(this.destroy$))
+      .subscribe((message) => {
+        let application = message.processName as string;
+        if (application == "") {
+          application = this.i18nService.t("unknownApplication");
+        }
+        const blockedUntil = new Date((message.blockedUntil as number) * 1000);
+        this.toastService.showToast({
+          variant: "warning",
+          title: null,
+          message: this.i18nService.t(
+            "sshAgentProcessBlocked",
+            application,
+            blockedUntil.toLocaleTimeString(),
+          ),
+        });
+      });
+
     this.accountService.activeAccount$.pipe(skip(1), takeUntil(this.destroy$)).subscribe({
       next: (account) => {
         if (!this.isFeatureFlagEnabled) {
@@ -196,6 +247,7 @@ export class SshAgentService implements OnDestroy {
         }
 
         this.logService.info("Active account changed, clearing SSH keys");
+        this.keysLocked = true;
         ipc.platform.sshAgent
           .clearKeys()
           .catch((e) => this.logService.error("Failed to clear SSH keys", e));
@@ -222,6 +274,34 @@ export class SshAgentService implements OnDestroy {
       },
     });
 
+    // Seal the agent's keys when the vault locks, and restore them on unlock
+    this.accountService.activeAccount$
+      .pipe(
+        filter((account) => account != null),
+        switchMap((account) =>
+          this.keyService
+            .userKey$(account.id)
+            .pipe(map((userKey) => [account.id, userKey] as const)),
+        ),
+        concatMap(async ([userId, userKey]) => {
+          if (!this.isFeatureFlagEnabled) {
+            return;
+          }
+
+          if (userKey == null) {
+            await this.lockKeys(userId);
+            return;
+          }
+          await this.unlockKeys(userId);
+        }),
+        catchError((e: unknown) => {
+          this.logService.error("Failed to lock or unlock SSH keys", e);
+          return EMPTY;
+        }),
+        takeUntil(this.destroy$),
+      )
+      .subscribe();
+
     combineLatest([
       timer(0, this.SSH_REFRESH_INTERVAL),
       this.desktopSettingsService.sshAgentEnabled$,
@@ -247,9 +327,10 @@ export class SshAgentService implements OnDestroy {
 
           const ciphers = await this.cipherService.getAllDecrypted();
           if (ciphers == null) {
-            await ipc.platform.sshAgent.lock();
+            await this.lockKeys(activeAccount.id);
             return;
           }
+          await this.unlockKeys(activeAccount.id);
 
           const sshCiphers = ciphers.filter(
             (cipher) =>
@@ -257,20 +338,131 @@ export class SshAgentService implements OnDestroy {
               !cipher.isDeleted &&
               cipher.organizationId === null,
           );
-          const keys = sshCiphers.map((cipher) => {
-            return {
-              name: cipher.name,
-              privateKey: cipher.sshKey.privateKey,
-              cipherId: cipher.id,
-            };
-          });
-          await ipc.platform.sshAgent.setKeys(keys);
+          await this.syncKeys(sshCiphers);
         }),
         takeUntil(this.destroy$),
       )
       .subscribe();
   }
 
+  /**
+   * Seals the agent's keys under a session key, so they can be restored when the vault is unlocked
+   * again. The main process keeps the secret wrapping the session key in the OS biometric store,
+   * without biometric unlock the keys are dropped instead.
+   */
+  private async lockKeys(userId: UserId) {
+    if (this.keysLocked) {
+      return;
+    }
+
+    this.keysLocked = true;
+    await ipc.platform.sshAgent.lock(userId);
+  }
+
+  /** Restores the keys sealed by `lockKeys`, the next sync sends any key that was not restored */
+  private async unlockKeys(userId: UserId) {
+    if (!this.keysLocked) {
+      return;
+    }
+
+    this.keysLocked = false;
+    try {
+      await ipc.platform.sshAgent.unlock(userId);
+    } catch (e) {
+      this.logService.error("Failed to restore sealed SSH keys", e);
+    }
+  }
+
+  /**
+   * Sends the given SSH keys to the agent and stops serving the keys of previously synced ciphers
+   * that are no longer part of the vault. The agent keeps the keys that did not change.
+   */
+  private async syncKeys(sshCiphers: CipherView[]) {
+    const keys = sshCiphers.map((cipher) => {
+      return {
+        cipherId: cipher.id,
+        name: cipher.name,
+        privateKey: cipher.sshKey.privateKey,
+        folderId: cipher.folderId ?? undefined,
+        enabled: true,
+      };
+    });
+    const results = await ipc.platform.sshAgent.upsertKeys(keys);
+    for (const result of results) {
+      if (result.error != null) {
+        this.logService.warning(
+          `SSH key of cipher ${result.cipherId} is not served: ${result.error}`,
+        );
+      }
+    }
+
+    const cipherIds = new Set(keys.map((key) => key.cipherId));
+    const removedCipherIds = [...this.syncedCipherIds].filter((id) => !cipherIds.has(id));
+    if (removedCipherIds.length > 0) {
+      await ipc.platform.sshAgent.removeKeys(removedCipherIds);
+    }
+    this.syncedCipherIds = cipherIds;
+  }
+
+  /** Stores a key sent through `ssh-add` in the vault, if the user confirms */
+  private async importKey(request: ImportRequest, application: string): Promise<boolean> {
+    ipc.platform.focusWindow();
+    const confirmed = await this.dialogService.openSimpleDialog({
+      title: { key: "sshAgentImportKeyTitle" },
+      content: this.i18nService.t(
+        "sshAgentImportKeyDesc",
+        application,
+        request.sshKey.keyFingerprint,
+      ),
+      type: "info",
+    });
+    if (!confirmed) {
+      return false;
+    }
+
+    const cipher = new CipherView();
+    cipher.type = CipherType.SshKey;
+    cipher.name =
+      request.comment != "" ? request.comment : this.i18nService.t("sshAgentImportedKeyName");
+    cipher.sshKey = new SshKeyView();
+    cipher.sshKey.privateKey = request.sshKey.privateKey;
+    cipher.sshKey.publicKey = request.sshKey.publicKey;
+    cipher.sshKey.keyFingerprint = request.sshKey.keyFingerprint;
+
+    try {
+      const activeUserId = await firstValueFrom(
+        this.accountService.activeAccount$.pipe(map((a) => a?.id)),
+      );
+      const encrypted = await this.cipherService.encrypt(cipher, activeUserId);
+      await this.cipherService.createWithServer(encrypted);
+      return true;
+    } catch (e) {
+      this.logService.error("Failed to import SSH key", e);
+      return false;
+    }
+  }
+
+  /** Moves a vault key an agent client removed (`ssh-add -d`) to the trash, if the user confirms */
+  private async removeKey(cipher: CipherView, application: string): Promise<boolean> {
+    ipc.platform.focusWindow();
+    const confirmed = await this.dialogService.openSimpleDialog({
+      title: { key: "sshAgentRemoveKeyTitle" },
+      content: this.i18nService.t("sshAgentRemoveKeyDesc", application, cipher.name),
+      type: "warning",
+    });
+    if (!confirmed) {
+      return false;
+    }
+
+    try {
+      await this.cipherService.softDeleteWithServer(cipher.id);
+      return true;
+    } catch (e) {
+      this.logService.error("Failed to remove SSH key", e);
+      return false;
+    }
+  }
+
   ngOnDestroy() {
     this.destroy$.next();
     this.destroy$.complete();